@@ -0,0 +1,788 @@
+//! Two-pass assembler for CHIP-8 mnemonics.
+//!
+//! The syntax follows the mnemonics used by the disassembler and CowGod's CHIP-8 Reference page:
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! SPEED   EQU 2               ; constants are defined with EQU (or =)
+//!         ORG #200            ; set the assembly address
+//! start:  LD V0, #12          ; labels end with a colon
+//!         LD I, sprite
+//!         DRW V1, V2, 5
+//!         ADD V0, SPEED * 2
+//!         JP start
+//! sprite: DB %11110000, #90, $90, 0x90, 240
+//!         DW #1234
+//! ```
+//!
+//! Numbers may be decimal, hex (#FF, $FF or 0xFF) or binary (%1010 or 0b1010). Expressions
+//! support + - * / % & | ^ << >> ~, unary minus and parentheses, and may reference labels and
+//! constants defined anywhere in the source. The ROM image starts at 0x200.
+
+use crate::opcode::{encode, Instruction};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Address the assembled ROM image starts at
+pub const ROM_START: u16 = 0x200;
+
+/// Maximum nesting of constants referencing other constants
+const MAX_EXPRESSION_DEPTH: usize = 32;
+
+/// Error raised while assembling. Line and column are 1-based.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A piece of source text and where it starts, for error reporting
+#[derive(Copy, Clone, Debug)]
+struct Span<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Span<'a> {
+    fn error<S: Into<String>>(&self, message: S) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+/// A directive or instruction, recorded in the first pass and emitted in the second
+struct Statement<'a> {
+    mnemonic: Span<'a>,
+    operands: Vec<Span<'a>>,
+    addr: u16,
+}
+
+/// Symbol tables built by the first pass
+#[derive(Default)]
+struct Symbols<'a> {
+    labels: HashMap<&'a str, u16>,
+    constants: HashMap<&'a str, Span<'a>>,
+}
+
+/// Assembles source text into a ROM image that starts at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut symbols = Symbols::default();
+    let mut statements = Vec::new();
+    let mut addr = ROM_START as u32;
+
+    // First pass - record labels and constants, and lay out statements
+    for (index, raw_line) in source.lines().enumerate() {
+        let line = index + 1;
+        let code = match raw_line.find(';') {
+            Some(pos) => &raw_line[..pos],
+            None => raw_line,
+        };
+        let mut rest = Span {
+            text: code,
+            line,
+            column: 1,
+        };
+
+        // Labels
+        loop {
+            rest = trim_start(rest);
+            let name_len = identifier_len(rest.text);
+            if name_len > 0 && rest.text[name_len..].starts_with(':') {
+                let name = &rest.text[..name_len];
+                if symbols.labels.insert(name, addr as u16).is_some() {
+                    return Err(rest.error(format!("Label '{}' is already defined", name)));
+                }
+                rest = advance(rest, name_len + 1);
+            } else {
+                break;
+            }
+        }
+        if rest.text.trim().is_empty() {
+            continue;
+        }
+
+        // Mnemonic, or the name of a constant
+        let word_len = rest
+            .text
+            .find(char::is_whitespace)
+            .unwrap_or(rest.text.len());
+        let word = Span {
+            text: &rest.text[..word_len],
+            ..rest
+        };
+        let after = trim_start(advance(rest, word_len));
+
+        // Constants
+        let after_upper = after.text.to_ascii_uppercase();
+        let equ_len = if after_upper.starts_with("EQU ") || after_upper.starts_with("EQU\t") {
+            Some(3)
+        } else if after.text.starts_with('=') {
+            Some(1)
+        } else {
+            None
+        };
+        if let Some(equ_len) = equ_len {
+            if identifier_len(word.text) != word.text.len() {
+                return Err(word.error(format!("Invalid constant name '{}'", word.text)));
+            }
+            let value = trim_end(trim_start(advance(after, equ_len)));
+            if value.text.is_empty() {
+                return Err(after.error("Missing constant value"));
+            }
+            if symbols.constants.insert(word.text, value).is_some() {
+                return Err(word.error(format!("Constant '{}' is already defined", word.text)));
+            }
+            continue;
+        }
+
+        let operands = split_operands(after)?;
+        let mnemonic = word.text.to_ascii_uppercase();
+        let size = match mnemonic.as_str() {
+            "ORG" => {
+                let target = single_operand(&word, &operands)?;
+                let value = symbols.eval(target, 0)?;
+                if !(ROM_START as i64..=0xFFFF).contains(&value) {
+                    return Err(
+                        target.error(format!("ORG address {:#X} is outside #200-#FFFF", value))
+                    );
+                }
+                addr = value as u32;
+                continue;
+            }
+            "DB" => operands.len() as u32,
+            "DW" => operands.len() as u32 * 2,
+            "LD" if operands.len() == 2 && has_keyword_prefix(operands[1].text, "LONG") => 4,
+            _ => 2,
+        };
+        if addr + size > 0x10000 {
+            return Err(word.error("Program does not fit below #10000"));
+        }
+        statements.push(Statement {
+            mnemonic: word,
+            operands,
+            addr: addr as u16,
+        });
+        addr += size;
+    }
+
+    // Second pass - evaluate operands and emit bytes
+    let mut rom: Vec<u8> = Vec::new();
+    for statement in statements.iter() {
+        let bytes = symbols.emit(statement)?;
+        let offset = (statement.addr - ROM_START) as usize;
+        if rom.len() < offset + bytes.len() {
+            rom.resize(offset + bytes.len(), 0);
+        }
+        rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    Ok(rom)
+}
+
+/// Removes leading whitespace from a span, keeping its column up to date
+fn trim_start(span: Span) -> Span {
+    let trimmed = span.text.trim_start();
+    advance(span, span.text.len() - trimmed.len())
+}
+
+/// Removes trailing whitespace from a span
+fn trim_end(span: Span) -> Span {
+    Span {
+        text: span.text.trim_end(),
+        ..span
+    }
+}
+
+/// Drops the first count bytes of a span
+fn advance(span: Span, count: usize) -> Span {
+    Span {
+        text: &span.text[count..],
+        line: span.line,
+        column: span.column + span.text[..count].chars().count(),
+    }
+}
+
+/// Returns the length of the identifier at the start of text, or 0 if there is none
+fn identifier_len(text: &str) -> usize {
+    let mut chars = text.char_indices();
+    match chars.next() {
+        Some((_, c)) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {}
+        _ => return 0,
+    }
+    chars
+        .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '.'))
+        .map(|(pos, _)| pos)
+        .unwrap_or(text.len())
+}
+
+/// Returns true if text starts with the given keyword, case-insensitively, followed by whitespace
+fn has_keyword_prefix(text: &str, keyword: &str) -> bool {
+    text.get(..keyword.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(keyword))
+        && text[keyword.len()..].starts_with(char::is_whitespace)
+}
+
+/// Splits the operand list on commas that are not inside parentheses or brackets
+fn split_operands(span: Span) -> Result<Vec<Span>, AsmError> {
+    let mut operands = Vec::new();
+    if span.text.trim().is_empty() {
+        return Ok(operands);
+    }
+    let mut depth = 0;
+    let mut start = 0;
+    for (pos, c) in span.text.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(operand(span, start, pos)?);
+                start = pos + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(operand(span, start, span.text.len())?);
+    Ok(operands)
+}
+
+/// Extracts a single trimmed, non-empty operand from span.text[start..end]
+fn operand(span: Span, start: usize, end: usize) -> Result<Span, AsmError> {
+    let piece = Span {
+        text: &span.text[..end],
+        ..span
+    };
+    let piece = trim_end(trim_start(advance(piece, start)));
+    if piece.text.is_empty() {
+        return Err(piece.error("Missing operand"));
+    }
+    Ok(piece)
+}
+
+/// Returns the only operand of a statement, or an error if there is not exactly one
+fn single_operand<'a>(mnemonic: &Span<'a>, operands: &[Span<'a>]) -> Result<Span<'a>, AsmError> {
+    match operands {
+        [operand] => Ok(*operand),
+        _ => Err(mnemonic.error(format!(
+            "{} takes 1 operand, found {}",
+            mnemonic.text.to_ascii_uppercase(),
+            operands.len()
+        ))),
+    }
+}
+
+/// Parses a register name (V0 - VF)
+fn register(span: &Span) -> Option<u8> {
+    let text = span.text;
+    if text.len() == 2 && (text.starts_with('V') || text.starts_with('v')) {
+        u8::from_str_radix(&text[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+/// Parses a register name, or returns an error
+fn expect_register(span: &Span) -> Result<u8, AsmError> {
+    register(span)
+        .ok_or_else(|| span.error(format!("Expected a register V0-VF, found '{}'", span.text)))
+}
+
+/// Returns true if the operand is the given keyword, case-insensitively
+fn is_keyword(span: &Span, keyword: &str) -> bool {
+    span.text.eq_ignore_ascii_case(keyword)
+}
+
+impl<'a> Symbols<'a> {
+    /// Evaluates an expression
+    fn eval(&self, span: Span<'a>, depth: usize) -> Result<i64, AsmError> {
+        if depth > MAX_EXPRESSION_DEPTH {
+            return Err(span.error("Constant definitions are circular"));
+        }
+        let mut parser = ExpressionParser {
+            symbols: self,
+            span,
+            pos: 0,
+            depth,
+        };
+        let value = parser.parse_or()?;
+        parser.skip_whitespace();
+        if parser.pos < span.text.len() {
+            return Err(parser.error_here("Unexpected characters in expression"));
+        }
+        Ok(value)
+    }
+
+    /// Evaluates an expression that must fit in the given inclusive range
+    fn eval_range(&self, span: Span<'a>, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let value = self.eval(span, 0)?;
+        if value < min || value > max {
+            return Err(span.error(format!(
+                "{} {} is out of range {}-{}",
+                what, value, min, max
+            )));
+        }
+        Ok(value)
+    }
+
+    /// Evaluates a 12-bit address
+    fn address(&self, span: Span<'a>) -> Result<u16, AsmError> {
+        Ok(self.eval_range(span, 0, 0xFFF, "Address")? as u16)
+    }
+
+    /// Evaluates a byte. Negative values down to -128 are stored in two's complement.
+    fn byte(&self, span: Span<'a>) -> Result<u8, AsmError> {
+        Ok(self.eval_range(span, -128, 255, "Byte")? as u8)
+    }
+
+    /// Evaluates a 4-bit value
+    fn nibble(&self, span: Span<'a>) -> Result<u8, AsmError> {
+        Ok(self.eval_range(span, 0, 15, "Nibble")? as u8)
+    }
+
+    /// Produces the bytes of a statement
+    fn emit(&self, statement: &Statement<'a>) -> Result<Vec<u8>, AsmError> {
+        let mnemonic = statement.mnemonic.text.to_ascii_uppercase();
+        let ops = &statement.operands;
+        match mnemonic.as_str() {
+            "DB" => ops.iter().map(|op| self.byte(*op)).collect(),
+            "DW" => {
+                let mut bytes = Vec::with_capacity(ops.len() * 2);
+                for op in ops.iter() {
+                    let word = self.eval_range(*op, -0x8000, 0xFFFF, "Word")? as u16;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            _ => {
+                let (instruction, long_operand) = self.instruction(&mnemonic, statement)?;
+                let mut bytes = encode(&instruction).to_be_bytes().to_vec();
+                if let Some(word) = long_operand {
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Parses an instruction statement. Returns the instruction, and the address word for
+    /// LD I, LONG.
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        statement: &Statement<'a>,
+    ) -> Result<(Instruction, Option<u16>), AsmError> {
+        let ops = statement.operands.as_slice();
+        let name = &statement.mnemonic;
+        let wrong_operands = || {
+            let found: Vec<&str> = ops.iter().map(|op| op.text).collect();
+            name.error(format!(
+                "Invalid operands for {}: '{}'",
+                mnemonic,
+                found.join(", ")
+            ))
+        };
+        let reg = |index: usize| expect_register(&ops[index]);
+
+        let instruction = match (mnemonic, ops.len()) {
+            ("CLS", 0) => Instruction::Cls,
+            ("RET", 0) => Instruction::Ret,
+            ("SCR", 0) => Instruction::ScrollRight,
+            ("SCL", 0) => Instruction::ScrollLeft,
+            ("EXIT", 0) => Instruction::Exit,
+            ("LOW", 0) => Instruction::LowRes,
+            ("HIGH", 0) => Instruction::HighRes,
+            ("AUDIO", 0) => Instruction::LoadAudioPattern,
+            ("SYS", 1) => Instruction::Sys {
+                nnn: self.address(ops[0])?,
+            },
+            ("SCD", 1) => Instruction::ScrollDown {
+                n: self.nibble(ops[0])?,
+            },
+            ("SCU", 1) => Instruction::ScrollUp {
+                n: self.nibble(ops[0])?,
+            },
+            ("PLANE", 1) => Instruction::SelectPlanes {
+                n: self.eval_range(ops[0], 0, 3, "Plane mask")? as u8,
+            },
+            ("JP", 1) => Instruction::Jump {
+                nnn: self.address(ops[0])?,
+            },
+            ("JP", 2) if is_keyword(&ops[0], "V0") => Instruction::JumpV0 {
+                nnn: self.address(ops[1])?,
+            },
+            ("CALL", 1) => Instruction::Call {
+                nnn: self.address(ops[0])?,
+            },
+            ("SE", 2) | ("SNE", 2) => {
+                let x = reg(0)?;
+                let equal = mnemonic == "SE";
+                match (register(&ops[1]), equal) {
+                    (Some(y), true) => Instruction::SkipEqualXY { x, y },
+                    (Some(y), false) => Instruction::SkipNotEqualXY { x, y },
+                    (None, true) => Instruction::SkipEqual {
+                        x,
+                        kk: self.byte(ops[1])?,
+                    },
+                    (None, false) => Instruction::SkipNotEqual {
+                        x,
+                        kk: self.byte(ops[1])?,
+                    },
+                }
+            }
+            ("SAVE", 2) => Instruction::SaveRange {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("LOAD", 2) => Instruction::LoadRange {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("OR", 2) => Instruction::Or {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("AND", 2) => Instruction::And {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("XOR", 2) => Instruction::Xor {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SUB", 2) => Instruction::SubXY {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SUBN", 2) => Instruction::SubYX {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SHR", 1) => Instruction::ShiftRight {
+                x: reg(0)?,
+                y: reg(0)?,
+            },
+            ("SHR", 2) => Instruction::ShiftRight {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("SHL", 1) => Instruction::ShiftLeft {
+                x: reg(0)?,
+                y: reg(0)?,
+            },
+            ("SHL", 2) => Instruction::ShiftLeft {
+                x: reg(0)?,
+                y: reg(1)?,
+            },
+            ("RND", 2) => Instruction::Rand {
+                x: reg(0)?,
+                kk: self.byte(ops[1])?,
+            },
+            ("DRW", 3) => Instruction::Draw {
+                x: reg(0)?,
+                y: reg(1)?,
+                n: self.nibble(ops[2])?,
+            },
+            ("SKP", 1) => Instruction::SkipIfKey { x: reg(0)? },
+            ("SKNP", 1) => Instruction::SkipNotKey { x: reg(0)? },
+            ("PITCH", 1) => Instruction::LoadPitch { x: reg(0)? },
+            ("ADD", 2) if is_keyword(&ops[0], "I") => Instruction::AddIVx { x: reg(1)? },
+            ("ADD", 2) => {
+                let x = reg(0)?;
+                match register(&ops[1]) {
+                    Some(y) => Instruction::AddXY { x, y },
+                    None => Instruction::Add {
+                        x,
+                        kk: self.byte(ops[1])?,
+                    },
+                }
+            }
+            ("LD", 2) => {
+                return self
+                    .load(ops)
+                    .map_err(|err| err.unwrap_or_else(wrong_operands))
+            }
+            _ => return Err(wrong_operands()),
+        };
+        Ok((instruction, None))
+    }
+
+    /// Parses the many forms of LD. Returns Err(None) when the operand combination is invalid.
+    fn load(&self, ops: &[Span<'a>]) -> Result<(Instruction, Option<u16>), Option<AsmError>> {
+        let (dst, src) = (&ops[0], &ops[1]);
+        let instruction = if let Some(x) = register(dst) {
+            if let Some(y) = register(src) {
+                Instruction::LoadXY { x, y }
+            } else if is_keyword(src, "DT") {
+                Instruction::LoadDelayToVx { x }
+            } else if is_keyword(src, "K") {
+                Instruction::WaitForKey { x }
+            } else if is_keyword(src, "[I]") {
+                Instruction::LoadRegs { x }
+            } else if is_keyword(src, "R") {
+                Instruction::LoadRplFlags { x }
+            } else {
+                Instruction::Load {
+                    x,
+                    kk: self.byte(*src)?,
+                }
+            }
+        } else if is_keyword(dst, "I") {
+            if has_keyword_prefix(src.text, "LONG") {
+                let target = trim_start(advance(*src, 4));
+                let addr = self.eval_range(target, 0, 0xFFFF, "Address")? as u16;
+                return Ok((Instruction::LongLoadI, Some(addr)));
+            }
+            Instruction::LoadI {
+                nnn: self.address(*src)?,
+            }
+        } else {
+            let x = register(src).ok_or(None)?;
+            match dst.text.to_ascii_uppercase().as_str() {
+                "DT" => Instruction::LoadDelayTimer { x },
+                "ST" => Instruction::LoadSoundTimer { x },
+                "F" => Instruction::LoadAsciiAddress { x },
+                "HF" => Instruction::LoadLargeFontAddress { x },
+                "B" => Instruction::LoadBcd { x },
+                "[I]" => Instruction::StoreRegs { x },
+                "R" => Instruction::StoreRplFlags { x },
+                _ => return Err(None),
+            }
+        };
+        Ok((instruction, None))
+    }
+}
+
+/// Recursive descent parser for operand expressions
+struct ExpressionParser<'s, 'a> {
+    symbols: &'s Symbols<'a>,
+    span: Span<'a>,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'s, 'a> ExpressionParser<'s, 'a> {
+    fn error_here<S: Into<String>>(&self, message: S) -> AsmError {
+        advance(self.span, self.pos).error(message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.span.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes the operator if it is next in the input
+    fn eat(&mut self, operator: &str) -> bool {
+        self.skip_whitespace();
+        if self.span.text[self.pos..].starts_with(operator) {
+            self.pos += operator.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<i64, AsmError> {
+        let mut value = self.parse_xor()?;
+        while self.eat("|") {
+            value |= self.parse_xor()?;
+        }
+        Ok(value)
+    }
+
+    fn parse_xor(&mut self) -> Result<i64, AsmError> {
+        let mut value = self.parse_and()?;
+        while self.eat("^") {
+            value ^= self.parse_and()?;
+        }
+        Ok(value)
+    }
+
+    fn parse_and(&mut self) -> Result<i64, AsmError> {
+        let mut value = self.parse_shift()?;
+        while self.eat("&") {
+            value &= self.parse_shift()?;
+        }
+        Ok(value)
+    }
+
+    fn parse_shift(&mut self) -> Result<i64, AsmError> {
+        let mut value = self.parse_sum()?;
+        loop {
+            if self.eat("<<") {
+                value = value.wrapping_shl(self.parse_sum()? as u32);
+            } else if self.eat(">>") {
+                value = value.wrapping_shr(self.parse_sum()? as u32);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<i64, AsmError> {
+        let mut value = self.parse_product()?;
+        loop {
+            if self.eat("+") {
+                value = value.wrapping_add(self.parse_product()?);
+            } else if self.eat("-") {
+                value = value.wrapping_sub(self.parse_product()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn parse_product(&mut self) -> Result<i64, AsmError> {
+        let mut value = self.parse_unary()?;
+        loop {
+            let operator_pos = self.pos;
+            if self.eat("*") {
+                value = value.wrapping_mul(self.parse_unary()?);
+            } else if self.eat("/") || self.eat("%") {
+                let is_div = self.span.text[..self.pos].ends_with('/');
+                let divisor = self.parse_unary()?;
+                if divisor == 0 {
+                    self.pos = operator_pos;
+                    self.skip_whitespace();
+                    return Err(self.error_here("Division by zero"));
+                }
+                value = if is_div {
+                    value / divisor
+                } else {
+                    value % divisor
+                };
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<i64, AsmError> {
+        if self.eat("-") {
+            Ok(self.parse_unary()?.wrapping_neg())
+        } else if self.eat("~") {
+            Ok(!self.parse_unary()?)
+        } else if self.eat("+") {
+            self.parse_unary()
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<i64, AsmError> {
+        self.skip_whitespace();
+        if self.eat("(") {
+            let value = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(self.error_here("Expected ')'"));
+            }
+            return Ok(value);
+        }
+
+        let rest = &self.span.text[self.pos..];
+        let start = self.pos;
+        let (radix, prefix_len) = if rest.starts_with('#') || rest.starts_with('$') {
+            (16, 1)
+        } else if rest.starts_with("0x") || rest.starts_with("0X") {
+            (16, 2)
+        } else if rest.starts_with('%') {
+            (2, 1)
+        } else if rest.starts_with("0b") || rest.starts_with("0B") {
+            (2, 2)
+        } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            (10, 0)
+        } else {
+            let name_len = identifier_len(rest);
+            if name_len == 0 {
+                return Err(self.error_here("Expected a number, label or constant"));
+            }
+            self.pos += name_len;
+            return self.lookup(&rest[..name_len], start);
+        };
+
+        let digits = &rest[prefix_len..];
+        let digits_len = digits
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(digits.len());
+        let value = i64::from_str_radix(&digits[..digits_len], radix).map_err(|_| {
+            self.error_here(format!(
+                "Invalid number '{}'",
+                &rest[..prefix_len + digits_len]
+            ))
+        })?;
+        self.pos += prefix_len + digits_len;
+        Ok(value)
+    }
+
+    /// Resolves a label or constant name
+    fn lookup(&self, name: &str, start: usize) -> Result<i64, AsmError> {
+        if let Some(addr) = self.symbols.labels.get(name) {
+            return Ok(*addr as i64);
+        }
+        if let Some(value) = self.symbols.constants.get(name) {
+            return self.symbols.eval(*value, self.depth + 1);
+        }
+        Err(advance(self.span, start).error(format!("Undefined symbol '{}'", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::disassemble;
+
+    #[test]
+    fn assembles_instructions_and_data() {
+        let source = "
+            SIZE    EQU 5
+            start:  LD V0, #12      ; comment
+                    DRW V1, V2, SIZE
+                    LD I, sprite
+                    JP start
+            sprite: DB %11110000, $90 + 1, -1
+                    DW 0x1234
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x60, 0x12, 0xD1, 0x25, 0xA2, 0x08, 0x12, 0x00, 0xF0, 0x91, 0xFF, 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    fn org_and_long_load() {
+        let source = "LD I, LONG target\n ORG #206\ntarget: CLS";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0xF0, 0x00, 0x02, 0x06, 0x00, 0x00, 0x00, 0xE0]
+        );
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        let err = assemble("CLS\n  LD V0, missing").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 10));
+        let err = assemble("DRW V0, VG, 1").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 9));
+        let err = assemble("LD V0, 256").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 8)); // Operands that are not ASCII are errors, not panics
+        assert!(assemble("LD I, aaaé x").is_err());
+    }
+
+    #[test]
+    fn reassembles_disassembled_rom() {
+        let rom = include_bytes!("../roms/test_opcode.ch8");
+        let source = disassemble(rom, ROM_START).render();
+        assert_eq!(assemble(&source).unwrap(), rom.to_vec());
+    }
+}
@@ -0,0 +1,257 @@
+//! Command line parsing for the interpreter binary.
+
+use crate::config::{self, Settings};
+
+pub const USAGE: &str = "\
+Usage:
+    chip8_interpreter [options] <rom file | ->
+    chip8_interpreter record <movie file> [options] <rom file | ->
+    chip8_interpreter play <movie file> [options] <rom file | ->
+    chip8_interpreter disasm <rom file>
+    chip8_interpreter asm <source file> <rom file>
+
+Options:
+    --ipf <n>               Instructions per 60hz frame (default 8)
+    --platform <name>       vip, chip48, schip or xochip (default vip)
+    --display <mode>        lores (64x32) or hires (128x64) (default lores)
+    --theme <name>          Colour theme: green, amber, lcd, high-contrast or colorblind
+    --colors <c1,c2,c3>     Colours of plane 1, plane 2 and both planes, replacing those of
+                            the theme. Names such as green or dark_red, or #RRGGBB
+    --color-depth <depth>   Colours the terminal shows: truecolor, 256 or 16 (default
+                            detected from COLORTERM and TERM)
+    --character <c>         Character drawn for pixels that are on (default *)
+    --render <mode>         character, double (square pixels), half (half blocks, two pixel
+                            rows per line) or braille (2x4 pixels per character)
+                            (default character)
+    --graphics <mode>       auto, text, sixel or kitty. auto draws images when environment
+                            variables name a terminal known to support them; use text if
+                            it guesses wrong (default auto)
+    --scale <n>             Image pixels per CHIP-8 pixel, when drawing images (default 8)
+    --persistence <mode>    Hides flicker. decay:<n> fades pixels out over n frames (decay
+                            alone is 4 frames), blend shows the OR of the last two frames
+                            (default off)
+    --keymap <16 keys>      Terminal keys for CHIP-8 keys 0-F (default x123qweasdzc4rfv)
+    --config <file>         Per-ROM settings file (default chip8.ini, if present)
+    --rom-db <file>         JSON file of ROMs adding to the built in compatibility database
+                            (default chip8_roms.json, if present)
+    --load-address <addr>   Address the ROM is loaded and started at (default 0x200, 0x600
+                            for ETI-660 programs)
+    --seed <n>              Seed for the random number generator
+    --headless              Run without a terminal display or keyboard, then print the screen
+    --max-frames <n>        Stop after n frames
+    -h, --help              Print this message
+
+A ROM path of - reads the ROM from standard input. A ROM inside a zip archive or directory is
+named as collection:entry, where entry is a path, a file name or an index; naming only the
+collection lists its ROMs.
+
+Options given here take precedence over the ROM's section of the config file, which takes
+precedence over the ROM database. The platform named by the ROM file's extension comes next,
+and the [global] section of the config file only sets what none of them do.";
+
+/// Settings for running a ROM
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// Path of the ROM file, or - for standard input
+    pub rom: String,
+    /// Settings given on the command line
+    pub settings: Settings,
+    /// Path of the config file, if one was named
+    pub config: Option<String>,
+    /// Path of the ROM database file, if one was named
+    pub rom_database: Option<String>,
+    /// Address the ROM is loaded at, if not 0x200
+    pub load_address: Option<u16>,
+    pub seed: Option<u32>,
+    pub headless: bool,
+    pub max_frames: Option<u64>,
+}
+
+/// What the binary was asked to do
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Run(Options),
+    Record { movie: String, options: Options },
+    Play { movie: String, options: Options },
+    Disasm { rom: String },
+    Asm { source: String, rom: String },
+    Help,
+}
+
+/// Parses the arguments following the program name
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return Ok(Command::Help);
+    }
+    match args.first().map(String::as_str) {
+        Some("disasm") => match &args[1..] {
+            [rom] => Ok(Command::Disasm { rom: rom.clone() }),
+            _ => Err(String::from("disasm takes a ROM file")),
+        },
+        Some("asm") => match &args[1..] {
+            [source, rom] => Ok(Command::Asm {
+                source: source.clone(),
+                rom: rom.clone(),
+            }),
+            _ => Err(String::from("asm takes a source file and a ROM file")),
+        },
+        Some("record") | Some("play") => {
+            let movie = args
+                .get(1)
+                .ok_or_else(|| format!("{} takes a movie file", args[0]))?
+                .clone();
+            let options = parse_options(&args[2..])?;
+            if args[0] == "record" {
+                Ok(Command::Record { movie, options })
+            } else {
+                Ok(Command::Play { movie, options })
+            }
+        }
+        _ => Ok(Command::Run(parse_options(args)?)),
+    }
+}
+
+/// Parses the run options and the ROM path
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: String::new(),
+        settings: Settings::default(),
+        config: None,
+        rom_database: None,
+        load_address: None,
+        seed: None,
+        headless: false,
+        max_frames: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(String::as_str)
+                .ok_or_else(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--ipf" => options.settings.instructions_per_frame = Some(parse_number(arg, value()?)?),
+            "--platform" => options.settings.platform = Some(value()?.parse()?),
+            "--display" => {
+                options.settings.display_mode = Some(config::parse_display_mode(value()?)?)
+            }
+            "--theme" => options.settings.theme = Some(value()?.parse()?),
+            "--color-depth" => options.settings.color_depth = Some(value()?.parse()?),
+            "--colors" => options.settings.colors = Some(config::parse_colors(value()?)?),
+            "--character" => options.settings.character = Some(config::parse_character(value()?)?),
+            "--render" => options.settings.render_mode = Some(value()?.parse()?),
+            "--graphics" => options.settings.graphics = Some(config::parse_graphics(value()?)?),
+            "--persistence" => options.settings.persistence = Some(value()?.parse()?),
+            "--scale" => options.settings.scale = Some(config::parse_scale(value()?)?),
+            "--keymap" => options.settings.keymap = Some(config::parse_keymap(value()?)?),
+            "--config" => options.config = Some(String::from(value()?)),
+            "--rom-db" => options.rom_database = Some(String::from(value()?)),
+            "--load-address" => options.load_address = Some(parse_address(arg, value()?)?),
+            "--seed" => options.seed = Some(parse_number(arg, value()?)?),
+            "--headless" => options.headless = true,
+            "--max-frames" => options.max_frames = Some(parse_number(arg, value()?)?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option '{}'", arg)),
+            _ if rom.is_none() => rom = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| String::from("Missing ROM file"))?;
+    Ok(options)
+}
+
+/// Parses a decimal number option value
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, found '{}'", option, value))
+}
+
+/// Parses an address option value, in hex with a 0x prefix or in decimal
+fn parse_address(option: &str, value: &str) -> Result<u16, String> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16)
+            .map_err(|_| format!("{} expects an address, found '{}'", option, value)),
+        None => parse_number(option, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Graphics;
+    use chip8_interpreter::display::graphics_display::GraphicsProtocol;
+    use chip8_interpreter::display::persistence::Persistence;
+    use chip8_interpreter::display::render_mode::RenderMode;
+    use chip8_interpreter::display::theme::ColorDepth;
+    use chip8_interpreter::display::DisplayMode;
+    use chip8_interpreter::quirks::Platform;
+    use crossterm::style::Color;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_run_options() {
+        let command = parse(&args(
+            "--ipf 20 --platform schip --display hires --colors white,#ff0000,blue \
+             --keymap 0123456789abcdef --character @ --render braille --graphics sixel \
+             --scale 4 --theme lcd --color-depth 16 --persistence decay:6 --config games.ini \
+             --load-address 0x600 --seed 7 --headless --max-frames 60 game.ch8",
+        ))
+        .unwrap();
+        let options = match command {
+            Command::Run(options) => options,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(options.rom, "game.ch8");
+        let settings = &options.settings;
+        assert_eq!(settings.instructions_per_frame, Some(20));
+        assert_eq!(settings.platform, Some(Platform::SuperChip));
+        assert_eq!(settings.display_mode, Some(DisplayMode::H128V64MONOCHROME));
+        assert_eq!(
+            settings.colors,
+            Some([Color::White, Color::Rgb { r: 255, g: 0, b: 0 }, Color::Blue])
+        );
+        assert_eq!(settings.keymap.unwrap()[0xA], 'a');
+        assert_eq!(settings.character, Some('@'));
+        assert_eq!(settings.render_mode, Some(RenderMode::Braille));
+        assert_eq!(
+            settings.graphics,
+            Some(Graphics::Protocol(GraphicsProtocol::Sixel))
+        );
+        assert_eq!(settings.scale, Some(4));
+        assert_eq!(settings.theme.map(|theme| theme.name), Some("lcd"));
+        assert_eq!(settings.color_depth, Some(ColorDepth::Ansi16));
+        assert_eq!(settings.persistence, Some(Persistence::Decay(6)));
+        assert_eq!(options.config.as_deref(), Some("games.ini"));
+        assert_eq!(options.load_address, Some(0x600));
+        assert_eq!((options.seed, options.max_frames), (Some(7), Some(60)));
+        assert!(options.headless);
+    }
+
+    #[test]
+    fn reports_bad_arguments() {
+        assert_eq!(parse(&args("")), Err(String::from("Missing ROM file")));
+        assert!(parse(&args("--ipf fast game.ch8")).is_err());
+        assert!(parse(&args("--platform nes game.ch8")).is_err());
+        assert!(parse(&args("--keymap 0123 game.ch8")).is_err());
+        assert!(parse(&args("--scale 0 game.ch8")).is_err());
+        assert!(parse(&args("a.ch8 b.ch8")).is_err());
+        assert_eq!(
+            parse(&args("record")).unwrap_err(),
+            "record takes a movie file"
+        );
+        assert!(matches!(
+            parse(&args("play demo.movie -")),
+            Ok(Command::Play { .. })
+        ));
+    }
+}
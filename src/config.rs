@@ -0,0 +1,358 @@
+//! Per-ROM settings file for the launcher.
+//!
+//! The file is INI style. A [global] section holds the settings for every ROM, and a section
+//! named by the SHA-1 of a ROM file overrides them for that ROM. Comments start with ; or #.
+//!
+//! ```text
+//! [global]
+//! ipf = 10
+//! keymap = x123qweasdzc4rfv
+//!
+//! ; Blinky
+//! [4b9a0... 40 hex digits of the ROM's SHA-1 ...]
+//! name = Blinky
+//! platform = schip
+//! display = hires
+//! colors = white, #ff8000, yellow
+//! character = #
+//! render = half
+//! theme = amber
+//! color-depth = 256
+//! persistence = decay:6
+//! graphics = auto
+//! scale = 6
+//! ```
+//!
+//! Settings given on the command line take precedence over the ROM section, which takes
+//! precedence over the ROM database. The [global] section only fills in what none of them set,
+//! and its platform also gives way to the one the ROM file's extension names.
+
+use chip8_interpreter::display::graphics_display::GraphicsProtocol;
+use chip8_interpreter::display::persistence::Persistence;
+use chip8_interpreter::display::render_mode::RenderMode;
+use chip8_interpreter::display::theme::{ColorDepth, Theme};
+use chip8_interpreter::display::DisplayMode;
+use chip8_interpreter::quirks::Platform;
+use crossterm::style::Color;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+/// Config file read when none is named on the command line, if it exists
+pub const DEFAULT_CONFIG_FILE: &str = "chip8.ini";
+
+/// Settings for running a ROM. None leaves a setting to the next source of settings.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    /// Quirk profile
+    pub platform: Option<Platform>,
+    /// Speed, in instructions per 60hz frame
+    pub instructions_per_frame: Option<u32>,
+    pub display_mode: Option<DisplayMode>,
+    /// Colours of pixels OFF and ON
+    pub theme: Option<Theme>,
+    /// Colours of plane 1, plane 2 and both planes. Replace those of the theme.
+    pub colors: Option<[Color; 3]>,
+    /// Colours the terminal can show. Detected when None.
+    pub color_depth: Option<ColorDepth>,
+    /// Character drawn for pixels that are ON
+    pub character: Option<char>,
+    /// How pixels are drawn with terminal characters
+    pub render_mode: Option<RenderMode>,
+    /// Terminal character for each CHIP-8 key, indexed by the key's hex value
+    pub keymap: Option<[char; 16]>,
+    /// Whether the screen is drawn as text or as images
+    pub graphics: Option<Graphics>,
+    /// Image pixels per CHIP-8 pixel, when drawing images
+    pub scale: Option<u32>,
+    /// How pixels turned OFF stay on screen, to hide flicker
+    pub persistence: Option<Persistence>,
+}
+
+/// How the screen is drawn
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Graphics {
+    /// Images if the terminal is known to support them, otherwise text
+    Auto,
+    /// Text characters, even if the terminal supports images
+    Text,
+    /// Images sent with the protocol
+    Protocol(GraphicsProtocol),
+}
+
+impl Settings {
+    /// Returns these settings, with the settings left as None taken from fallback
+    pub fn or(&self, fallback: &Settings) -> Settings {
+        Settings {
+            platform: self.platform.or(fallback.platform),
+            instructions_per_frame: self
+                .instructions_per_frame
+                .or(fallback.instructions_per_frame),
+            display_mode: self.display_mode.or(fallback.display_mode),
+            theme: self.theme.or(fallback.theme),
+            colors: self.colors.or(fallback.colors),
+            color_depth: self.color_depth.or(fallback.color_depth),
+            character: self.character.or(fallback.character),
+            render_mode: self.render_mode.or(fallback.render_mode),
+            keymap: self.keymap.or(fallback.keymap),
+            graphics: self.graphics.or(fallback.graphics),
+            scale: self.scale.or(fallback.scale),
+            persistence: self.persistence.or(fallback.persistence),
+        }
+    }
+
+    /// Sets a setting from its config file key and value
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "platform" => self.platform = Some(value.parse()?),
+            "ipf" => {
+                self.instructions_per_frame = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("ipf expects a number, found '{}'", value))?,
+                )
+            }
+            "display" => self.display_mode = Some(parse_display_mode(value)?),
+            "theme" => self.theme = Some(value.parse()?),
+            "colors" => self.colors = Some(parse_colors(value)?),
+            "color-depth" => self.color_depth = Some(value.parse()?),
+            "character" => self.character = Some(parse_character(value)?),
+            "render" => self.render_mode = Some(value.parse()?),
+            "keymap" => self.keymap = Some(parse_keymap(value)?),
+            "graphics" => self.graphics = Some(parse_graphics(value)?),
+            "scale" => self.scale = Some(parse_scale(value)?),
+            "persistence" => self.persistence = Some(value.parse()?),
+            // Free text so people can tell the sections apart
+            "name" => {}
+            _ => return Err(format!("Unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Settings read from a config file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    /// Settings of the [global] section
+    pub global: Settings,
+    /// Settings of each ROM section, keyed by lowercase SHA-1 hex digest
+    pub roms: HashMap<String, Settings>,
+}
+
+impl Config {
+    /// Parses the text of a config file. Errors name the line they were found on.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section: Option<&mut Settings> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| error(String::from("Section name is missing ]")))?
+                    .trim()
+                    .to_ascii_lowercase();
+                if name == "global" {
+                    section = Some(&mut config.global);
+                } else if name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+                    section = Some(config.roms.entry(name).or_default());
+                } else {
+                    return Err(error(format!(
+                        "Section '{}' is neither global nor a SHA-1",
+                        name
+                    )));
+                }
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(String::from("Expected key = value")))?;
+            let settings = section
+                .as_deref_mut()
+                .ok_or_else(|| error(String::from("Setting outside of a section")))?;
+            settings
+                .set(&key.trim().to_ascii_lowercase(), value.trim())
+                .map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    /// Reads and parses a config file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Could not read config '{}': {}", path.display(), err))?;
+        Config::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Returns the settings of the ROM section for the given SHA-1 hex digest, without the
+    /// [global] section
+    pub fn rom_settings(&self, sha1: &str) -> Settings {
+        self.roms
+            .get(&sha1.to_ascii_lowercase())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Removes a ; or # comment from a line. A # directly after = or , is a #RRGGBB colour.
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        if c == ';' || (c == '#' && previous != '=' && previous != ',') {
+            return &line[..index];
+        }
+        if !c.is_whitespace() {
+            previous = c;
+        }
+    }
+    line
+}
+
+/// Parses a display mode name
+pub fn parse_display_mode(value: &str) -> Result<DisplayMode, String> {
+    match value {
+        "lores" | "64x32" => Ok(DisplayMode::H64V32MONOCHROME),
+        "hires" | "128x64" => Ok(DisplayMode::H128V64MONOCHROME),
+        other => Err(format!("Unknown display mode '{}'", other)),
+    }
+}
+
+/// Parses a single character
+pub fn parse_character(value: &str) -> Result<char, String> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(character), None) => Ok(character),
+        _ => Err(format!("Expected one character, found '{}'", value)),
+    }
+}
+
+/// Parses a colour name or #RRGGBB
+pub fn parse_color(value: &str) -> Result<Color, String> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() == 6 {
+            if let Ok(rgb) = u32::from_str_radix(hex, 16) {
+                return Ok(Color::Rgb {
+                    r: (rgb >> 16) as u8,
+                    g: (rgb >> 8) as u8,
+                    b: rgb as u8,
+                });
+            }
+        }
+    }
+    Color::try_from(value.to_ascii_lowercase().as_str())
+        .map_err(|_| format!("Unknown colour '{}'", value))
+}
+
+/// Parses three comma separated colours
+pub fn parse_colors(value: &str) -> Result<[Color; 3], String> {
+    let colors = value
+        .split(',')
+        .map(parse_color)
+        .collect::<Result<Vec<Color>, String>>()?;
+    match colors.as_slice() {
+        [first, second, both] => Ok([*first, *second, *both]),
+        _ => Err(format!("Expected 3 colours, found {}", colors.len())),
+    }
+}
+
+/// Parses auto, text, sixel or kitty
+pub fn parse_graphics(value: &str) -> Result<Graphics, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "auto" => Ok(Graphics::Auto),
+        "text" => Ok(Graphics::Text),
+        _ => value
+            .parse()
+            .map(Graphics::Protocol)
+            .map_err(|_| format!("Expected auto, text, sixel or kitty, found '{}'", value)),
+    }
+}
+
+/// Parses an image scale, a whole number from 1 to 32
+pub fn parse_scale(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(scale @ 1..=32) => Ok(scale),
+        _ => Err(format!("Expected a scale from 1 to 32, found '{}'", value)),
+    }
+}
+
+/// Parses 16 distinct characters, the keys for CHIP-8 keys 0-F
+pub fn parse_keymap(value: &str) -> Result<[char; 16], String> {
+    let keys: Vec<char> = value.chars().collect();
+    if keys.len() != 16 {
+        return Err(format!("Keymap needs 16 keys, found {}", keys.len()));
+    }
+    for (index, key) in keys.iter().enumerate() {
+        if keys[..index].contains(key) {
+            return Err(format!("Key '{}' is mapped twice", key));
+        }
+    }
+    let mut keymap = [' '; 16];
+    keymap.copy_from_slice(&keys);
+    Ok(keymap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+
+    #[test]
+    fn rom_section_overrides_global() {
+        let config = Config::parse(&format!(
+            "; defaults\n\
+             [global]\n\
+             ipf = 10\n\
+             platform = vip\n\
+             colors = white,#ff0000,blue ; comment\n\
+             \n\
+             [{}]\n\
+             name = Test ROM\n\
+             platform = schip\n\
+             character = #\n",
+            SHA1.to_ascii_uppercase()
+        ))
+        .unwrap();
+
+        let settings = config.rom_settings(SHA1).or(&config.global);
+        assert_eq!(settings.platform, Some(Platform::SuperChip));
+        assert_eq!(settings.instructions_per_frame, Some(10));
+        assert_eq!(settings.character, Some('#'));
+        assert_eq!(
+            settings.colors,
+            Some([Color::White, Color::Rgb { r: 255, g: 0, b: 0 }, Color::Blue])
+        );
+        assert_eq!(config.rom_settings("0"), Settings::default());
+        assert_eq!(config.global.platform, Some(Platform::CosmacVip));
+
+        let command_line = Settings {
+            instructions_per_frame: Some(30),
+            ..Settings::default()
+        };
+        assert_eq!(command_line.or(&settings).instructions_per_frame, Some(30));
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_eq!(
+            Config::parse("ipf = 10"),
+            Err(String::from("line 1: Setting outside of a section"))
+        );
+        assert_eq!(
+            Config::parse("[global]\nspeed = fast"),
+            Err(String::from("line 2: Unknown setting 'speed'"))
+        );
+        assert!(Config::parse("[pong]").is_err());
+        assert!(Config::parse("[global]\nkeymap = 0123").is_err());
+    }
+}
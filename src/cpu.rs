@@ -2,7 +2,9 @@ use crate::memory::Memory;
 
 use crate::display::{DisplayBuffer, DisplayMode};
 use crate::input::Keyboard;
-use std::time::{Duration, Instant};
+use crate::quirks::{Platform, Quirks};
+use crate::rng::{RandomSource, SeededRng};
+use crate::timers::FrameClock;
 
 /// Chip-8 CPU. Contains all Registers and Memory included in the CHIP-8 System.
 pub struct CPU {
@@ -10,7 +12,8 @@ pub struct CPU {
     pub mem: Memory,
     /// Function Stack - Array of 16x16-bit values (16 words)
     pub stack: [u16; 16],
-    /// General purpose registers - 16x8-bit registers stored as u8 array.
+    /// General purpose registers - 16x8-bit registers stored as u8 array. The last register (VF)
+    /// is also written by the interpreter as the carry, borrow and collision flag.
     pub gp_regs: [u8; 16],
     /// Index Register - Single 16-bit register commonly used to store addresses
     pub i_reg: u16,
@@ -20,15 +23,31 @@ pub struct CPU {
     pub sound_reg: u8,
     /// Program Counter - Single 16-bit register that points to the current memory instruction
     pub pc_reg: u16,
-    /// Stack Register - Single 8-bit register that points to the address of the top of the stack.
+    /// Stack Register - Single 8-bit register that counts the addresses on the stack. The top of the
+    /// stack is the entry below it.
     pub stack_pointer_reg: u8,
-    /// Video Register - Single 8-bit register. Set to 1 by interpreter if a pixel collision occurs
-    pub vf_reg: u8,
+    /// RPL user flags - 16x8-bit registers that persist V0 through VF outside of memory (SUPER-CHIP)
+    pub rpl_flags: [u8; 16],
+    /// Audio pattern buffer - 128 1-bit samples played while the sound timer is non-zero (XO-CHIP)
+    pub audio_pattern: [u8; 16],
+    /// Pitch register - Playback rate of the audio pattern is 4000*2^((pitch-64)/48) hz (XO-CHIP)
+    pub pitch_reg: u8,
 
-    /// Timestamp used to track delay register update
-    pub delay_reg_timestamp: Instant,
-    /// Timestamp used to track sound register update
-    pub sound_register_timestamp: Instant,
+    /// Emulated clock that ticks the delay and sound registers once per 60hz frame
+    pub clock: FrameClock,
+    /// Set after a draw when the display wait quirk is active. Execution halts until the next
+    /// display refresh clears it.
+    pub waiting_for_display: bool,
+
+    /// Platform the CPU was last configured for, which sets the memory size
+    pub platform: Platform,
+    /// Quirks selecting the behaviour of ambiguous opcodes
+    pub quirks: Quirks,
+    /// Source of the random bytes used by Cxkk
+    pub rng: Box<dyn RandomSource>,
+    /// SHA-1 digest of the ROM loaded by rom_loader, used to match save states and movies to
+    /// their ROM
+    pub rom_sha1: [u8; 20],
 
     /// Display Buffer of this CPU
     pub display_buffer: DisplayBuffer,
@@ -47,27 +66,56 @@ impl CPU {
             sound_reg: 0,
             pc_reg: 0,
             stack_pointer_reg: 0,
-            vf_reg: 0,
-            delay_reg_timestamp: Instant::now(),
-            sound_register_timestamp: Instant::now(),
+            rpl_flags: [0; 16],
+            audio_pattern: [0; 16],
+            pitch_reg: 64,
+            clock: FrameClock::default(),
+            waiting_for_display: false,
+            platform: Platform::CosmacVip,
+            quirks: Quirks::default(),
+            rng: Box::new(SeededRng::from_entropy()),
+            rom_sha1: [0; 20],
             display_buffer: DisplayBuffer::new(display_mode),
             keyboard: Keyboard::new(),
         }
     }
 
-    /// Checks if it is time to decrement the delay and sound register (60hz)
-    pub fn update_time_registers(&mut self) {
-        if self.delay_reg > 0 {
-            if self.delay_reg_timestamp.elapsed() > Duration::from_micros(16666) {
-                self.delay_reg -= 1;
-                self.delay_reg_timestamp = Instant::now();
-            }
-        }
-        if self.sound_reg > 0 {
-            if self.sound_register_timestamp.elapsed() > Duration::from_micros(16666) {
-                self.sound_reg -= 1;
-                self.sound_register_timestamp = Instant::now();
-            }
+    /// Constructs a new CPU configured for the given Platform. Sets the quirks and memory size of
+    /// the platform, and starts in the 64x32 display mode.
+    pub fn for_platform(platform: Platform) -> CPU {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        cpu.mem = Memory::with_size(platform.memory_size());
+        cpu.platform = platform;
+        cpu.quirks = platform.quirks();
+        cpu
+    }
+
+    /// Switches the CPU to the given Platform. Sets the quirks of the platform and resizes memory,
+    /// keeping the contents that still fit.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.mem.mem.resize(platform.memory_size(), 0);
+        self.platform = platform;
+        self.quirks = platform.quirks();
+    }
+
+    /// Replaces the random source with a SeededRng using the given seed
+    pub fn seed_rng(&mut self, seed: u32) {
+        self.rng = Box::new(SeededRng::new(seed));
+    }
+
+    /// Counts one executed instruction on the emulated clock. Ticks the timers when the
+    /// instruction completes a frame.
+    pub fn advance_clock(&mut self) {
+        if self.clock.step() {
+            self.tick_timers();
         }
     }
+
+    /// Advances the timers by exactly one 60hz frame. Decrements the delay and sound registers and
+    /// ends any display wait.
+    pub fn tick_timers(&mut self) {
+        self.delay_reg = self.delay_reg.saturating_sub(1);
+        self.sound_reg = self.sound_reg.saturating_sub(1);
+        self.waiting_for_display = false;
+    }
 }
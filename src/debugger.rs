@@ -0,0 +1,261 @@
+//! Breakpoint and stepping layer around the CPU.
+//!
+//! The Debugger owns a CPU and drives it through instructions::execute. Every run method takes a
+//! step budget, so a frontend can keep its own frame loop (input, pacing, drawing) going between
+//! calls, and tests can run a program without the risk of hanging on an endless loop.
+
+use crate::cpu::CPU;
+use crate::error::Chip8Error;
+use crate::instructions::{execute, StepOutcome};
+use crate::opcode::{decode, Instruction};
+use std::collections::BTreeSet;
+
+/// Why the Debugger stopped executing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason {
+    /// A single step, step-over, step-out or run-to completed
+    Stepped,
+    /// The program counter reached a breakpoint
+    Breakpoint(u16),
+    /// A Dxyn instruction drew a sprite
+    Draw,
+    /// Execution started waiting for a key on Fx0A
+    KeyWait,
+    /// The sound timer went from zero to non-zero
+    SoundStart,
+    /// The program exited with 00FD
+    Exited,
+    /// The step budget ran out before any other reason to stop
+    StepLimit,
+    /// The CPU faulted. The CPU is left as it was when the fault happened.
+    Fault(Chip8Error),
+}
+
+/// Wraps a CPU with breakpoints, stepping and event breaks
+pub struct Debugger {
+    /// CPU being debugged
+    pub cpu: CPU,
+    /// Stop after a Dxyn instruction draws
+    pub break_on_draw: bool,
+    /// Stop when Fx0A starts waiting for a key
+    pub break_on_key_wait: bool,
+    /// Stop when the sound timer starts
+    pub break_on_sound_start: bool,
+    /// Program counter breakpoints
+    breakpoints: BTreeSet<u16>,
+    /// Set while the CPU is halted on Fx0A, so the wait only breaks once
+    waiting_for_key: bool,
+}
+
+impl Debugger {
+    /// Constructs a new Debugger around the given CPU, with no breakpoints and no event breaks
+    pub fn new(cpu: CPU) -> Debugger {
+        Debugger {
+            cpu,
+            break_on_draw: false,
+            break_on_key_wait: false,
+            break_on_sound_start: false,
+            breakpoints: BTreeSet::new(),
+            waiting_for_key: false,
+        }
+    }
+
+    /// Adds a breakpoint at the given address
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes the breakpoint at the given address. Returns false if there was none.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Removes all breakpoints
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Returns the breakpoint addresses in ascending order
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.iter()
+    }
+
+    /// Returns the instruction at the program counter, if it decodes
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        let pc = self.cpu.pc_reg as usize;
+        if pc + 1 >= self.cpu.mem.mem.len() {
+            return None;
+        }
+        let opcode = ((self.cpu.mem.mem[pc] as u16) << 8) | self.cpu.mem.mem[pc + 1] as u16;
+        decode(opcode).ok()
+    }
+
+    /// Executes a single instruction. A pending display wait is skipped, so the step always
+    /// executes the instruction at the program counter.
+    pub fn step(&mut self) -> StopReason {
+        self.cpu.waiting_for_display = false;
+        match self.execute_one() {
+            Some(StopReason::Breakpoint(_)) | None => StopReason::Stepped,
+            Some(reason) => reason,
+        }
+    }
+
+    /// Runs until a breakpoint, an enabled event, an exit or a fault, or until max_steps
+    /// instructions have executed. The instruction at the program counter always executes, so
+    /// continuing from a breakpoint does not stop on it again.
+    pub fn run(&mut self, max_steps: usize) -> StopReason {
+        self.run_until(max_steps, |_| false)
+    }
+
+    /// Runs until the program counter reaches addr. Stops early for the same reasons as run.
+    pub fn run_to(&mut self, addr: u16, max_steps: usize) -> StopReason {
+        self.run_until(max_steps, |cpu| cpu.pc_reg == addr)
+    }
+
+    /// Steps over a 2nnn call, running until the subroutine returns. Any other instruction is
+    /// single-stepped.
+    pub fn step_over(&mut self, max_steps: usize) -> StopReason {
+        match self.current_instruction() {
+            Some(Instruction::Call { .. }) => {
+                let return_addr = self.cpu.pc_reg.wrapping_add(2);
+                let depth = self.cpu.stack_pointer_reg;
+                self.run_until(max_steps, |cpu| {
+                    cpu.pc_reg == return_addr && cpu.stack_pointer_reg == depth
+                })
+            }
+            _ => self.step(),
+        }
+    }
+
+    /// Runs until the 00EE that returns from the current subroutine
+    pub fn step_out(&mut self, max_steps: usize) -> StopReason {
+        let depth = self.cpu.stack_pointer_reg;
+        self.run_until(max_steps, |cpu| cpu.stack_pointer_reg < depth)
+    }
+
+    /// Runs until done returns true after an instruction, stopping early for breakpoints, events,
+    /// exits and faults
+    fn run_until<F: Fn(&CPU) -> bool>(&mut self, max_steps: usize, done: F) -> StopReason {
+        for _ in 0..max_steps {
+            if let Some(reason) = self.execute_one() {
+                return reason;
+            }
+            if done(&self.cpu) {
+                return StopReason::Stepped;
+            }
+        }
+        StopReason::StepLimit
+    }
+
+    /// Executes the instruction at the program counter. Returns the reason to stop, if any.
+    fn execute_one(&mut self) -> Option<StopReason> {
+        let instruction = self.current_instruction();
+        let sound_was_off = self.cpu.sound_reg == 0;
+
+        let outcome = match execute(&mut self.cpu) {
+            Ok(outcome) => outcome,
+            Err(err) => return Some(StopReason::Fault(err)),
+        };
+
+        let was_waiting = self.waiting_for_key;
+        self.waiting_for_key = outcome == StepOutcome::WaitingForKey;
+        match outcome {
+            StepOutcome::Exited => return Some(StopReason::Exited),
+            StepOutcome::WaitingForKey if self.break_on_key_wait && !was_waiting => {
+                return Some(StopReason::KeyWait)
+            }
+            StepOutcome::Drew
+                if self.break_on_draw && matches!(instruction, Some(Instruction::Draw { .. })) =>
+            {
+                return Some(StopReason::Draw)
+            }
+            _ => {}
+        }
+        if self.break_on_sound_start && sound_was_off && self.cpu.sound_reg != 0 {
+            return Some(StopReason::SoundStart);
+        }
+        if self.breakpoints.contains(&self.cpu.pc_reg) {
+            return Some(StopReason::Breakpoint(self.cpu.pc_reg));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::display::DisplayMode;
+    use crate::quirks::{Platform, Quirks};
+    use crate::rom_loader::load_rom_bytes;
+
+    fn debugger(source: &str) -> Debugger {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        cpu.quirks = Quirks::CHIP_48;
+        load_rom_bytes(&mut cpu, &assemble(source).unwrap()).unwrap();
+        cpu.pc_reg = 0x200;
+        Debugger::new(cpu)
+    }
+
+    const PROGRAM: &str = "
+        start:  CALL sub        ; 200
+                LD V1, 1        ; 202
+        loop:   JP loop         ; 204
+        sub:    LD V0, 5        ; 206
+                LD ST, V0       ; 208
+                DRW V0, V0, 1   ; 20A
+                RET             ; 20C
+    ";
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut dbg = debugger(PROGRAM);
+        dbg.add_breakpoint(0x20A);
+        assert_eq!(dbg.run(100), StopReason::Breakpoint(0x20A));
+        assert_eq!(dbg.run(100), StopReason::StepLimit);
+        assert_eq!(dbg.cpu.pc_reg, 0x204);
+
+        let mut dbg = debugger(PROGRAM);
+        assert_eq!(dbg.step_over(100), StopReason::Stepped);
+        assert_eq!((dbg.cpu.pc_reg, dbg.cpu.gp_regs[0]), (0x202, 5));
+
+        let mut dbg = debugger(PROGRAM);
+        dbg.step();
+        assert_eq!(dbg.step_out(100), StopReason::Stepped);
+        assert_eq!(dbg.cpu.pc_reg, 0x202);
+
+        let mut dbg = debugger(PROGRAM);
+        assert_eq!(dbg.run_to(0x208, 100), StopReason::Stepped);
+        assert_eq!(dbg.cpu.pc_reg, 0x208);
+    }
+
+    #[test]
+    fn steps_over_a_call_at_the_end_of_memory() {
+        let mut cpu = CPU::for_platform(Platform::XoChip);
+        // CALL 0x206 as the last instruction, returning to 0x0000; LD V0, 5; RET
+        cpu.mem.mem[0xFFFE..].copy_from_slice(&[0x22, 0x06]);
+        cpu.mem.mem[0x206..0x20A].copy_from_slice(&[0x60, 0x05, 0x00, 0xEE]);
+        cpu.pc_reg = 0xFFFE;
+        let mut dbg = Debugger::new(cpu);
+        assert_eq!(dbg.step_over(100), StopReason::Stepped);
+        assert_eq!((dbg.cpu.pc_reg, dbg.cpu.gp_regs[0]), (0x0000, 5));
+    }
+
+    #[test]
+    fn breaks_on_events() {
+        let mut dbg = debugger(PROGRAM);
+        dbg.break_on_sound_start = true;
+        dbg.break_on_draw = true;
+        assert_eq!(dbg.run(100), StopReason::SoundStart);
+        assert_eq!(dbg.cpu.pc_reg, 0x20A);
+        assert_eq!(dbg.run(100), StopReason::Draw);
+
+        let mut dbg = debugger("LD V0, K\nEXIT");
+        dbg.break_on_key_wait = true;
+        assert_eq!(dbg.run(100), StopReason::KeyWait);
+        assert_eq!(dbg.run(100), StopReason::StepLimit);
+        dbg.cpu.keyboard.key_7 = true;
+        assert_eq!(dbg.run(100), StopReason::Exited);
+        assert_eq!(dbg.cpu.gp_regs[0], 7);
+    }
+}
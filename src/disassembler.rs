@@ -0,0 +1,322 @@
+//! Recursive traversal disassembler for CHIP-8 ROMs.
+//!
+//! Starting from the load address, every reachable instruction is followed through jumps, calls
+//! and skips. Bytes that are never reached are treated as data. Data referenced by LD I is most
+//! likely sprite data, so it is rendered as a bitmap of '#' and '.' characters.
+
+use crate::opcode::{decode, Instruction};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The kind of auto-generated label attached to an address
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub enum LabelKind {
+    /// Entry point of the program
+    Start,
+    /// Target of a 2nnn call
+    Subroutine,
+    /// Target of a 1nnn or Bnnn jump
+    Jump,
+    /// Target of an Annn or F000 NNNN load of I
+    Data,
+}
+
+impl LabelKind {
+    /// Returns the label name for the given address
+    pub fn name(&self, addr: u16) -> String {
+        match *self {
+            LabelKind::Start => String::from("start"),
+            LabelKind::Subroutine => format!("sub_{:03X}", addr),
+            LabelKind::Jump => format!("label_{:03X}", addr),
+            LabelKind::Data => format!("data_{:03X}", addr),
+        }
+    }
+}
+
+/// Result of disassembling a ROM. Holds the code/data map and labels, and renders them as text.
+pub struct Disassembly<'a> {
+    /// ROM bytes being disassembled
+    rom: &'a [u8],
+    /// Address the first byte of the ROM is loaded at
+    load_addr: u16,
+    /// Decoded instructions, keyed by address
+    code: BTreeMap<u16, Instruction>,
+    /// Labels, keyed by address. When several kinds apply, the lowest kind wins.
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+/// Disassembles a ROM loaded at load_addr, following control flow from the load address
+pub fn disassemble(rom: &[u8], load_addr: u16) -> Disassembly<'_> {
+    let mut disassembly = Disassembly {
+        rom,
+        load_addr,
+        code: BTreeMap::new(),
+        labels: BTreeMap::new(),
+    };
+    disassembly.add_label(load_addr, LabelKind::Start);
+
+    let mut pending = vec![load_addr];
+    while let Some(addr) = pending.pop() {
+        disassembly.trace(addr, &mut pending);
+    }
+    disassembly
+}
+
+impl<'a> Disassembly<'a> {
+    /// Returns the instruction decoded at the given address, if the address was reached as code
+    pub fn instruction_at(&self, addr: u16) -> Option<&Instruction> {
+        self.code.get(&addr)
+    }
+
+    /// Returns the name of the label at the given address, if any. Labels are only given to
+    /// addresses inside the ROM that do not fall in the middle of an instruction.
+    pub fn label_at(&self, addr: u16) -> Option<String> {
+        if !self.is_boundary(addr) {
+            return None;
+        }
+        self.labels.get(&addr).map(|kind| kind.name(addr))
+    }
+
+    /// Returns true if the address lies inside the ROM and is not covered by the operand bytes
+    /// of an instruction
+    fn is_boundary(&self, addr: u16) -> bool {
+        let end = self.load_addr as usize + self.rom.len();
+        if addr < self.load_addr || addr as usize >= end {
+            return false;
+        }
+        (1..4).all(|back| match self.code.get(&addr.wrapping_sub(back)) {
+            Some(instruction) => instruction.size() <= back,
+            None => true,
+        })
+    }
+
+    /// Returns the 16-bit word at the given address, if it lies inside the ROM
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(self.load_addr)? as usize;
+        if offset + 1 >= self.rom.len() {
+            return None;
+        }
+        Some(((self.rom[offset] as u16) << 8) | self.rom[offset + 1] as u16)
+    }
+
+    /// Returns the word after the instruction at the given address, the operand of F000 NNNN
+    fn operand_at(&self, addr: u16) -> Option<u16> {
+        self.word_at(addr.checked_add(2)?)
+    }
+
+    /// Attaches a label to an address, keeping the more important kind if one already exists
+    fn add_label(&mut self, addr: u16, kind: LabelKind) {
+        let entry = self.labels.entry(addr).or_insert(kind);
+        if kind < *entry {
+            *entry = kind;
+        }
+    }
+
+    /// Follows a straight line of code from addr until control flow leaves it. Branch targets
+    /// are pushed onto pending.
+    fn trace(&mut self, mut addr: u16, pending: &mut Vec<u16>) {
+        loop {
+            if self.code.contains_key(&addr) {
+                return;
+            }
+            let instruction = match self.word_at(addr).map(decode) {
+                Some(Ok(instruction)) => instruction,
+                _ => return,
+            };
+            // The operand of F000 NNNN must also be inside the ROM. There is no next instruction
+            // past the end of memory.
+            if instruction.size() > 2 && self.operand_at(addr).is_none() {
+                return;
+            }
+            let next = addr.checked_add(instruction.size());
+            self.code.insert(addr, instruction);
+
+            match instruction {
+                Instruction::Jump { nnn } => {
+                    self.add_label(nnn, LabelKind::Jump);
+                    pending.push(nnn);
+                    return;
+                }
+                Instruction::JumpV0 { nnn } => {
+                    // The offset is only known at runtime, so only the base is labelled
+                    self.add_label(nnn, LabelKind::Jump);
+                    return;
+                }
+                Instruction::Call { nnn } => {
+                    self.add_label(nnn, LabelKind::Subroutine);
+                    pending.push(nnn);
+                }
+                Instruction::LoadI { nnn } => self.add_label(nnn, LabelKind::Data),
+                Instruction::LongLoadI => {
+                    let target = self.operand_at(addr).unwrap();
+                    self.add_label(target, LabelKind::Data);
+                }
+                Instruction::SkipEqual { .. }
+                | Instruction::SkipNotEqual { .. }
+                | Instruction::SkipEqualXY { .. }
+                | Instruction::SkipNotEqualXY { .. }
+                | Instruction::SkipIfKey { .. }
+                | Instruction::SkipNotKey { .. } => {
+                    let skipped = match next.and_then(|next| self.word_at(next)) {
+                        Some(0xF000) => 4,
+                        _ => 2,
+                    };
+                    if let Some(target) = next.and_then(|next| next.checked_add(skipped)) {
+                        pending.push(target);
+                    }
+                }
+                Instruction::Ret | Instruction::Exit => return,
+                _ => {}
+            }
+            match next {
+                Some(next) => addr = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Formats an instruction, replacing addresses that carry a label with the label name
+    fn format_instruction(&self, addr: u16, instruction: &Instruction) -> String {
+        let label = |target: u16| {
+            self.label_at(target)
+                .unwrap_or_else(|| format!("#{:03X}", target))
+        };
+        match *instruction {
+            Instruction::Jump { nnn } => format!("JP {}", label(nnn)),
+            Instruction::Call { nnn } => format!("CALL {}", label(nnn)),
+            Instruction::LoadI { nnn } => format!("LD I, {}", label(nnn)),
+            Instruction::JumpV0 { nnn } => format!("JP V0, {}", label(nnn)),
+            Instruction::LongLoadI => {
+                let target = self.operand_at(addr).unwrap_or(0);
+                match self.label_at(target) {
+                    Some(name) => format!("LD I, LONG {}", name),
+                    None => format!("LD I, LONG #{:04X}", target),
+                }
+            }
+            _ => instruction.to_string(),
+        }
+    }
+
+    /// Renders the disassembly as assembler source. Each line shows the address and raw bytes as
+    /// a comment, so the output can be fed back into the assembler.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let end = self.load_addr as usize + self.rom.len();
+        let mut addr = self.load_addr as usize;
+        let mut in_sprite = false;
+
+        while addr < end {
+            let addr16 = addr as u16;
+            if let Some(name) = self.label_at(addr16) {
+                writeln!(out, "{}:", name).unwrap();
+                in_sprite = self.labels[&addr16] == LabelKind::Data;
+            }
+
+            if let Some(instruction) = self.code.get(&addr16) {
+                let size = instruction.size() as usize;
+                let offset = addr - self.load_addr as usize;
+                let bytes: String = self.rom[offset..offset + size]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                writeln!(
+                    out,
+                    "    {:<24}; {:03X}: {}",
+                    self.format_instruction(addr16, instruction),
+                    addr,
+                    bytes
+                )
+                .unwrap();
+                addr += size;
+                in_sprite = false;
+                continue;
+            }
+
+            // Data runs until the next instruction or label
+            let mut run_end = addr + 1;
+            while run_end < end
+                && !self.code.contains_key(&(run_end as u16))
+                && !self.labels.contains_key(&(run_end as u16))
+            {
+                run_end += 1;
+            }
+            let data = &self.rom[addr - self.load_addr as usize..run_end - self.load_addr as usize];
+            if in_sprite {
+                for (index, byte) in data.iter().enumerate() {
+                    let bitmap: String = (0..8)
+                        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                        .collect();
+                    writeln!(
+                        out,
+                        "    DB #{:02X}                  ; {:03X}: {}",
+                        byte,
+                        addr + index,
+                        bitmap
+                    )
+                    .unwrap();
+                }
+            } else {
+                for (index, chunk) in data.chunks(8).enumerate() {
+                    let bytes: Vec<String> =
+                        chunk.iter().map(|byte| format!("#{:02X}", byte)).collect();
+                    writeln!(
+                        out,
+                        "    DB {:<21}; {:03X}",
+                        bytes.join(", "),
+                        addr + index * 8
+                    )
+                    .unwrap();
+                }
+            }
+            addr = run_end;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_code_from_sprite_data() {
+        // LD I, data; DRW V0, V0, 2; JP self; followed by a 2 byte sprite
+        let rom = [0xA2, 0x06, 0xD0, 0x02, 0x12, 0x04, 0xF0, 0x90];
+        let disassembly = disassemble(&rom, 0x200);
+        assert_eq!(
+            disassembly.instruction_at(0x204),
+            Some(&Instruction::Jump { nnn: 0x204 })
+        );
+        assert_eq!(disassembly.instruction_at(0x206), None);
+
+        let text = disassembly.render();
+        assert!(text.contains("LD I, data_206"));
+        assert!(text.contains("label_204:"));
+        assert!(text.contains("####...."));
+        assert!(text.contains("#..#...."));
+    }
+
+    #[test]
+    fn follows_both_sides_of_a_skip() {
+        // SE V0, #00; JP #208; CALL #20A; RET; RET
+        let rom = [
+            0x30, 0x00, 0x12, 0x08, 0x22, 0x0A, 0x00, 0x00, 0x00, 0xEE, 0x00, 0xEE,
+        ];
+        let disassembly = disassemble(&rom, 0x200);
+        assert!(disassembly.instruction_at(0x204).is_some());
+        assert_eq!(disassembly.label_at(0x20A), Some(String::from("sub_20A")));
+        assert!(disassembly.instruction_at(0x206).is_some());
+    }
+
+    #[test]
+    fn stops_at_the_end_of_memory() {
+        // JP #FFFC at the start, then SE V0, #00; SE V0, #00 in the last 4 bytes of memory
+        let mut rom = vec![0; 0xFE00];
+        rom[..2].copy_from_slice(&[0x1F, 0xFC]);
+        rom[0xFDFC..].copy_from_slice(&[0x30, 0x00, 0x30, 0x00]);
+        let disassembly = disassemble(&rom, 0x200);
+        assert!(disassembly.instruction_at(0xFFFC).is_some());
+        assert!(disassembly.instruction_at(0xFFFE).is_some());
+        assert!(disassembly.render().contains("SE V0, #00"));
+    }
+}
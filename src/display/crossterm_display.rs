@@ -1,51 +1,107 @@
+use crate::display::persistence::Afterglow;
+use crate::display::render_mode::{pixel_shade, Cell, RenderMode};
+use crate::display::theme::{mix, ColorDepth, Theme};
 use crate::display::{Display, DisplayBuffer, DisplayMode};
-use crossterm::{
-    cursor,
-    style::{self, Colorize},
-    terminal, ExecutableCommand, QueueableCommand, Result,
-};
-use std::io::Write;
+use crossterm::{cursor, style, terminal, QueueableCommand, Result};
+use std::io::{self, Write};
+
+/// Default character drawn for pixels that are ON
+pub const DEFAULT_TERM_CHARACTER: char = '*';
+
+/// Default colours of pixels ON in the first plane, the second plane, and both planes
+pub const DEFAULT_PIXEL_COLORS: [style::Color; 3] =
+    [style::Color::Green, style::Color::Red, style::Color::Yellow];
 
 /// Chip-8 Display interface object that uses CrossTerm as its concrete implementation.
-pub struct CrosstermDisplay {
-    /// Handle to standard output for writing to terminal
-    stdout: std::io::Stdout,
-    /// Content style object for formatting terminal output
-    term_char: style::StyledContent<char>,
+pub struct CrosstermDisplay<W: Write> {
+    /// Where escape sequences are written
+    out: W,
+    /// Character drawn for pixels that are ON
+    term_char: char,
+    /// Colours of ON pixels, indexed by colour index - 1. The first colour is used for pixels in
+    /// the first plane only, the second for the second plane only and the third for both (XO-CHIP).
+    pixel_colors: [style::Color; 3],
+    /// Colour of pixels that are OFF. None leaves them in the terminal's background colour.
+    background: Option<style::Color>,
+    /// Colours the terminal can show
+    color_depth: ColorDepth,
+    /// DisplayMode the terminal is currently sized for
+    display_mode: DisplayMode,
+    /// How pixels are drawn with terminal characters
+    render_mode: RenderMode,
+    /// True if the display set up the terminal, and should restore it when dropped
+    owns_terminal: bool,
+    /// Cells of the last frame drawn, as they are on the terminal. None when the terminal is
+    /// blank, or must be repainted in full.
+    last_frame: Option<Vec<Vec<Cell>>>,
 }
 
-impl Display for CrosstermDisplay {
-    fn draw(&mut self, display_buffer: &DisplayBuffer) {
-        // Align cursor to start
-        self.stdout.queue(cursor::MoveTo(0, 0)).unwrap();
-        self.stdout
-            .queue(terminal::Clear(terminal::ClearType::All))
-            .unwrap();
+/// Unchanged cells between two changed cells that are rewritten rather than skipped with a cursor
+/// move. A MoveTo costs around 8 bytes, so short gaps are cheaper to rewrite.
+const MAX_REWRITTEN_GAP: usize = 4;
 
-        // Iterate over the display buffer and draw
-        for (col, vbuf) in display_buffer.buff.iter().enumerate() {
-            for (row, pixel) in vbuf.iter().enumerate() {
-                // Write pixel data
-                if *pixel {
-                    self.stdout
-                        .queue(style::PrintStyledContent(self.term_char))
-                        .unwrap();
-                } else {
-                    self.stdout.queue(cursor::MoveRight(1)).unwrap();
+/// A horizontal run of cells to repaint, starting at column x of row y
+#[derive(Debug, PartialEq)]
+struct Run<T> {
+    x: usize,
+    y: usize,
+    cells: Vec<T>,
+}
+
+/// Returns the runs of cells that differ between two frames of the same size. Changed cells
+/// separated by at most MAX_REWRITTEN_GAP unchanged cells share a run.
+fn changed_runs<T: Copy + PartialEq>(previous: &[Vec<T>], current: &[Vec<T>]) -> Vec<Run<T>> {
+    let mut runs = Vec::new();
+    for (y, (previous, current)) in previous.iter().zip(current).enumerate() {
+        let width = current.len();
+        let mut x = 0;
+        while x < width {
+            if previous[x] == current[x] {
+                x += 1;
+                continue;
+            }
+
+            // Extend the run to the last changed cell that is no more than the gap away
+            let start = x;
+            let mut end = x + 1;
+            let mut scan = end;
+            while scan < width && scan - end <= MAX_REWRITTEN_GAP {
+                if previous[scan] != current[scan] {
+                    end = scan + 1;
                 }
+                scan += 1;
             }
-            self.stdout.queue(cursor::MoveToColumn(0)).unwrap();
-            self.stdout.queue(cursor::MoveDown(1)).unwrap();
+            runs.push(Run {
+                x: start,
+                y,
+                cells: current[start..end].to_vec(),
+            });
+            x = end;
         }
+    }
+    runs
+}
 
-        self.stdout.flush().unwrap();
+impl<W: Write> Display for CrosstermDisplay<W> {
+    fn draw(&mut self, display_buffer: &DisplayBuffer) {
+        let cells = self.render_mode.cells(display_buffer, self.term_char);
+        self.draw_cells(display_buffer.get_display_mode(), cells);
+    }
+
+    /// Draws fading pixels in colours between their ON colour and the background
+    fn draw_with_afterglow(&mut self, display_buffer: &DisplayBuffer, afterglow: &Afterglow) {
+        let cells =
+            self.render_mode
+                .cells_with_afterglow(display_buffer, afterglow, self.term_char);
+        self.draw_cells(display_buffer.get_display_mode(), cells);
     }
 
     fn clear_screen(&mut self) {
-        self.stdout
+        self.last_frame = None;
+        self.out
             .queue(terminal::Clear(terminal::ClearType::All))
             .unwrap();
-        self.stdout.flush().unwrap();
+        self.out.flush().unwrap();
     }
 
     fn hide(&mut self) {
@@ -53,37 +109,280 @@ impl Display for CrosstermDisplay {
     }
 }
 
-impl CrosstermDisplay {
+impl<W: Write> Drop for CrosstermDisplay<W> {
+    /// Returns the terminal to its normal state if the display set it up, so that output after
+    /// the display is dropped (such as crash reports) is readable.
+    fn drop(&mut self) {
+        if self.owns_terminal {
+            let _ = self.restore_terminal();
+        }
+    }
+}
+
+impl CrosstermDisplay<io::Stdout> {
     /// Constructs a new CrossTermDisplay. RAII, formats the terminal display upon construction.
-    pub fn new(mode: &DisplayMode) -> CrosstermDisplay {
-        let mut new = CrosstermDisplay {
-            stdout: std::io::stdout(),
-            term_char: style::style('*').with(style::Color::Green),
-        };
+    pub fn new(mode: &DisplayMode) -> CrosstermDisplay<io::Stdout> {
+        let mut new = CrosstermDisplay::with_writer(io::stdout(), mode);
+        new.owns_terminal = true;
         // TODO: Actually handle failure to setup terminal
-        new.setup_terminal(&mode).unwrap();
-        return new;
+        new.setup_terminal(mode).unwrap();
+        new
+    }
+}
+
+impl<W: Write> CrosstermDisplay<W> {
+    /// Constructs a CrosstermDisplay that writes to out, leaving the terminal as it is
+    pub fn with_writer(out: W, mode: &DisplayMode) -> CrosstermDisplay<W> {
+        CrosstermDisplay {
+            out,
+            term_char: DEFAULT_TERM_CHARACTER,
+            pixel_colors: DEFAULT_PIXEL_COLORS,
+            background: None,
+            color_depth: ColorDepth::TrueColor,
+            display_mode: *mode,
+            render_mode: RenderMode::Character,
+            owns_terminal: false,
+            last_frame: None,
+        }
     }
 
     /// Sets the character and color for the terminal
     pub fn set_term_character(&mut self, character: &char, color: &style::Color) {
-        self.term_char = style::style(*character).with(*color);
+        self.term_char = *character;
+        self.pixel_colors[0] = *color;
+        self.repaint();
+    }
+
+    /// Sets the colors of pixels ON in the first plane, the second plane, and both planes
+    pub fn set_plane_colors(&mut self, colors: [style::Color; 3]) {
+        self.pixel_colors = colors;
+        self.repaint();
+    }
+
+    /// Sets the colours of pixels OFF and ON to those of a theme. The theme's colours should be
+    /// ones the terminal can show, see Theme::degraded.
+    pub fn set_theme(&mut self, theme: &Theme) {
+        self.pixel_colors = theme.pixel_colors;
+        self.background = Some(theme.background);
+        self.repaint();
+    }
+
+    /// Sets the colours the terminal can show, which the shades of fading pixels are converted to
+    pub fn set_color_depth(&mut self, color_depth: ColorDepth) {
+        self.color_depth = color_depth;
+    }
+
+    /// Repaints only the cells that changed since the last frame drawn. Each run of changed cells
+    /// is one cursor move followed by one write per colour.
+    fn draw_cells(&mut self, mode: DisplayMode, current: Vec<Vec<Cell>>) {
+        // Resize the terminal if the program switched resolution
+        if mode != self.display_mode {
+            self.setup_terminal(&mode).unwrap();
+        }
+
+        // After the screen was cleared, every lit cell is a change from a blank frame. With a
+        // background colour the blank cells must be painted too, so nothing matches.
+        let cleared = match self.background {
+            Some(_) => Cell {
+                glyph: '\0',
+                ..Cell::BLANK
+            },
+            None => Cell::BLANK,
+        };
+        let previous = self
+            .last_frame
+            .take()
+            .unwrap_or_else(|| current.iter().map(|row| vec![cleared; row.len()]).collect());
+
+        for run in changed_runs(&previous, &current) {
+            self.out
+                .queue(cursor::MoveTo(run.x as u16, run.y as u16))
+                .unwrap();
+            for segment in run
+                .cells
+                .chunk_by(|a, b| (a.foreground, a.background) == (b.foreground, b.background))
+            {
+                let glyphs: String = segment.iter().map(|cell| cell.glyph).collect();
+                let mut content = style::style(glyphs);
+                if segment[0].foreground != 0 {
+                    content = content.with(self.color(segment[0].foreground));
+                }
+                match (segment[0].background, self.background) {
+                    (0, None) => {}
+                    (0, Some(background)) => content = content.on(background),
+                    (value, _) => content = content.on(self.color(value)),
+                }
+                self.out.queue(style::PrintStyledContent(content)).unwrap();
+            }
+        }
+
+        self.out.flush().unwrap();
+        self.last_frame = Some(current);
+    }
+
+    /// Returns the colour of a pixel value
+    fn color(&self, value: u8) -> style::Color {
+        let (planes, brightness) = pixel_shade(value);
+        let color = self.pixel_colors[planes as usize - 1];
+        if value == planes {
+            return color;
+        }
+        let background = self.background.unwrap_or(style::Color::Black);
+        self.color_depth.degrade(mix(color, background, brightness))
+    }
+
+    /// Makes the next draw repaint every cell, for when the look of the pixels changed
+    fn repaint(&mut self) {
+        if self.last_frame.is_some() {
+            self.clear_screen();
+        }
+    }
+
+    /// Sets how pixels are drawn, and resizes the terminal to fit
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.render_mode = render_mode;
+        let display_mode = self.display_mode;
+        self.setup_terminal(&display_mode).unwrap();
+    }
+
+    /// Sets the title of the terminal window
+    pub fn set_title(&mut self, title: &str) {
+        let _ = self.out.queue(terminal::SetTitle(title));
+        let _ = self.out.flush();
     }
 
     /// Configures the display. Resizes terminal, disables blinking, sets cursor, etc.
     fn setup_terminal(&mut self, display_mode: &DisplayMode) -> Result<()> {
-        terminal::enable_raw_mode().unwrap();
-        self.stdout
-            .queue(terminal::SetSize(
-                display_mode.get_h_res() as u16,
-                display_mode.get_v_res() as u16,
-            ))?
+        self.display_mode = *display_mode;
+        self.last_frame = None;
+        let (columns, rows) = self.render_mode.terminal_size(display_mode);
+        if self.owns_terminal {
+            terminal::enable_raw_mode()?;
+        }
+        self.out
+            .queue(terminal::SetSize(columns as u16, rows as u16))?
             .queue(cursor::DisableBlinking)?
             .queue(cursor::Hide)?
             .queue(terminal::Clear(terminal::ClearType::All))?
             .queue(cursor::MoveTo(0, 0))?;
 
-        self.stdout.flush();
+        self.out.flush()?;
         Ok(())
     }
+
+    /// Undoes setup_terminal. Clears the screen, shows the cursor and disables raw mode.
+    fn restore_terminal(&mut self) -> Result<()> {
+        self.out
+            .queue(terminal::Clear(terminal::ClearType::All))?
+            .queue(cursor::MoveTo(0, 0))?
+            .queue(cursor::EnableBlinking)?
+            .queue(cursor::Show)?;
+        self.out.flush()?;
+        if self.owns_terminal {
+            terminal::disable_raw_mode()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_changed_cells_into_runs() {
+        let previous = vec![vec![0u8; 64]; 32];
+        let mut current = previous.clone();
+        assert!(changed_runs(&previous, &current).is_empty());
+
+        // Two changes with a short gap share a run, a long gap splits them
+        current[1][2] = 1;
+        current[1][5] = 2;
+        current[1][20] = 1;
+        assert_eq!(
+            changed_runs(&previous, &current),
+            vec![
+                Run {
+                    x: 2,
+                    y: 1,
+                    cells: vec![1, 0, 0, 2]
+                },
+                Run {
+                    x: 20,
+                    y: 1,
+                    cells: vec![1]
+                },
+            ]
+        );
+
+        // Pixels turning off are changes too
+        let runs = changed_runs(&current, &previous);
+        assert_eq!(runs[0].cells, vec![0, 0, 0, 0]);
+    }
+
+    /// Returns a frame with the given pixels lit
+    fn frame(mode: DisplayMode, pixels: &[(i32, i32)]) -> DisplayBuffer {
+        let mut buffer = DisplayBuffer::new(mode);
+        for &(x, y) in pixels {
+            buffer.set_pixel(x, y);
+        }
+        buffer
+    }
+
+    /// Returns the number of cursor moves in the escape output
+    fn cursor_moves(out: &[u8]) -> usize {
+        let text = String::from_utf8_lossy(out);
+        text.split("\x1b[")
+            .skip(1)
+            .filter(|sequence| {
+                let end = sequence.find(|c: char| c.is_ascii_alphabetic());
+                end.is_some_and(|end| &sequence[end..=end] == "H")
+            })
+            .count()
+    }
+
+    #[test]
+    fn writes_short_gaps_in_one_run() {
+        let mode = DisplayMode::H64V32MONOCHROME;
+        let mut display = CrosstermDisplay::with_writer(Vec::new(), &mode);
+        display.draw(&frame(mode, &[(2, 1), (5, 1)]));
+        assert_eq!(cursor_moves(&display.out), 1);
+        assert!(String::from_utf8_lossy(&display.out).contains("\x1b[2;3H"));
+
+        let mut display = CrosstermDisplay::with_writer(Vec::new(), &mode);
+        display.draw(&frame(mode, &[(2, 1), (20, 1)]));
+        assert_eq!(cursor_moves(&display.out), 2);
+        let text = String::from_utf8_lossy(&display.out);
+        assert!(text.contains("\x1b[2;3H") && text.contains("\x1b[2;21H"));
+    }
+
+    #[test]
+    fn writes_nothing_for_an_unchanged_frame() {
+        let mode = DisplayMode::H64V32MONOCHROME;
+        let mut display = CrosstermDisplay::with_writer(Vec::new(), &mode);
+        display.draw(&frame(mode, &[(2, 1)]));
+        assert!(!display.out.is_empty());
+
+        display.out.clear();
+        display.draw(&frame(mode, &[(2, 1)]));
+        assert!(display.out.is_empty());
+    }
+
+    #[test]
+    fn repaints_everything_after_a_resolution_change() {
+        let lores = DisplayMode::H64V32MONOCHROME;
+        let hires = DisplayMode::H128V64MONOCHROME;
+        let mut display = CrosstermDisplay::with_writer(Vec::new(), &lores);
+        display.draw(&frame(lores, &[(2, 1)]));
+
+        // The same lit pixel is drawn again on the resized, cleared terminal
+        display.out.clear();
+        display.draw(&frame(hires, &[(2, 1)]));
+        let text = String::from_utf8_lossy(&display.out);
+        assert!(text.contains("\x1b[8;64;128t"));
+        assert!(text.contains("\x1b[2J"));
+        let repaint = &text[text.find("\x1b[2J").unwrap()..];
+        assert!(repaint.contains("\x1b[2;3H"));
+        assert!(repaint.ends_with("*\x1b[39m"));
+    }
 }
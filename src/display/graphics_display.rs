@@ -0,0 +1,457 @@
+//! Bitmap display for terminals that show inline images.
+//!
+//! Each frame is drawn as one image in the top left corner of the terminal, using either of the
+//! two common terminal graphics protocols:
+//!
+//! ```text
+//! Protocol   Encoding                                      Terminals
+//! sixel      DEC sixel bands, 6 pixel rows per band,       xterm -ti vt340, mlterm, foot,
+//!            run-length encoded per colour                 WezTerm, Windows Terminal
+//! kitty      Raw RGB pixels in base64 chunks of 4096       kitty, WezTerm, Ghostty
+//! ```
+//!
+//! Pixels are scaled by a whole number, so they stay square and sharp.
+
+use crate::display::crossterm_display::DEFAULT_PIXEL_COLORS;
+use crate::display::persistence::Afterglow;
+use crate::display::render_mode::{faded_pixel, pixel_shade};
+use crate::display::theme::{mix, rgb, Theme};
+use crate::display::{Display, DisplayBuffer, DisplayMode};
+use crossterm::style::Color;
+use crossterm::{cursor, terminal, QueueableCommand, Result};
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Default number of image pixels per CHIP-8 pixel, in each direction
+pub const DEFAULT_SCALE: u32 = 8;
+
+/// Bytes of base64 sent in each kitty graphics escape sequence, the most the protocol allows
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Terminal graphics protocol images are sent with
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GraphicsProtocol {
+    Sixel,
+    Kitty,
+}
+
+impl GraphicsProtocol {
+    /// Returns the protocol the terminal running the interpreter likely supports, if any.
+    ///
+    /// This is a heuristic on the environment variables of known terminals, not a query of the
+    /// terminal: it can pick a protocol the terminal does not support (such as under tmux or
+    /// screen, or with TERM set by hand), or miss one it does. Users can force text output with
+    /// `--graphics text`, or a protocol with `--graphics sixel` or `--graphics kitty`.
+    pub fn detect() -> Option<GraphicsProtocol> {
+        GraphicsProtocol::from_environment(|name| env::var(name).ok())
+    }
+
+    /// Guesses the protocol from the environment variables terminals set, without asking the
+    /// terminal. var returns the value of a variable.
+    fn from_environment<F: Fn(&str) -> Option<String>>(var: F) -> Option<GraphicsProtocol> {
+        let term = var("TERM").unwrap_or_default();
+        let program = var("TERM_PROGRAM").unwrap_or_default();
+        if var("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || term.contains("ghostty")
+            || program == "WezTerm"
+            || program == "ghostty"
+        {
+            Some(GraphicsProtocol::Kitty)
+        } else if term.contains("sixel")
+            || term.starts_with("mlterm")
+            || term.starts_with("foot")
+            || var("WT_SESSION").is_some()
+        {
+            Some(GraphicsProtocol::Sixel)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the name of this GraphicsProtocol, as accepted by from_str
+    pub fn name(&self) -> &'static str {
+        match *self {
+            GraphicsProtocol::Sixel => "sixel",
+            GraphicsProtocol::Kitty => "kitty",
+        }
+    }
+}
+
+impl fmt::Display for GraphicsProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for GraphicsProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<GraphicsProtocol, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "sixel" => Ok(GraphicsProtocol::Sixel),
+            "kitty" => Ok(GraphicsProtocol::Kitty),
+            _ => Err(format!("Unknown graphics protocol '{}'", s)),
+        }
+    }
+}
+
+/// Chip-8 Display interface object that draws frames as inline images. Writes escape sequences to
+/// any writer, standard output when it runs the terminal.
+pub struct GraphicsDisplay<W: Write> {
+    /// Where escape sequences are written
+    out: W,
+    protocol: GraphicsProtocol,
+    /// Image pixels per CHIP-8 pixel, in each direction
+    scale: u32,
+    /// Colours of ON pixels, indexed by colour index - 1, as in CrosstermDisplay
+    pixel_colors: [[u8; 3]; 3],
+    /// Colour of pixels that are OFF
+    background: [u8; 3],
+    /// True if the display put the terminal in raw mode, and must restore it when dropped
+    owns_terminal: bool,
+    /// Last frame drawn. None when the screen is blank.
+    last_frame: Option<Frame>,
+}
+
+/// Resolution and pixel values of a frame, as returned by render_mode::faded_pixel for pixels that
+/// are fading
+type Frame = (DisplayMode, [[u8; 128]; 64]);
+
+impl GraphicsDisplay<io::Stdout> {
+    /// Constructs a GraphicsDisplay on the terminal. RAII, formats the terminal upon construction
+    /// and restores it when dropped.
+    pub fn new(protocol: GraphicsProtocol, scale: u32) -> GraphicsDisplay<io::Stdout> {
+        let mut new = GraphicsDisplay::with_writer(io::stdout(), protocol, scale);
+        new.owns_terminal = true;
+        // TODO: Actually handle failure to setup terminal
+        new.setup_terminal().unwrap();
+        new
+    }
+}
+
+impl<W: Write> GraphicsDisplay<W> {
+    /// Constructs a GraphicsDisplay that writes to out, leaving the terminal as it is
+    pub fn with_writer(out: W, protocol: GraphicsProtocol, scale: u32) -> GraphicsDisplay<W> {
+        GraphicsDisplay {
+            out,
+            protocol,
+            scale: scale.max(1),
+            pixel_colors: [
+                rgb(DEFAULT_PIXEL_COLORS[0]),
+                rgb(DEFAULT_PIXEL_COLORS[1]),
+                rgb(DEFAULT_PIXEL_COLORS[2]),
+            ],
+            background: [0, 0, 0],
+            owns_terminal: false,
+            last_frame: None,
+        }
+    }
+
+    /// Sets the colors of pixels ON in the first plane, the second plane, and both planes
+    pub fn set_plane_colors(&mut self, colors: [Color; 3]) {
+        self.pixel_colors = [rgb(colors[0]), rgb(colors[1]), rgb(colors[2])];
+        self.last_frame = None;
+    }
+
+    /// Sets the colours of pixels OFF and ON to those of a theme. Images are always in 24 bit
+    /// colour, so the theme is used as it is.
+    pub fn set_theme(&mut self, theme: &Theme) {
+        self.set_plane_colors(theme.pixel_colors);
+        self.background = rgb(theme.background);
+    }
+
+    /// Sets the title of the terminal window
+    pub fn set_title(&mut self, title: &str) {
+        let _ = self.out.queue(terminal::SetTitle(title));
+        let _ = self.out.flush();
+    }
+
+    /// Returns the pixel value of each image pixel, row by row, with the image width and height
+    fn scaled_pixels(&self, frame: &Frame) -> (usize, usize, Vec<u8>) {
+        let (mode, values) = frame;
+        let scale = self.scale as usize;
+        let (width, height) = (
+            mode.get_h_res() as usize * scale,
+            mode.get_v_res() as usize * scale,
+        );
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| values[y / scale][x / scale]))
+            .collect();
+        (width, height, pixels)
+    }
+
+    /// Returns the colour of a pixel value
+    fn value_rgb(&self, value: u8) -> [u8; 3] {
+        if value == 0 {
+            return self.background;
+        }
+        let (planes, brightness) = pixel_shade(value);
+        let to_color = |[r, g, b]: [u8; 3]| Color::Rgb { r, g, b };
+        rgb(mix(
+            to_color(self.pixel_colors[planes as usize - 1]),
+            to_color(self.background),
+            brightness,
+        ))
+    }
+
+    /// Returns the sixel image of a frame
+    fn sixel(&self, frame: &Frame) -> String {
+        let (width, height, pixels) = self.scaled_pixels(frame);
+
+        // Raster attributes give square pixels and the image size, so the background is painted
+        // even where no pixel is ON
+        let mut image = format!("\x1bP0;0;0q\"1;1;{};{}", width, height);
+        // One colour register for each pixel value in the frame, background first
+        let mut palette: Vec<u8> = std::iter::once(0).chain(pixels.iter().copied()).collect();
+        palette.sort_unstable();
+        palette.dedup();
+        for (index, &value) in palette.iter().enumerate() {
+            // Sixel colour registers are in percent
+            let [r, g, b] = self.value_rgb(value);
+            let percent = |c: u8| (c as u32 * 100 + 127) / 255;
+            image += &format!("#{};2;{};{};{}", index, percent(r), percent(g), percent(b));
+        }
+
+        for band in (0..height).step_by(6) {
+            let rows = band..(band + 6).min(height);
+            for (index, &color) in palette.iter().enumerate() {
+                let mut columns: Vec<u8> = (0..width)
+                    .map(|x| {
+                        rows.clone()
+                            .filter(|y| pixels[y * width + x] == color)
+                            .fold(0, |bits, y| bits | 1 << (y - band))
+                    })
+                    .collect();
+                // Columns past the last pixel of the colour need not be sent
+                match columns.iter().rposition(|&bits| bits != 0) {
+                    Some(last) => columns.truncate(last + 1),
+                    None => continue,
+                }
+                // Every colour of the band starts over from its left edge
+                image += &format!("#{}", index);
+                for run in columns.chunk_by(|a, b| a == b) {
+                    let sixel = (0x3F + run[0]) as char;
+                    match run.len() {
+                        1..=3 => image.extend(std::iter::repeat_n(sixel, run.len())),
+                        len => image += &format!("!{}{}", len, sixel),
+                    }
+                }
+                image.push('$');
+            }
+            image.push('-');
+        }
+        image + "\x1b\\"
+    }
+
+    /// Returns the kitty graphics escape sequences that show a frame. The image replaces the one
+    /// drawn before it.
+    fn kitty(&self, frame: &Frame) -> String {
+        let (width, height, pixels) = self.scaled_pixels(frame);
+        let rgb_data: Vec<u8> = pixels
+            .iter()
+            .flat_map(|&value| self.value_rgb(value))
+            .collect();
+        let data = base64(&rgb_data);
+
+        let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+        let mut image = String::with_capacity(data.len() + chunks.len() * 16 + 64);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = (i + 1 < chunks.len()) as u8;
+            // Only the first chunk carries the image's keys. q=2 stops the terminal replying,
+            // which would show up as key presses, and C=1 leaves the cursor where it is.
+            if i == 0 {
+                image += &format!(
+                    "\x1b_Ga=T,f=24,s={},v={},i=1,p=1,q=2,C=1,m={};",
+                    width, height, more
+                );
+            } else {
+                image += &format!("\x1b_Gm={};", more);
+            }
+            image += std::str::from_utf8(chunk).unwrap();
+            image += "\x1b\\";
+        }
+        image
+    }
+
+    /// Draws the frame as one image, unless it is the frame already on screen
+    fn draw_frame(&mut self, frame: Frame) {
+        if self.last_frame == Some(frame) {
+            return;
+        }
+        // A lores frame is smaller than a hires one, so the old image must go first
+        if self.last_frame.is_some_and(|(mode, _)| mode != frame.0) {
+            self.clear_screen();
+        }
+
+        let image = match self.protocol {
+            GraphicsProtocol::Sixel => self.sixel(&frame),
+            GraphicsProtocol::Kitty => self.kitty(&frame),
+        };
+        self.out.queue(cursor::MoveTo(0, 0)).unwrap();
+        self.out.write_all(image.as_bytes()).unwrap();
+        self.out.flush().unwrap();
+        self.last_frame = Some(frame);
+    }
+
+    /// Configures the terminal. Enables raw mode, hides the cursor and clears the screen.
+    fn setup_terminal(&mut self) -> Result<()> {
+        terminal::enable_raw_mode()?;
+        self.out
+            .queue(cursor::DisableBlinking)?
+            .queue(cursor::Hide)?
+            .queue(terminal::Clear(terminal::ClearType::All))?
+            .queue(cursor::MoveTo(0, 0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Undoes setup_terminal. Removes the image, shows the cursor and disables raw mode.
+    fn restore_terminal(&mut self) -> Result<()> {
+        self.clear_screen();
+        self.out
+            .queue(cursor::MoveTo(0, 0))?
+            .queue(cursor::EnableBlinking)?
+            .queue(cursor::Show)?;
+        self.out.flush()?;
+        terminal::disable_raw_mode()
+    }
+}
+
+impl<W: Write> Display for GraphicsDisplay<W> {
+    fn draw(&mut self, display_buffer: &DisplayBuffer) {
+        let frame = (
+            display_buffer.get_display_mode(),
+            *display_buffer.get_buffer(),
+        );
+        self.draw_frame(frame);
+    }
+
+    /// Draws fading pixels in colours between their ON colour and the background
+    fn draw_with_afterglow(&mut self, display_buffer: &DisplayBuffer, afterglow: &Afterglow) {
+        let mut values = *display_buffer.get_buffer();
+        for (y, row) in values.iter_mut().enumerate() {
+            for (x, value) in row.iter_mut().enumerate().filter(|(_, value)| **value == 0) {
+                if let Some((color, brightness)) = afterglow.fading(x as i32, y as i32) {
+                    *value = faded_pixel(color, brightness);
+                }
+            }
+        }
+        self.draw_frame((display_buffer.get_display_mode(), values));
+    }
+
+    fn clear_screen(&mut self) {
+        self.last_frame = None;
+        if self.protocol == GraphicsProtocol::Kitty {
+            // Kitty images are not text, so clearing the screen does not remove them
+            self.out.write_all(b"\x1b_Ga=d,q=2\x1b\\").unwrap();
+        }
+        self.out
+            .queue(terminal::Clear(terminal::ClearType::All))
+            .unwrap();
+        self.out.flush().unwrap();
+    }
+
+    fn hide(&mut self) {
+        self.clear_screen();
+    }
+}
+
+impl<W: Write> Drop for GraphicsDisplay<W> {
+    /// Returns the terminal to its normal state, if the display set it up
+    fn drop(&mut self) {
+        if self.owns_terminal {
+            let _ = self.restore_terminal();
+        }
+    }
+}
+
+/// Encodes data as standard base64, with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> DisplayBuffer {
+        let mut buffer = DisplayBuffer::new(DisplayMode::H64V32MONOCHROME);
+        buffer.set_pixel(0, 0);
+        buffer.set_pixel(1, 0);
+        buffer
+    }
+
+    #[test]
+    fn writes_sixel_images() {
+        let mut out = Vec::new();
+        let mut display = GraphicsDisplay::with_writer(&mut out, GraphicsProtocol::Sixel, 2);
+        display.draw(&frame());
+        // The same frame again is not redrawn
+        display.draw(&frame());
+        drop(display);
+
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.matches("\x1bP").count(), 1);
+        assert!(text.contains("\"1;1;128;64#0;2;0;0;0#1;2;0;100;0"));
+        // First band: 4 columns of the top 2 rows in colour 1, the rest of the band background
+        assert!(text.contains("#0!4{!124~$#1!4B$-"));
+        assert_eq!(text.matches('-').count(), 11);
+        assert!(text.ends_with("-\x1b\\"));
+    }
+
+    #[test]
+    fn writes_kitty_images_in_chunks() {
+        let mut out = Vec::new();
+        let mut display = GraphicsDisplay::with_writer(&mut out, GraphicsProtocol::Kitty, 1);
+        display.draw(&frame());
+        drop(display);
+
+        let text = String::from_utf8(out).unwrap();
+        // 64x32 RGB pixels are 6144 bytes, 8192 bytes of base64: two chunks
+        assert!(text.contains("\x1b_Ga=T,f=24,s=64,v=32,i=1,p=1,q=2,C=1,m=1;AP8AAP8AAAAA"));
+        assert!(text.contains("\x1b_Gm=0;"));
+        assert_eq!(text.matches("\x1b_G").count(), 2);
+        assert_eq!(base64(b"hello"), "aGVsbG8=");
+    }
+
+    #[test]
+    fn detects_protocols_from_the_environment() {
+        let detect = |vars: &[(&str, &str)]| {
+            GraphicsProtocol::from_environment(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+        assert_eq!(
+            detect(&[("TERM", "xterm-kitty")]),
+            Some(GraphicsProtocol::Kitty)
+        );
+        assert_eq!(
+            detect(&[("TERM", "foot"), ("TERM_PROGRAM", "WezTerm")]),
+            Some(GraphicsProtocol::Kitty)
+        );
+        assert_eq!(detect(&[("TERM", "mlterm")]), Some(GraphicsProtocol::Sixel));
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), None);
+    }
+}
@@ -1,6 +1,12 @@
 pub mod crossterm_display;
+pub mod graphics_display;
+pub mod persistence;
+pub mod render_mode;
+pub mod theme;
 
-#[derive(Copy, Clone)]
+use persistence::Afterglow;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum DisplayMode {
     H64V32MONOCHROME,
     H128V64MONOCHROME,
@@ -32,41 +38,196 @@ pub trait Display {
     fn clear_screen(&mut self);
     /// Hides the display
     fn hide(&mut self);
+
+    /// Draws the display buffer, with the pixels that are fading in the afterglow but OFF in the
+    /// buffer drawn dimmed. Displays that cannot show shades draw them ON.
+    fn draw_with_afterglow(&mut self, display_buffer: &DisplayBuffer, afterglow: &Afterglow) {
+        self.draw(&afterglow.merged(display_buffer));
+    }
+
+    /// Returns true if the display changes from frame to frame on its own, and must be drawn on
+    /// every frame even when the display buffer has not changed
+    fn needs_redraw(&self) -> bool {
+        false
+    }
+
+    /// Called once at the end of every emulated 60hz frame, before the frame is drawn if it is.
+    /// Displays that change over time advance here, so they keep time with the program however
+    /// often they are drawn.
+    fn end_frame(&mut self, _display_buffer: &DisplayBuffer) {}
+}
+
+/// Lets the concrete Display be chosen at runtime
+impl<D: Display + ?Sized> Display for Box<D> {
+    fn draw(&mut self, display_buffer: &DisplayBuffer) {
+        (**self).draw(display_buffer)
+    }
+
+    fn clear_screen(&mut self) {
+        (**self).clear_screen()
+    }
+
+    fn hide(&mut self) {
+        (**self).hide()
+    }
+
+    fn draw_with_afterglow(&mut self, display_buffer: &DisplayBuffer, afterglow: &Afterglow) {
+        (**self).draw_with_afterglow(display_buffer, afterglow)
+    }
+
+    fn needs_redraw(&self) -> bool {
+        (**self).needs_redraw()
+    }
+
+    fn end_frame(&mut self, display_buffer: &DisplayBuffer) {
+        (**self).end_frame(display_buffer)
+    }
 }
 
+/// Number of bitplanes in a DisplayBuffer. Standard CHIP-8 programs only use the first plane.
+pub const NUMBER_OF_PLANES: u8 = 2;
+
 /// Wrapper for Display buffer memory. Includes helper methods for working with display memory.
 #[derive(Copy, Clone)]
 pub struct DisplayBuffer {
     /// 2D array that matches size of the screen resolution.
     /// First dimension (ie. [buff[y]] ) is the row (vertical dimension)
     /// Second dimension (ie. [buff[y][x] ) is the column (horizontal dimension)
-    buff: [[bool; 128]; 64],
+    /// Each value is a bitmask of the planes the pixel is ON in. Bit 0 is the first plane, bit 1
+    /// the second (XO-CHIP).
+    buff: [[u8; 128]; 64],
 
     /// The DisplayMode of this DisplayBuffer
     display_mode: DisplayMode,
+
+    /// Bitmask of the planes that drawing, clearing and scrolling operate on
+    selected_planes: u8,
 }
 
 impl DisplayBuffer {
     /// Constructs a new DisplayBuffer
     pub fn new(mode: DisplayMode) -> DisplayBuffer {
         DisplayBuffer {
-            buff: [[false; 128]; 64],
+            buff: [[0; 128]; 64],
             display_mode: mode,
+            selected_planes: 1,
         }
     }
 
+    /// Constructs a DisplayBuffer from previously saved contents, as returned by get_buffer.
+    /// Plane bits outside NUMBER_OF_PLANES are dropped.
+    pub fn from_parts(
+        mode: DisplayMode,
+        selected_planes: u8,
+        buff: [[u8; 128]; 64],
+    ) -> DisplayBuffer {
+        let mut display_buffer = DisplayBuffer::new(mode);
+        display_buffer.select_planes(selected_planes);
+        let mask = (1 << NUMBER_OF_PLANES) - 1;
+        for (row, saved) in display_buffer.buff.iter_mut().zip(buff.iter()) {
+            for (pixel, value) in row.iter_mut().zip(saved.iter()) {
+                *pixel = value & mask;
+            }
+        }
+        display_buffer
+    }
+
+    /// Returns the raw pixel storage. Each value is the bitmask of planes the pixel is ON in.
+    /// Only the top left region covered by the DisplayMode is visible.
+    pub fn get_buffer(&self) -> &[[u8; 128]; 64] {
+        &self.buff
+    }
+
+    /// Returns the DisplayMode of this DisplayBuffer
     pub fn get_display_mode(&self) -> DisplayMode {
-        return self.display_mode;
+        self.display_mode
+    }
+
+    /// Switches the DisplayBuffer to a new DisplayMode. All planes are cleared.
+    pub fn set_display_mode(&mut self, mode: DisplayMode) {
+        self.display_mode = mode;
+        self.buff = [[0; 128]; 64];
+    }
+
+    /// Returns the bitmask of the selected planes
+    pub fn get_selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    /// Selects the planes that drawing, clearing and scrolling operate on (XO-CHIP).
+    /// Bit 0 selects the first plane and bit 1 the second. A mask of 0 disables drawing.
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & ((1 << NUMBER_OF_PLANES) - 1);
     }
 
-    /// Clears the display buffer (sets all values to false)
+    /// Clears the selected planes of the display buffer (sets all values to OFF)
     pub fn clear(&mut self) {
-        self.buff = [[false; 128]; 64];
+        let keep = !self.selected_planes;
+        for row in self.buff.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= keep;
+            }
+        }
     }
 
-    /// Sets a pixel on the DisplayBuffer to ON (true). If the pixel being set is already on (true),
-    /// then a collision has occurred. Settings pixels off-screen (outside resolution bounds), will
-    /// cause the pixel coordinates to roll-over.
+    /// Returns true if the pixel at the given coordinates is ON in any plane. Pixels outside the
+    /// resolution bounds are always OFF.
+    pub fn get_pixel(&self, x: i32, y: i32) -> bool {
+        self.get_pixel_color(x, y) != 0
+    }
+
+    /// Returns the colour index (0-3) of the pixel at the given coordinates. The colour index is
+    /// the bitmask of the planes the pixel is ON in. Pixels outside the resolution bounds are 0.
+    pub fn get_pixel_color(&self, x: i32, y: i32) -> u8 {
+        if self.in_bounds(x, y) {
+            self.buff[y as usize][x as usize]
+        } else {
+            0
+        }
+    }
+
+    /// Scrolls the selected planes down by n pixels. Rows scrolled in at the top are blank.
+    pub fn scroll_down(&mut self, n: i32) {
+        self.scroll(0, n);
+    }
+
+    /// Scrolls the selected planes up by n pixels. Rows scrolled in at the bottom are blank.
+    pub fn scroll_up(&mut self, n: i32) {
+        self.scroll(0, -n);
+    }
+
+    /// Scrolls the selected planes right by n pixels. Columns scrolled in on the left are blank.
+    pub fn scroll_right(&mut self, n: i32) {
+        self.scroll(n, 0);
+    }
+
+    /// Scrolls the selected planes left by n pixels. Columns scrolled in on the right are blank.
+    pub fn scroll_left(&mut self, n: i32) {
+        self.scroll(-n, 0);
+    }
+
+    /// Moves the selected planes by (dx, dy) pixels. Pixels moved off-screen are lost, and
+    /// pixels moved in from off-screen are blank. Unselected planes are left untouched.
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let old = self.buff;
+        let (h_res, v_res) = (self.display_mode.get_h_res(), self.display_mode.get_v_res());
+        for y in 0..v_res {
+            for x in 0..h_res {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let src = if self.in_bounds(src_x, src_y) {
+                    old[src_y as usize][src_x as usize]
+                } else {
+                    0
+                };
+                let pixel = &mut self.buff[y as usize][x as usize];
+                *pixel = (*pixel & !self.selected_planes) | (src & self.selected_planes);
+            }
+        }
+    }
+
+    /// Toggles a pixel on the selected planes of the DisplayBuffer. If the pixel being set is
+    /// already on in a selected plane, then a collision has occurred. Settings pixels off-screen
+    /// (outside resolution bounds), will cause the pixel coordinates to roll-over.
     /// Returns true when a collision has occurred.
     pub fn set_pixel(&mut self, x: i32, y: i32) -> bool {
         // Normalize (roll over) pixel values into resolution range
@@ -79,62 +240,66 @@ impl DisplayBuffer {
             y += self.display_mode.get_v_res();
         }
 
-        let pixel: &mut bool = &mut self.buff[y as usize][x as usize];
-        // Pixel is already on
-        return if *pixel {
-            *pixel = false;
-            true
-        }
-        // Pixel is off
-        else {
-            *pixel = true;
-            false
-        };
+        let pixel: &mut u8 = &mut self.buff[y as usize][x as usize];
+        let collision = *pixel & self.selected_planes != 0;
+        *pixel ^= self.selected_planes;
+        collision
     }
 
+    /// Returns true if the pixel at the given coordinates is within the resolution bounds of the
+    /// current DisplayMode.
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.display_mode.get_h_res() && y < self.display_mode.get_v_res()
+    }
+
+    /// XORs an 8-pixel wide sprite onto the DisplayBuffer at the given coordinates. Pixels that
+    /// fall outside the resolution bounds wrap around to the opposite side of the screen.
+    /// Returns true when a collision has occurred.
     pub fn write_sprite(&mut self, x: i32, y: i32, sprite_slice: &[u8]) -> bool {
-        let mut collision = false;
+        let rows = sprite_slice.iter().map(|byte| (*byte as u16) << 8);
+        self.write_rows(x, y, rows, 8, false)
+    }
 
-        for (i, byte) in sprite_slice.iter().enumerate() {
-            let i = i as i32;
+    /// XORs an 8-pixel wide sprite onto the DisplayBuffer at the given coordinates. The starting
+    /// coordinates wrap around, but pixels that fall outside the resolution bounds are clipped.
+    /// Returns true when a collision has occurred.
+    pub fn write_sprite_clipped(&mut self, x: i32, y: i32, sprite_slice: &[u8]) -> bool {
+        let rows = sprite_slice.iter().map(|byte| (*byte as u16) << 8);
+        self.write_rows(x, y, rows, 8, true)
+    }
 
-            if byte & 0x80 != 0 {
-                if self.set_pixel(x, y + i) {
-                    collision = true;
-                }
-            }
-            if byte & 0x40 != 0 {
-                if self.set_pixel(x + 1, y + i) {
-                    collision = true;
-                }
-            }
-            if byte & 0x20 != 0 {
-                if self.set_pixel(x + 2, y + i) {
-                    collision = true;
-                }
-            }
-            if byte & 0x10 != 0 {
-                if self.set_pixel(x + 3, y + i) {
-                    collision = true;
-                }
-            }
-            if byte & 0x08 != 0 {
-                if self.set_pixel(x + 4, y + i) {
-                    collision = true;
-                }
-            }
-            if byte & 0x04 != 0 {
-                if self.set_pixel(x + 5, y + i) {
-                    collision = true;
-                }
-            }
-            if byte & 0x02 != 0 {
-                if self.set_pixel(x + 6, y + i) {
-                    collision = true;
+    /// XORs a 16x16 SUPER-CHIP sprite onto the DisplayBuffer at the given coordinates. Each row
+    /// is two bytes of sprite_slice, most significant byte first. When clip is true, pixels that
+    /// fall outside the resolution bounds are clipped, otherwise they wrap around.
+    /// Returns true when a collision has occurred.
+    pub fn write_large_sprite(&mut self, x: i32, y: i32, sprite_slice: &[u8], clip: bool) -> bool {
+        let rows = sprite_slice
+            .chunks(2)
+            .map(|pair| ((pair[0] as u16) << 8) | *pair.get(1).unwrap_or(&0) as u16);
+        self.write_rows(x, y, rows, 16, clip)
+    }
+
+    /// XORs sprite rows of up to 16 pixels onto the DisplayBuffer. The most significant bit of
+    /// each row is the leftmost pixel.
+    fn write_rows<I: Iterator<Item = u16>>(
+        &mut self,
+        x: i32,
+        y: i32,
+        rows: I,
+        width: i32,
+        clip: bool,
+    ) -> bool {
+        let x = x.rem_euclid(self.display_mode.get_h_res());
+        let y = y.rem_euclid(self.display_mode.get_v_res());
+        let mut collision = false;
+
+        for (i, row) in rows.enumerate() {
+            for bit in 0..width {
+                let (px, py) = (x + bit, y + i as i32);
+                if row & (0x8000 >> bit) == 0 || (clip && !self.in_bounds(px, py)) {
+                    continue;
                 }
-            }
-            if byte & 0x01 != 0 {
-                if self.set_pixel(x + 7, y + i) {
+                if self.set_pixel(px, py) {
                     collision = true;
                 }
             }
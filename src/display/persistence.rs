@@ -0,0 +1,295 @@
+//! Persistence filter, which hides the flicker of sprites erased and redrawn with XOR.
+//!
+//! CHIP-8 programs move a sprite by drawing it again to erase it, then drawing it at its new
+//! position, so moving sprites are missing from some of the frames. The filter sits in front of
+//! any Display and keeps pixels on screen for a while after they are turned OFF:
+//!
+//! ```text
+//! Mode       A pixel turned OFF is drawn
+//! decay:N    fading towards the background over N frames. Displays that cannot show shades
+//!            draw it ON for the N frames.
+//! blend      ON for one more frame, so each frame is the OR of the last two
+//! ```
+//!
+//! Frames are emulated 60hz frames, counted by Display::end_frame, not the frames drawn.
+
+use crate::display::{Display, DisplayBuffer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Frames a pixel takes to fade out when no number is given
+pub const DEFAULT_DECAY_FRAMES: u8 = 4;
+
+/// Most frames a pixel can take to fade out, one second
+const MAX_DECAY_FRAMES: u8 = 60;
+
+/// How pixels turned OFF stay on screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Persistence {
+    /// Pixels disappear as soon as they are turned OFF
+    Off,
+    /// Pixels fade out over the number of frames
+    Decay(u8),
+    /// Pixels stay ON for one more frame
+    Blend,
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Persistence::Off => write!(f, "off"),
+            Persistence::Decay(frames) => write!(f, "decay:{}", frames),
+            Persistence::Blend => write!(f, "blend"),
+        }
+    }
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Persistence, String> {
+        let lowercase = s.to_ascii_lowercase();
+        let (mode, frames) = match lowercase.split_once(':') {
+            Some((mode, frames)) => (mode, Some(frames)),
+            None => (lowercase.as_str(), None),
+        };
+        match (mode, frames) {
+            ("off", None) => Ok(Persistence::Off),
+            ("blend", None) => Ok(Persistence::Blend),
+            ("decay", None) => Ok(Persistence::Decay(DEFAULT_DECAY_FRAMES)),
+            ("decay", Some(frames)) => match frames.parse() {
+                Ok(frames @ 1..=MAX_DECAY_FRAMES) => Ok(Persistence::Decay(frames)),
+                _ => Err(format!(
+                    "Expected decay frames from 1 to {}, found '{}'",
+                    MAX_DECAY_FRAMES, frames
+                )),
+            },
+            _ => Err(format!(
+                "Unknown persistence '{}', expected off, blend or decay:<frames>",
+                s
+            )),
+        }
+    }
+}
+
+/// Pixels that were turned OFF recently, and how far they have faded
+#[derive(Clone)]
+pub struct Afterglow {
+    /// Plane bitmask each fading pixel had while it was ON, 0 for pixels that are not fading
+    colors: [[u8; 128]; 64],
+    /// Frames since each fading pixel was turned OFF, from 1
+    ages: [[u8; 128]; 64],
+    /// Frames pixels take to fade out
+    frames: u8,
+    /// Last frame, the pixels that start fading when they are OFF in the next one
+    previous: Option<DisplayBuffer>,
+}
+
+impl Afterglow {
+    /// Constructs an Afterglow in which pixels fade out over frames frames
+    pub fn new(frames: u8) -> Afterglow {
+        Afterglow {
+            colors: [[0; 128]; 64],
+            ages: [[0; 128]; 64],
+            frames: frames.max(1),
+            previous: None,
+        }
+    }
+
+    /// Advances one frame, to the display buffer at the end of the frame
+    pub fn update(&mut self, display_buffer: &DisplayBuffer) {
+        // Nothing carries over a change of resolution, as the buffer is cleared
+        let mode = display_buffer.get_display_mode();
+        if self.previous.map(|previous| previous.get_display_mode()) != Some(mode) {
+            self.clear();
+        }
+
+        let current = display_buffer.get_buffer();
+        let previous = self.previous.map(|previous| *previous.get_buffer());
+        for y in 0..mode.get_v_res() as usize {
+            for x in 0..mode.get_h_res() as usize {
+                let was_on = previous.map_or(0, |previous| previous[y][x]);
+                if current[y][x] != 0 {
+                    self.colors[y][x] = 0;
+                } else if was_on != 0 {
+                    self.colors[y][x] = was_on;
+                    self.ages[y][x] = 1;
+                } else if self.colors[y][x] != 0 {
+                    self.ages[y][x] += 1;
+                    if self.ages[y][x] > self.frames {
+                        self.colors[y][x] = 0;
+                    }
+                }
+            }
+        }
+        self.previous = Some(*display_buffer);
+    }
+
+    /// Forgets every pixel, as after the screen is cleared
+    pub fn clear(&mut self) {
+        self.colors = [[0; 128]; 64];
+        self.previous = None;
+    }
+
+    /// Returns true while any pixel is fading, so the next frame differs even if the display
+    /// buffer does not change
+    pub fn is_fading(&self) -> bool {
+        self.colors.iter().flatten().any(|&color| color != 0)
+    }
+
+    /// Returns the plane bitmask and brightness, from 0 to 1, of a pixel that is fading
+    pub fn fading(&self, x: i32, y: i32) -> Option<(u8, f32)> {
+        let color = *self.colors.get(y as usize)?.get(x as usize)?;
+        if color == 0 {
+            return None;
+        }
+        let age = self.ages[y as usize][x as usize];
+        let brightness = (self.frames + 1 - age) as f32 / (self.frames + 1) as f32;
+        Some((color, brightness))
+    }
+
+    /// Returns the display buffer with the fading pixels ON, for displays that cannot show shades
+    pub fn merged(&self, display_buffer: &DisplayBuffer) -> DisplayBuffer {
+        let mut buff = *display_buffer.get_buffer();
+        for (row, colors) in buff.iter_mut().zip(self.colors.iter()) {
+            for (pixel, color) in row.iter_mut().zip(colors.iter()) {
+                *pixel |= color;
+            }
+        }
+        DisplayBuffer::from_parts(
+            display_buffer.get_display_mode(),
+            display_buffer.get_selected_planes(),
+            buff,
+        )
+    }
+}
+
+/// Display that applies a Persistence to the frames drawn on another Display
+pub struct PersistenceFilter<D: Display> {
+    /// Display the filtered frames are drawn on
+    pub display: D,
+    persistence: Persistence,
+    afterglow: Afterglow,
+    /// True if the afterglow changed in the last frame, so it must be drawn again
+    changed: bool,
+}
+
+impl<D: Display> PersistenceFilter<D> {
+    /// Constructs a PersistenceFilter in front of display
+    pub fn new(display: D, persistence: Persistence) -> PersistenceFilter<D> {
+        let frames = match persistence {
+            Persistence::Decay(frames) => frames,
+            _ => 1,
+        };
+        PersistenceFilter {
+            display,
+            persistence,
+            afterglow: Afterglow::new(frames),
+            changed: false,
+        }
+    }
+}
+
+impl<D: Display> Display for PersistenceFilter<D> {
+    fn draw(&mut self, display_buffer: &DisplayBuffer) {
+        match self.persistence {
+            Persistence::Off => self.display.draw(display_buffer),
+            Persistence::Decay(_) => self
+                .display
+                .draw_with_afterglow(display_buffer, &self.afterglow),
+            // A pixel that lasts one frame is the OR of the last two frames
+            Persistence::Blend => self.display.draw(&self.afterglow.merged(display_buffer)),
+        }
+    }
+
+    /// Ages the fading pixels. A frame in which pixels were fading is drawn again, including the
+    /// one in which the last of them fades out.
+    fn end_frame(&mut self, display_buffer: &DisplayBuffer) {
+        if self.persistence != Persistence::Off {
+            let was_fading = self.afterglow.is_fading();
+            self.afterglow.update(display_buffer);
+            self.changed = was_fading || self.afterglow.is_fading();
+        }
+        self.display.end_frame(display_buffer);
+    }
+
+    fn needs_redraw(&self) -> bool {
+        self.changed || self.display.needs_redraw()
+    }
+
+    fn clear_screen(&mut self) {
+        self.afterglow.clear();
+        self.changed = false;
+        self.display.clear_screen();
+    }
+
+    fn hide(&mut self) {
+        self.display.hide();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DisplayMode;
+
+    /// Display that keeps the pixel at 0, 0 of every frame drawn
+    struct PixelDisplay {
+        pixels: Vec<u8>,
+    }
+
+    impl Display for PixelDisplay {
+        fn draw(&mut self, display_buffer: &DisplayBuffer) {
+            self.pixels.push(display_buffer.get_pixel_color(0, 0));
+        }
+        fn clear_screen(&mut self) {}
+        fn hide(&mut self) {}
+    }
+
+    /// Ends a frame the way Emulator::run_frame does, drawing it if it drew or needs redrawing
+    fn run_frame<D: Display>(filter: &mut PersistenceFilter<D>, frame: &DisplayBuffer, drew: bool) {
+        filter.end_frame(frame);
+        if drew || filter.needs_redraw() {
+            filter.draw(frame);
+        }
+    }
+
+    #[test]
+    fn keeps_pixels_turned_off() {
+        let on = {
+            let mut buffer = DisplayBuffer::new(DisplayMode::H64V32MONOCHROME);
+            buffer.set_pixel(0, 0);
+            buffer
+        };
+        let off = DisplayBuffer::new(DisplayMode::H64V32MONOCHROME);
+
+        let mut blend = PersistenceFilter::new(PixelDisplay { pixels: vec![] }, Persistence::Blend);
+        run_frame(&mut blend, &on, true);
+        run_frame(&mut blend, &off, true);
+        run_frame(&mut blend, &off, false);
+        run_frame(&mut blend, &off, false);
+        assert_eq!(blend.display.pixels, vec![1, 1, 0]);
+        assert!(!blend.needs_redraw());
+
+        // Displays that cannot show shades draw fading pixels ON until they have faded
+        let mut decay =
+            PersistenceFilter::new(PixelDisplay { pixels: vec![] }, "decay:2".parse().unwrap());
+        run_frame(&mut decay, &on, true);
+        run_frame(&mut decay, &off, true);
+        assert_eq!(decay.afterglow.fading(0, 0), Some((1, 2.0 / 3.0)));
+        // Pixels fade by emulated frames, not by draws
+        decay.draw(&off);
+        decay.draw(&off);
+        assert_eq!(decay.afterglow.fading(0, 0), Some((1, 2.0 / 3.0)));
+        while decay.needs_redraw() {
+            run_frame(&mut decay, &off, false);
+        }
+        assert_eq!(decay.display.pixels, vec![1, 1, 1, 1, 1, 0]);
+
+        assert!("decay:0".parse::<Persistence>().is_err());
+        assert_eq!(
+            "Decay".parse(),
+            Ok(Persistence::Decay(DEFAULT_DECAY_FRAMES))
+        );
+    }
+}
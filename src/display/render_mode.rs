@@ -0,0 +1,285 @@
+//! Mapping of CHIP-8 pixels onto character cells of a text terminal.
+//!
+//! Terminal cells are about twice as tall as they are wide, so drawing one character per pixel
+//! stretches the image vertically. The other modes pack pixels into cells to restore the aspect
+//! ratio, or to fit a 128x64 screen into a small terminal:
+//!
+//! ```text
+//! Mode          Pixels per cell   Terminal size for 64x32   for 128x64
+//! character     1x1               64x32                     128x64
+//! double-width  1x1 in 2 cells    128x32                    256x64
+//! half-block    1x2 (▀ ▄ █)       64x16                     128x32
+//! braille       2x4 (⣿)           32x8                      64x16
+//! ```
+
+use crate::display::persistence::Afterglow;
+use crate::display::{DisplayBuffer, DisplayMode};
+use std::fmt;
+use std::str::FromStr;
+
+/// How CHIP-8 pixels are drawn with terminal characters
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RenderMode {
+    /// One character per pixel
+    Character,
+    /// Two full blocks per pixel, so pixels are square
+    DoubleWidth,
+    /// Upper and lower half blocks, two pixel rows per cell
+    HalfBlock,
+    /// Braille patterns, 2x4 pixels per cell
+    Braille,
+}
+
+/// Steps a fading pixel dims by before it is OFF
+pub const FADE_STEPS: u8 = 63;
+
+/// Returns the pixel value of a fading pixel of the planes at a brightness from 0 to 1. The low
+/// 2 bits of a pixel value are the plane bitmask, and the rest the steps the pixel has dimmed by.
+pub fn faded_pixel(color: u8, brightness: f32) -> u8 {
+    let dimmed = ((1.0 - brightness) * FADE_STEPS as f32)
+        .round()
+        .clamp(1.0, FADE_STEPS as f32) as u8;
+    (color & 3) | dimmed << 2
+}
+
+/// Returns the plane bitmask and brightness, from 0 to 1, of a pixel value
+pub fn pixel_shade(value: u8) -> (u8, f32) {
+    (value & 3, 1.0 - (value >> 2) as f32 / FADE_STEPS as f32)
+}
+
+/// A terminal character cell. Colours are the pixel values of the pixels they show, 0 for the
+/// terminal's own colour.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub foreground: u8,
+    pub background: u8,
+}
+
+impl Cell {
+    /// Cell with no pixels ON
+    pub const BLANK: Cell = Cell {
+        glyph: ' ',
+        foreground: 0,
+        background: 0,
+    };
+
+    fn lit(glyph: char, foreground: u8) -> Cell {
+        Cell {
+            glyph,
+            foreground,
+            background: 0,
+        }
+    }
+}
+
+impl RenderMode {
+    /// Returns the number of terminal columns and rows needed to draw a DisplayMode
+    pub fn terminal_size(&self, display_mode: &DisplayMode) -> (usize, usize) {
+        let (width, height) = (
+            display_mode.get_h_res() as usize,
+            display_mode.get_v_res() as usize,
+        );
+        match *self {
+            RenderMode::Character => (width, height),
+            RenderMode::DoubleWidth => (width * 2, height),
+            RenderMode::HalfBlock => (width, height.div_ceil(2)),
+            RenderMode::Braille => (width.div_ceil(2), height.div_ceil(4)),
+        }
+    }
+
+    /// Returns the terminal cells that draw the display buffer, as rows of cells.
+    /// term_char is the character drawn for ON pixels in Character mode.
+    pub fn cells(&self, display_buffer: &DisplayBuffer, term_char: char) -> Vec<Vec<Cell>> {
+        self.cells_of(display_buffer.get_display_mode(), term_char, |x, y| {
+            display_buffer.get_pixel_color(x as i32, y as i32)
+        })
+    }
+
+    /// Returns the terminal cells that draw the display buffer, with the pixels of the afterglow
+    /// that are OFF in the buffer as fading pixels
+    pub fn cells_with_afterglow(
+        &self,
+        display_buffer: &DisplayBuffer,
+        afterglow: &Afterglow,
+        term_char: char,
+    ) -> Vec<Vec<Cell>> {
+        self.cells_of(
+            display_buffer.get_display_mode(),
+            term_char,
+            |x, y| match display_buffer.get_pixel_color(x as i32, y as i32) {
+                0 => afterglow
+                    .fading(x as i32, y as i32)
+                    .map_or(0, |(color, brightness)| faded_pixel(color, brightness)),
+                color => color,
+            },
+        )
+    }
+
+    /// Returns the terminal cells that draw the pixel values of a screen of the DisplayMode
+    fn cells_of<F: Fn(usize, usize) -> u8>(
+        &self,
+        mode: DisplayMode,
+        term_char: char,
+        pixel: F,
+    ) -> Vec<Vec<Cell>> {
+        let (columns, rows) = self.terminal_size(&mode);
+
+        (0..rows)
+            .map(|row| {
+                (0..columns)
+                    .map(|column| match *self {
+                        RenderMode::Character => match pixel(column, row) {
+                            0 => Cell::BLANK,
+                            color => Cell::lit(term_char, color),
+                        },
+                        RenderMode::DoubleWidth => match pixel(column / 2, row) {
+                            0 => Cell::BLANK,
+                            color => Cell::lit('█', color),
+                        },
+                        RenderMode::HalfBlock => {
+                            half_block(pixel(column, row * 2), pixel(column, row * 2 + 1))
+                        }
+                        RenderMode::Braille => {
+                            let dots = BRAILLE_DOTS
+                                .iter()
+                                .map(|&(dx, dy, bit)| (pixel(column * 2 + dx, row * 4 + dy), bit));
+                            braille(dots)
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Returns the name of this RenderMode, as accepted by from_str
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RenderMode::Character => "character",
+            RenderMode::DoubleWidth => "double",
+            RenderMode::HalfBlock => "half",
+            RenderMode::Braille => "braille",
+        }
+    }
+}
+
+/// Offset of each dot of a Braille cell, and its bit in the Unicode pattern
+const BRAILLE_DOTS: [(usize, usize, u32); 8] = [
+    (0, 0, 0x01),
+    (0, 1, 0x02),
+    (0, 2, 0x04),
+    (1, 0, 0x08),
+    (1, 1, 0x10),
+    (1, 2, 0x20),
+    (0, 3, 0x40),
+    (1, 3, 0x80),
+];
+
+/// Returns the half block cell for a pair of pixels stacked vertically. Pixels of different colours
+/// are drawn with the upper half block in the top colour over a background of the bottom colour.
+fn half_block(top: u8, bottom: u8) -> Cell {
+    match (top, bottom) {
+        (0, 0) => Cell::BLANK,
+        (top, 0) => Cell::lit('▀', top),
+        (0, bottom) => Cell::lit('▄', bottom),
+        (top, bottom) if top == bottom => Cell::lit('█', top),
+        (top, bottom) => Cell {
+            glyph: '▀',
+            foreground: top,
+            background: bottom,
+        },
+    }
+}
+
+/// Returns the Braille cell for the pixel value and bit of each of its dots. A cell only has one
+/// colour, so it takes the union of the planes of its brightest pixels.
+fn braille<I: Iterator<Item = (u8, u32)>>(dots: I) -> Cell {
+    let lit: Vec<(u8, u32)> = dots.filter(|(value, _)| *value != 0).collect();
+    let dimmed = match lit.iter().map(|(value, _)| value >> 2).min() {
+        Some(dimmed) => dimmed,
+        None => return Cell::BLANK,
+    };
+    let pattern = lit.iter().fold(0, |pattern, (_, bit)| pattern | bit);
+    let planes = lit
+        .iter()
+        .filter(|(value, _)| value >> 2 == dimmed)
+        .fold(0, |planes, (value, _)| planes | value & 3);
+    Cell::lit(
+        char::from_u32(0x2800 + pattern).unwrap(),
+        planes | dimmed << 2,
+    )
+}
+
+impl fmt::Display for RenderMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RenderMode, String> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "character" | "char" => Ok(RenderMode::Character),
+            "double" | "doublewidth" | "square" => Ok(RenderMode::DoubleWidth),
+            "half" | "halfblock" => Ok(RenderMode::HalfBlock),
+            "braille" => Ok(RenderMode::Braille),
+            _ => Err(format!("Unknown render mode '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_pixels_into_cells() {
+        let mut buffer = DisplayBuffer::new(DisplayMode::H64V32MONOCHROME);
+        buffer.set_pixel(0, 0);
+        buffer.set_pixel(1, 1);
+        buffer.select_planes(2);
+        buffer.set_pixel(1, 3);
+
+        let half = RenderMode::HalfBlock.cells(&buffer, '*');
+        assert_eq!((half[0].len(), half.len()), (64, 16));
+        assert_eq!(half[0][0], Cell::lit('▀', 1));
+        assert_eq!(half[0][1], Cell::lit('▄', 1));
+        assert_eq!(half[1][1], Cell::lit('▄', 2));
+
+        let braille = RenderMode::Braille.cells(&buffer, '*');
+        assert_eq!((braille[0].len(), braille.len()), (32, 8));
+        assert_eq!(braille[0][0], Cell::lit('⢑', 3));
+        assert_eq!(braille[0][1], Cell::BLANK);
+
+        let double = RenderMode::DoubleWidth.cells(&buffer, '*');
+        assert_eq!(
+            &double[0][..3],
+            &[Cell::lit('█', 1), Cell::lit('█', 1), Cell::BLANK]
+        );
+
+        assert_eq!(
+            half_block(1, 2),
+            Cell {
+                glyph: '▀',
+                foreground: 1,
+                background: 2
+            }
+        );
+        assert_eq!("half-block".parse(), Ok(RenderMode::HalfBlock));
+
+        // Pixels turned OFF fade, and a Braille cell takes the colour of its brightest dots
+        let mut afterglow = Afterglow::new(3);
+        afterglow.update(&buffer);
+        buffer.select_planes(3);
+        buffer.clear();
+        buffer.select_planes(1);
+        buffer.set_pixel(0, 0);
+        afterglow.update(&buffer);
+        let braille = RenderMode::Braille.cells_with_afterglow(&buffer, &afterglow, '*');
+        assert_eq!(braille[0][0], Cell::lit('⢑', 1));
+        let half = RenderMode::HalfBlock.cells_with_afterglow(&buffer, &afterglow, '*');
+        assert_eq!(half[1][1], Cell::lit('▄', faded_pixel(2, 0.75)));
+    }
+}
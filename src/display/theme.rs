@@ -0,0 +1,306 @@
+//! Colour themes for the displays, and their conversion to what the terminal can show.
+//!
+//! A theme is a four colour palette: the colour of pixels that are OFF, and of pixels ON in the
+//! first plane, the second plane and both planes (XO-CHIP). Themes are given in 24 bit colour and
+//! degraded to the nearest colours of the 256 or 16 colour palette on terminals without
+//! truecolor:
+//!
+//! ```text
+//! Theme           Off       Plane 1   Plane 2   Both
+//! green           #0a140a   #33ff66   #1a9940   #b3ffcc   Green phosphor monitor
+//! amber           #1a1000   #ffb000   #b36b00   #ffd98a   Amber phosphor monitor
+//! lcd             #9bbc0f   #0f380f   #8bac0f   #306230   Green monochrome LCD
+//! high-contrast   #000000   #ffffff   #ffff00   #00ffff
+//! colorblind      #000000   #e69f00   #56b4e9   #f0e442   Okabe-Ito colours, distinct with
+//!                                                           any colour vision deficiency
+//! ```
+
+use crossterm::style::Color;
+use std::env;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Named colours of the 16 colour palette, in palette order
+const NAMED_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::DarkRed,
+    Color::DarkGreen,
+    Color::DarkYellow,
+    Color::DarkBlue,
+    Color::DarkMagenta,
+    Color::DarkCyan,
+    Color::Grey,
+    Color::DarkGrey,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::White,
+];
+
+/// A palette for the display
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub name: &'static str,
+    /// Colour of pixels that are OFF
+    pub background: Color,
+    /// Colours of pixels ON in the first plane, the second plane, and both planes
+    pub pixel_colors: [Color; 3],
+}
+
+impl Theme {
+    /// Themes built into the interpreter
+    pub const ALL: [Theme; 5] = [
+        Theme::new("green", 0x0a140a, [0x33ff66, 0x1a9940, 0xb3ffcc]),
+        Theme::new("amber", 0x1a1000, [0xffb000, 0xb36b00, 0xffd98a]),
+        Theme::new("lcd", 0x9bbc0f, [0x0f380f, 0x8bac0f, 0x306230]),
+        Theme::new("high-contrast", 0x000000, [0xffffff, 0xffff00, 0x00ffff]),
+        Theme::new("colorblind", 0x000000, [0xe69f00, 0x56b4e9, 0xf0e442]),
+    ];
+
+    const fn new(name: &'static str, background: u32, pixel_colors: [u32; 3]) -> Theme {
+        Theme {
+            name,
+            background: hex(background),
+            pixel_colors: [
+                hex(pixel_colors[0]),
+                hex(pixel_colors[1]),
+                hex(pixel_colors[2]),
+            ],
+        }
+    }
+
+    /// Returns this theme with its colours converted to ones the terminal can show
+    pub fn degraded(&self, depth: ColorDepth) -> Theme {
+        Theme {
+            name: self.name,
+            background: depth.degrade(self.background),
+            pixel_colors: [
+                depth.degrade(self.pixel_colors[0]),
+                depth.degrade(self.pixel_colors[1]),
+                depth.degrade(self.pixel_colors[2]),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Theme, String> {
+        let name = match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "colourblind" | "color-blind" | "colour-blind" => String::from("colorblind"),
+            "highcontrast" => String::from("high-contrast"),
+            name => String::from(name),
+        };
+        Theme::ALL
+            .iter()
+            .find(|theme| theme.name == name)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<&str> = Theme::ALL.iter().map(|theme| theme.name).collect();
+                format!("Unknown theme '{}', expected {}", s, names.join(", "))
+            })
+    }
+}
+
+/// Colours a terminal can show
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorDepth {
+    /// Any 24 bit colour
+    TrueColor,
+    /// xterm's 256 colour palette
+    Ansi256,
+    /// The 16 named colours
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// Returns the colour depth of the terminal running the interpreter
+    pub fn detect() -> ColorDepth {
+        ColorDepth::from_environment(|name| env::var(name).ok())
+    }
+
+    /// Guesses the colour depth from the environment variables terminals set. var returns the
+    /// value of a variable.
+    fn from_environment<F: Fn(&str) -> Option<String>>(var: F) -> ColorDepth {
+        let colorterm = var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if var("TERM").unwrap_or_default().contains("256") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    /// Returns the nearest colour the terminal can show
+    pub fn degrade(&self, color: Color) -> Color {
+        match (*self, color) {
+            (ColorDepth::TrueColor, color) => color,
+            // The 16 system colours differ between terminals, so only the fixed colours of the
+            // cube and grey ramp are matched against
+            (ColorDepth::Ansi256, Color::Rgb { .. }) => Color::AnsiValue(nearest(color, 16..=255)),
+            (ColorDepth::Ansi16, Color::Rgb { .. })
+            | (ColorDepth::Ansi16, Color::AnsiValue(16..=255)) => {
+                NAMED_COLORS[nearest(color, 0..=15) as usize]
+            }
+            (_, color) => color,
+        }
+    }
+
+    /// Returns the name of this ColorDepth, as accepted by from_str
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ColorDepth::TrueColor => "truecolor",
+            ColorDepth::Ansi256 => "256",
+            ColorDepth::Ansi16 => "16",
+        }
+    }
+}
+
+impl fmt::Display for ColorDepth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ColorDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ColorDepth, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "truecolor" | "24bit" => Ok(ColorDepth::TrueColor),
+            "256" => Ok(ColorDepth::Ansi256),
+            "16" => Ok(ColorDepth::Ansi16),
+            _ => Err(format!(
+                "Unknown colour depth '{}', expected truecolor, 256 or 16",
+                s
+            )),
+        }
+    }
+}
+
+const fn hex(rgb: u32) -> Color {
+    Color::Rgb {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+    }
+}
+
+/// Returns the colour brightness of the way from background to color, as 24 bit colour
+pub fn mix(color: Color, background: Color, brightness: f32) -> Color {
+    let ([r, g, b], [br, bg, bb]) = (rgb(color), rgb(background));
+    let channel =
+        |on: u8, off: u8| (off as f32 + (on as f32 - off as f32) * brightness).round() as u8;
+    Color::Rgb {
+        r: channel(r, br),
+        g: channel(g, bg),
+        b: channel(b, bb),
+    }
+}
+
+/// Returns the entry of the 256 colour palette in candidates closest to a colour
+fn nearest(color: Color, candidates: RangeInclusive<u8>) -> u8 {
+    let [r, g, b] = rgb(color);
+    candidates
+        .min_by_key(|&candidate| {
+            let [cr, cg, cb] = ansi_rgb(candidate);
+            let distance = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            distance(r, cr) + distance(g, cg) + distance(b, cb)
+        })
+        .unwrap()
+}
+
+/// Returns the RGB value of a terminal colour. Named colours are given xterm's default values.
+pub fn rgb(color: Color) -> [u8; 3] {
+    match color {
+        Color::Rgb { r, g, b } => [r, g, b],
+        Color::AnsiValue(value) => ansi_rgb(value),
+        Color::Reset => ansi_rgb(0),
+        named => ansi_rgb(NAMED_COLORS.iter().position(|c| *c == named).unwrap() as u8),
+    }
+}
+
+/// Returns the RGB value of an entry of xterm's 256 colour palette
+fn ansi_rgb(value: u8) -> [u8; 3] {
+    const SYSTEM: [[u8; 3]; 16] = [
+        [0, 0, 0],
+        [205, 0, 0],
+        [0, 205, 0],
+        [205, 205, 0],
+        [0, 0, 238],
+        [205, 0, 205],
+        [0, 205, 205],
+        [229, 229, 229],
+        [127, 127, 127],
+        [255, 0, 0],
+        [0, 255, 0],
+        [255, 255, 0],
+        [92, 92, 255],
+        [255, 0, 255],
+        [0, 255, 255],
+        [255, 255, 255],
+    ];
+    match value {
+        0..=15 => SYSTEM[value as usize],
+        // 6x6x6 colour cube
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = value - 16;
+            [level(n / 36), level(n / 6 % 6), level(n % 6)]
+        }
+        // Grey ramp
+        _ => [8 + (value - 232) * 10; 3],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrades_themes_to_the_terminal() {
+        let amber: Theme = "Amber".parse().unwrap();
+        assert_eq!(amber.degraded(ColorDepth::TrueColor), amber);
+        assert_eq!(
+            amber.degraded(ColorDepth::Ansi256).pixel_colors[0],
+            Color::AnsiValue(214)
+        );
+        let high_contrast = "high_contrast".parse::<Theme>().unwrap();
+        assert_eq!(
+            high_contrast.degraded(ColorDepth::Ansi16),
+            Theme {
+                name: "high-contrast",
+                background: Color::Black,
+                pixel_colors: [Color::White, Color::Yellow, Color::Cyan],
+            }
+        );
+        assert_eq!(
+            ColorDepth::Ansi16.degrade(Color::AnsiValue(196)),
+            Color::Red
+        );
+        assert!("sepia".parse::<Theme>().is_err());
+
+        let detect = |colorterm: &str, term: &str| {
+            ColorDepth::from_environment(|name| match name {
+                "COLORTERM" => Some(colorterm.to_string()),
+                "TERM" => Some(term.to_string()),
+                _ => None,
+            })
+        };
+        assert_eq!(detect("truecolor", "xterm"), ColorDepth::TrueColor);
+        assert_eq!(detect("", "xterm-256color"), ColorDepth::Ansi256);
+        assert_eq!(detect("", "linux"), ColorDepth::Ansi16);
+    }
+}
@@ -0,0 +1,228 @@
+//! Frame based driver that ties a CPU to a Display and an Input.
+
+use crate::cpu::CPU;
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::input::Input;
+use crate::instructions::{self, StepOutcome};
+use crate::pacing::FramePacer;
+
+/// Most frames run back to back to catch up after the host stalls
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// Runs a CPU one 60hz frame at a time, presenting to a Display and reading from an Input
+pub struct Emulator<D: Display, I: Input> {
+    /// CPU being run
+    pub cpu: CPU,
+    /// Display the DisplayBuffer is presented on
+    pub display: D,
+    /// Input the Keyboard is read from
+    pub input: I,
+    /// Wall clock pacing between frames
+    pacer: FramePacer,
+}
+
+impl<D: Display, I: Input> Emulator<D, I> {
+    /// Constructs a new Emulator
+    pub fn new(cpu: CPU, display: D, input: I) -> Emulator<D, I> {
+        Emulator {
+            cpu,
+            display,
+            input,
+            pacer: FramePacer::new(MAX_CATCH_UP_FRAMES),
+        }
+    }
+
+    /// Returns the number of instructions executed per frame
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.cpu.clock.instructions_per_frame
+    }
+
+    /// Sets the number of instructions executed per frame, at least 1. Takes effect from the
+    /// current frame, so the speed can be changed while running.
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.cpu.clock.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    /// Reads the Input into the CPU keyboard
+    pub fn update_input(&mut self) {
+        self.input.update(&mut self.cpu.keyboard);
+    }
+
+    /// Executes one frame of instructions, which ticks the timers once, ends the frame on the
+    /// Display, and presents the DisplayBuffer if it changed or the Display needs redrawing. Returns Drew if the DisplayBuffer
+    /// changed, or Exited as soon as the program exits. A frame cut short by the exit is still
+    /// presented if it drew.
+    pub fn run_frame(&mut self) -> Result<StepOutcome, Chip8Error> {
+        let frame = self.cpu.clock.frame;
+        let mut drew = false;
+        let mut exited = false;
+        while self.cpu.clock.frame == frame {
+            match instructions::execute(&mut self.cpu)? {
+                StepOutcome::Exited => {
+                    exited = true;
+                    break;
+                }
+                StepOutcome::Drew => drew = true,
+                _ => {}
+            }
+        }
+        if !exited {
+            self.display.end_frame(&self.cpu.display_buffer);
+        }
+        if drew || self.display.needs_redraw() {
+            self.present();
+        }
+        Ok(if exited {
+            StepOutcome::Exited
+        } else if drew {
+            StepOutcome::Drew
+        } else {
+            StepOutcome::Executed
+        })
+    }
+
+    /// Draws the DisplayBuffer on the Display
+    pub fn present(&mut self) {
+        self.display.draw(&self.cpu.display_buffer);
+    }
+
+    /// Waits until the next 60hz frame is due
+    pub fn wait_for_next_frame(&mut self) {
+        self.pacer.wait_for_next_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::{DisplayBuffer, DisplayMode};
+    use crate::input::Keyboard;
+
+    /// Display that counts draws and frames, and asks to be redrawn while redraw is set
+    struct CountingDisplay {
+        draws: usize,
+        frames: usize,
+        redraw: bool,
+    }
+
+    impl Display for CountingDisplay {
+        fn draw(&mut self, _display_buffer: &DisplayBuffer) {
+            self.draws += 1;
+        }
+        fn needs_redraw(&self) -> bool {
+            self.redraw
+        }
+        fn end_frame(&mut self, _display_buffer: &DisplayBuffer) {
+            self.frames += 1;
+        }
+        fn clear_screen(&mut self) {}
+        fn hide(&mut self) {}
+    }
+
+    /// Input that presses nothing
+    struct NoInput;
+
+    impl Input for NoInput {
+        fn update(&mut self, keyboard: &mut Keyboard) {
+            keyboard.clear();
+        }
+    }
+
+    /// Returns an Emulator running the program at 0x200 with the display wait quirk off
+    fn emulator(program: &[u8]) -> Emulator<CountingDisplay, NoInput> {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        cpu.mem.mem[0x200..0x200 + program.len()].copy_from_slice(program);
+        cpu.pc_reg = 0x200;
+        cpu.quirks.display_wait = false;
+        let display = CountingDisplay {
+            draws: 0,
+            frames: 0,
+            redraw: false,
+        };
+        Emulator::new(cpu, display, NoInput)
+    }
+
+    #[test]
+    fn runs_configured_instructions_per_frame() {
+        // loop: ADD V0, 1; DRW V1, V1, 1; JP loop
+        let mut emulator = emulator(&[0x70, 0x01, 0xD1, 0x11, 0x12, 0x00]);
+        emulator.cpu.delay_reg = 10;
+
+        emulator.set_instructions_per_frame(6);
+        emulator.run_frame().unwrap();
+        assert_eq!(emulator.cpu.gp_regs[0], 2);
+        assert_eq!(emulator.cpu.delay_reg, 9);
+        assert_eq!(emulator.display.draws, 1);
+
+        emulator.set_instructions_per_frame(30);
+        emulator.run_frame().unwrap();
+        assert_eq!(emulator.cpu.gp_regs[0], 12);
+        assert_eq!(emulator.cpu.delay_reg, 8);
+    }
+
+    #[test]
+    fn speed_changes_take_effect_mid_frame() {
+        // loop: ADD V0, 1; JP loop
+        let mut emulator = emulator(&[0x70, 0x01, 0x12, 0x00]);
+        emulator.set_instructions_per_frame(10);
+        for _ in 0..4 {
+            instructions::execute(&mut emulator.cpu).unwrap();
+        }
+
+        // The frame ends at the new speed, counting the instructions already run in it
+        emulator.set_instructions_per_frame(6);
+        emulator.run_frame().unwrap();
+        assert_eq!(emulator.cpu.clock.frame, 1);
+        assert_eq!(emulator.cpu.gp_regs[0], 3);
+
+        // A speed below the instructions already run ends the frame on the next instruction
+        for _ in 0..4 {
+            instructions::execute(&mut emulator.cpu).unwrap();
+        }
+        emulator.set_instructions_per_frame(2);
+        emulator.run_frame().unwrap();
+        assert_eq!(emulator.cpu.clock.frame, 2);
+        assert_eq!(emulator.cpu.clock.cycle, 0);
+
+        emulator.set_instructions_per_frame(0);
+        assert_eq!(emulator.instructions_per_frame(), 1);
+    }
+
+    #[test]
+    fn stops_the_frame_when_the_program_exits() {
+        // ADD V0, 1; DRW V1, V1, 1; EXIT
+        let mut emulator = emulator(&[0x70, 0x01, 0xD1, 0x11, 0x00, 0xFD]);
+        emulator.set_instructions_per_frame(10);
+        assert_eq!(emulator.run_frame().unwrap(), StepOutcome::Exited);
+        assert_eq!(emulator.cpu.gp_regs[0], 1);
+        assert_eq!(emulator.cpu.pc_reg, 0x204);
+        assert_eq!(emulator.cpu.clock.frame, 0);
+        // The sprite drawn before the exit is still presented
+        assert_eq!(emulator.display.draws, 1);
+    }
+
+    #[test]
+    fn exiting_without_drawing_presents_nothing() {
+        // EXIT
+        let mut emulator = emulator(&[0x00, 0xFD]);
+        assert_eq!(emulator.run_frame().unwrap(), StepOutcome::Exited);
+        assert_eq!(emulator.display.draws, 0);
+        assert_eq!(emulator.display.frames, 0);
+    }
+
+    #[test]
+    fn presents_frames_the_display_needs_redrawn() {
+        // loop: ADD V0, 1; JP loop
+        let mut emulator = emulator(&[0x70, 0x01, 0x12, 0x00]);
+        emulator.run_frame().unwrap();
+        assert_eq!(emulator.display.draws, 0);
+
+        emulator.display.redraw = true;
+        for _ in 0..3 {
+            assert_eq!(emulator.run_frame().unwrap(), StepOutcome::Executed);
+        }
+        assert_eq!(emulator.display.draws, 3);
+        assert_eq!(emulator.display.frames, 4);
+    }
+}
@@ -0,0 +1,52 @@
+use std::error::Error;
+use std::fmt;
+
+/// The kind of fault that stopped the interpreter
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FaultKind {
+    /// The opcode does not decode to any supported instruction
+    UnknownOpcode,
+    /// A 2nnn call was made with every stack level in use
+    StackOverflow,
+    /// A 00EE return was made with an empty stack
+    StackUnderflow,
+    /// An instruction read or wrote memory past the end of the address space
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FaultKind::UnknownOpcode => write!(f, "unknown opcode"),
+            FaultKind::StackOverflow => write!(f, "stack overflow"),
+            FaultKind::StackUnderflow => write!(f, "stack underflow"),
+            FaultKind::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#05X}", addr)
+            }
+        }
+    }
+}
+
+/// Error raised when the interpreter cannot execute an instruction. Carries the address and raw
+/// opcode of the faulting instruction along with the kind of fault.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Chip8Error {
+    /// Address of the faulting instruction
+    pub pc: u16,
+    /// Raw 16-bit opcode of the faulting instruction
+    pub opcode: u16,
+    /// What went wrong
+    pub kind: FaultKind,
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (opcode {:04X} at {:#05X})",
+            self.kind, self.opcode, self.pc
+        )
+    }
+}
+
+impl Error for Chip8Error {}
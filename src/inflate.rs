@@ -0,0 +1,323 @@
+//! DEFLATE decompressor (RFC 1951), for reading compressed entries of zip archives.
+
+/// Base lengths of length codes 257-285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Extra bits of length codes 257-285
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances of distance codes 0-29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Extra bits of distance codes 0-29
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order the code length code lengths are stored in, in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses a raw DEFLATE stream. Stops with an error once the output is larger than
+/// max_size bytes.
+pub fn inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+    let mut input = BitReader {
+        data,
+        position: 0,
+        bit: 0,
+    };
+    let mut output = Vec::new();
+
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => stored_block(&mut input, &mut output)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                compressed_block(&mut input, &mut output, max_size, &literals, &distances)?
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut input)?;
+                compressed_block(&mut input, &mut output, max_size, &literals, &distances)?
+            }
+            _ => return Err(String::from("invalid block type")),
+        }
+        if output.len() > max_size {
+            return Err(too_large(max_size));
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+fn too_large(max_size: usize) -> String {
+    format!("decompressed data is larger than {} bytes", max_size)
+}
+
+/// Reads bits least significant first, as DEFLATE packs them
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self
+                .data
+                .get(self.position)
+                .ok_or_else(|| String::from("unexpected end of compressed data"))?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+    }
+}
+
+/// Canonical Huffman code, stored as the number of codes of each length and the symbols in code
+/// order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(String::from("invalid Huffman code"))
+    }
+}
+
+fn stored_block(input: &mut BitReader, output: &mut Vec<u8>) -> Result<(), String> {
+    input.align();
+    let header = input
+        .data
+        .get(input.position..input.position + 4)
+        .ok_or_else(|| String::from("unexpected end of compressed data"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(String::from("stored block length is corrupt"));
+    }
+    let start = input.position + 4;
+    let block = input
+        .data
+        .get(start..start + len as usize)
+        .ok_or_else(|| String::from("unexpected end of compressed data"))?;
+    output.extend_from_slice(block);
+    input.position = start + len as usize;
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = input.bits(5)? as usize + 257;
+    let distance_count = input.bits(5)? as usize + 1;
+    let code_length_count = input.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = input.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_length_code.decode(input)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| String::from("repeat with no previous length"))?;
+                (previous, 3 + input.bits(2)?)
+            }
+            17 => (0, 3 + input.bits(3)?),
+            _ => (0, 11 + input.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(String::from("code lengths overflow the header counts"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn compressed_block(
+    input: &mut BitReader,
+    output: &mut Vec<u8>,
+    max_size: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        // A single block can expand without end, so the size is checked as it grows
+        if output.len() > max_size {
+            return Err(too_large(max_size));
+        }
+        match literals.decode(input)? {
+            literal @ 0..=255 => output.push(literal as u8),
+            256 => return Ok(()),
+            symbol => {
+                let code = (symbol - 257) as usize;
+                if code >= LENGTH_BASE.len() {
+                    return Err(String::from("invalid length code"));
+                }
+                let length = LENGTH_BASE[code] as usize + input.bits(LENGTH_EXTRA[code])? as usize;
+
+                let code = distances.decode(input)? as usize;
+                if code >= DISTANCE_BASE.len() {
+                    return Err(String::from("invalid distance code"));
+                }
+                let distance =
+                    DISTANCE_BASE[code] as usize + input.bits(DISTANCE_EXTRA[code])? as usize;
+                if distance > output.len() {
+                    return Err(String::from(
+                        "distance reaches before the start of the data",
+                    ));
+                }
+
+                // Copies byte by byte, as the match may overlap the bytes it produces
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dynamic Huffman block from zlib, 5000 zero bytes
+    const ZEROS: [u8; 22] = [
+        0xED, 0xC1, 0x31, 0x01, 0x00, 0x00, 0x00, 0xC2, 0xA0, 0xF5, 0x4F, 0x6D, 0x0A, 0x3F, 0xA0,
+        0x00, 0x00, 0x00, 0x00, 0x80, 0xB7, 0x01,
+    ];
+
+    #[test]
+    fn inflates_stored_blocks() {
+        assert_eq!(
+            inflate(&[0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'], 3).unwrap(),
+            b"abc"
+        );
+    }
+
+    #[test]
+    fn inflates_fixed_huffman_blocks() {
+        // From zlib, "hello hello hello hello"
+        assert_eq!(
+            inflate(
+                &[0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01],
+                100
+            )
+            .unwrap(),
+            b"hello hello hello hello"
+        );
+    }
+
+    #[test]
+    fn inflates_dynamic_huffman_blocks() {
+        // From zlib, a string with too few repeats for the fixed codes to pay
+        let data = [
+            0x0D, 0xC7, 0x31, 0x0D, 0x00, 0x00, 0x00, 0xC2, 0x30, 0x6D, 0x0B, 0xC7, 0x0C, 0xE0,
+            0x5F, 0x0B, 0x7C, 0xAD, 0x18, 0x89, 0x42, 0x8B, 0x34, 0x78, 0x7C, 0x3A,
+        ];
+        assert_eq!(
+            inflate(&data, 100).unwrap(),
+            b"GAGCGACGGAATTAGATCAGTTAAATGG"
+        );
+        assert_eq!(inflate(&ZEROS, 5000).unwrap(), vec![0; 5000]);
+    }
+
+    #[test]
+    fn rejects_corrupt_and_oversized_data() {
+        // Reserved block type
+        assert!(inflate(&[0x07], 100).is_err());
+        // Stored block length that does not match its complement
+        assert!(inflate(&[0x01, 0x03, 0x00, 0xFC, 0xFE, b'a', b'b', b'c'], 100).is_err());
+        // Truncated stream
+        assert!(inflate(&ZEROS[..10], 5000).is_err());
+        // Distance before the start of the output, in a fixed block
+        assert!(inflate(&[0x03, 0x02], 100).is_err());
+        assert_eq!(
+            inflate(&ZEROS, 4096).unwrap_err(),
+            "decompressed data is larger than 4096 bytes"
+        );
+    }
+}
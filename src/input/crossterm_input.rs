@@ -1,21 +1,37 @@
-use crossterm::{
-    cursor,
-    event::{poll, read, Event, KeyCode, KeyEvent},
-    style::{self, Colorize},
-    terminal, ExecutableCommand, QueueableCommand, Result,
-};
+use crossterm::event::{poll, read, Event, KeyCode};
 use std::time::Duration;
 
-use crate::input::{ChipKeys, Input, Keyboard};
+use crate::input::{Input, Keyboard};
+
+/// Default keymap. Maps the left hand side of a QWERTY keyboard onto the 4x4 CHIP-8 keypad:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// q w e r      4 5 6 D
+/// a s d f  ->  7 8 9 E
+/// z x c v      A 0 B F
+/// ```
+pub const DEFAULT_KEYMAP: [char; 16] = [
+    'x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v',
+];
 
 pub struct CrosstermInput {
     pub poll_timeout_millis: u64,
+    /// Terminal character for each CHIP-8 key, indexed by the key's hex value
+    pub keymap: [char; 16],
 }
 
 impl CrosstermInput {
     pub fn new(poll_timeout_millis: u64) -> CrosstermInput {
+        CrosstermInput::with_keymap(poll_timeout_millis, DEFAULT_KEYMAP)
+    }
+
+    /// Constructs a new CrosstermInput with a custom keymap. keymap[n] is the terminal character
+    /// for CHIP-8 key n.
+    pub fn with_keymap(poll_timeout_millis: u64, keymap: [char; 16]) -> CrosstermInput {
         CrosstermInput {
             poll_timeout_millis,
+            keymap,
         }
     }
 }
@@ -26,63 +42,26 @@ impl Input for CrosstermInput {
         keyboard.clear();
 
         if poll(Duration::from_millis(self.poll_timeout_millis)).unwrap() {
-            return match read().unwrap() {
-                Event::Key(event) => match event.code {
+            if let Event::Key(event) = read().unwrap() {
+                match event.code {
                     KeyCode::Esc => {
                         keyboard.esc = true;
                     }
-                    KeyCode::Char('1') => {
-                        keyboard.key_1 = true;
-                    }
-                    KeyCode::Char('2') => {
-                        keyboard.key_2 = true;
-                    }
-                    KeyCode::Char('3') => {
-                        keyboard.key_3 = true;
-                    }
-                    KeyCode::Char('4') => {
-                        keyboard.key_c = true;
-                    }
-                    KeyCode::Char('q') => {
-                        keyboard.key_4 = true;
-                    }
-                    KeyCode::Char('w') => {
-                        keyboard.key_5 = true;
-                    }
-                    KeyCode::Char('e') => {
-                        keyboard.key_6 = true;
-                    }
-                    KeyCode::Char('r') => {
-                        keyboard.key_d = true;
-                    }
-                    KeyCode::Char('a') => {
-                        keyboard.key_7 = true;
-                    }
-                    KeyCode::Char('s') => {
-                        keyboard.key_8 = true;
-                    }
-                    KeyCode::Char('d') => {
-                        keyboard.key_9 = true;
-                    }
-                    KeyCode::Char('f') => {
-                        keyboard.key_e = true;
-                    }
-                    KeyCode::Char('z') => {
-                        keyboard.key_a = true;
-                    }
-                    KeyCode::Char('x') => {
-                        keyboard.key_0 = true;
-                    }
-                    KeyCode::Char('c') => {
-                        keyboard.key_b = true;
+                    KeyCode::Backspace => {
+                        keyboard.rewind = true;
                     }
-                    KeyCode::Char('v') => {
-                        keyboard.key_f = true;
+                    KeyCode::Char(c) => {
+                        if let Some(key) = self.keymap.iter().position(|mapped| *mapped == c) {
+                            keyboard.set_pressed(key as u8, true);
+                        } else if c == '+' || c == '=' {
+                            keyboard.speed_up = true;
+                        } else if c == '-' {
+                            keyboard.speed_down = true;
+                        }
                     }
                     _ => {}
-                },
-                _ => {}
-            };
+                }
+            }
         }
     }
 }
@@ -32,7 +32,7 @@ pub enum ChipKeys {
 
 impl ChipKeys {
     pub fn to_hex(&self) -> u8 {
-        return match self {
+        match self {
             ChipKeys::Key0 => 0x0,
             ChipKeys::Key1 => 0x1,
             ChipKeys::Key2 => 0x2,
@@ -50,7 +50,7 @@ impl ChipKeys {
             ChipKeys::KeyE => 0xE,
             ChipKeys::KeyF => 0xF,
             ChipKeys::ESC => 0x0,
-        };
+        }
     }
 }
 
@@ -74,6 +74,12 @@ pub struct Keyboard {
     pub key_e: bool,
     pub key_f: bool,
     pub esc: bool,
+    /// Held to rewind play. Not a CHIP-8 key, so it is never reported to the program.
+    pub rewind: bool,
+    /// Pressed to run more instructions per frame
+    pub speed_up: bool,
+    /// Pressed to run fewer instructions per frame
+    pub speed_down: bool,
 }
 
 impl Keyboard {
@@ -103,6 +109,9 @@ impl Keyboard {
         self.key_e = false;
         self.key_f = false;
         self.esc = false;
+        self.rewind = false;
+        self.speed_up = false;
+        self.speed_down = false;
     }
 
     /// Returns a Vector of ChipKeys that contains all active (pressed) keys
@@ -163,7 +172,7 @@ impl Keyboard {
     }
 
     pub fn is_pressed(&self, hex_key: u8) -> bool {
-        return match hex_key {
+        match hex_key {
             0x0 => self.key_0,
             0x1 => self.key_1,
             0x2 => self.key_2,
@@ -181,6 +190,42 @@ impl Keyboard {
             0xE => self.key_e,
             0xF => self.key_f,
             _ => false,
-        };
+        }
+    }
+
+    /// Sets the pressed state of the CHIP-8 key with the given hex value. Other values are ignored.
+    pub fn set_pressed(&mut self, hex_key: u8, pressed: bool) {
+        match hex_key {
+            0x0 => self.key_0 = pressed,
+            0x1 => self.key_1 = pressed,
+            0x2 => self.key_2 = pressed,
+            0x3 => self.key_3 = pressed,
+            0x4 => self.key_4 = pressed,
+            0x5 => self.key_5 = pressed,
+            0x6 => self.key_6 = pressed,
+            0x7 => self.key_7 = pressed,
+            0x8 => self.key_8 = pressed,
+            0x9 => self.key_9 = pressed,
+            0xA => self.key_a = pressed,
+            0xB => self.key_b = pressed,
+            0xC => self.key_c = pressed,
+            0xD => self.key_d = pressed,
+            0xE => self.key_e = pressed,
+            0xF => self.key_f = pressed,
+            _ => {}
+        }
+    }
+
+    /// Returns the CHIP-8 keys as a bitmask, bit n set when key n is pressed. System keys are not
+    /// included.
+    pub fn key_mask(&self) -> u16 {
+        (0..16).fold(0, |mask, key| mask | ((self.is_pressed(key) as u16) << key))
+    }
+
+    /// Sets the CHIP-8 keys from a bitmask produced by key_mask. System keys are left unchanged.
+    pub fn set_key_mask(&mut self, mask: u16) {
+        for key in 0..16 {
+            self.set_pressed(key, mask & (1 << key) != 0);
+        }
     }
 }
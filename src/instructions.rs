@@ -1,106 +1,210 @@
+//! Documentation pulled from CowGod's CHIP-8 Reference page
+//! http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+//!
+//! In these listings, the following variables are used:
+//!
+//! nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
+//! n or nibble - A 4-bit value, the lowest 4 bits of the instruction
+//! x - A 4-bit value, the lower 4 bits of the high byte of the instruction
+//! y - A 4-bit value, the upper 4 bits of the low byte of the instruction
+//! kk or byte - An 8-bit value, the lowest 8 bits of the instruction
+
 use crate::cpu::CPU;
+use crate::display::{DisplayMode, NUMBER_OF_PLANES};
+use crate::error::{Chip8Error, FaultKind};
+use crate::memory::LARGE_FONT_ADDR;
+use crate::opcode::{decode, Instruction};
 
-use rand::prelude::*;
-use std::borrow::Borrow;
+/// Index of VF in the general purpose registers. VF doubles as the carry, borrow and collision
+/// flag.
+pub const VF: usize = 0xF;
 
-/// Documentation pulled from CowGod's CHIP-8 Reference page
-/// http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
-///
+/// Result of successfully executing a single instruction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StepOutcome {
+    /// The instruction executed with no effect visible outside the CPU
+    Executed,
+    /// The instruction changed the DisplayBuffer
+    Drew,
+    /// Execution is halted on Fx0A until a key is pressed
+    WaitingForKey,
+    /// The instruction started or stopped the sound timer
+    SoundChanged,
+    /// The program exited with 00FD. The program counter stays on the exit instruction.
+    Exited,
+}
 
-/// In these listings, the following variables are used:
-//
-// nnn or addr - A 12-bit value, the lowest 12 bits of the instruction
-// n or nibble - A 4-bit value, the lowest 4 bits of the instruction
-// x - A 4-bit value, the lower 4 bits of the high byte of the instruction
-// y - A 4-bit value, the upper 4 bits of the low byte of the instruction
-// kk or byte - An 8-bit value, the lowest 8 bits of the instruction
+/// Fetches, decodes and executes the instruction at the program counter.
+/// Returns what the instruction did, or a Chip8Error describing the fault that stopped it.
+pub fn execute(cpu: &mut CPU) -> Result<StepOutcome, Chip8Error> {
+    // Halt until the next display refresh after a draw (display wait quirk)
+    if cpu.waiting_for_display {
+        cpu.advance_clock();
+        return Ok(StepOutcome::Executed);
+    }
+
+    // Fetch instruction
+    let pc = cpu.pc_reg;
+    if pc as usize + 1 >= cpu.mem.mem.len() {
+        return Err(Chip8Error {
+            pc,
+            opcode: 0,
+            kind: FaultKind::MemoryOutOfBounds {
+                addr: pc as usize + 1,
+            },
+        });
+    }
+    let opcode = ((cpu.mem.mem[pc as usize] as u16) << 8) | cpu.mem.mem[pc as usize + 1] as u16;
+
+    let fault = |kind: FaultKind| Chip8Error { pc, opcode, kind };
 
-/// Helper function to combine three nibbles into a 12-bit value
-pub fn to_nnn(first: &u8, second: &u8, third: &u8) -> u16 {
-    let nnn: u16 = ((*first as u16) << 8) + ((*second as u16) << 4) + *third as u16;
-    nnn
+    // Decode instruction
+    let instruction = decode(opcode).map_err(|_| fault(FaultKind::UnknownOpcode))?;
+
+    // Increment the program counter. It wraps at the end of the 64K address space.
+    cpu.pc_reg = cpu.pc_reg.wrapping_add(2);
+
+    let outcome = execute_instruction(cpu, &instruction).map_err(fault)?;
+    cpu.advance_clock();
+    Ok(outcome)
 }
 
-pub fn to_kk(first: &u8, second: &u8) -> u8 {
-    let kk: u8 = (*first << 4) + *second;
-    kk
+/// Executes instructions until the emulated clock completes the current frame, which also ticks
+/// the timers. Returns Drew if any instruction drew, Exited as soon as the program exits, and
+/// Executed otherwise.
+pub fn run_frame(cpu: &mut CPU) -> Result<StepOutcome, Chip8Error> {
+    let frame = cpu.clock.frame;
+    let mut frame_outcome = StepOutcome::Executed;
+    while cpu.clock.frame == frame {
+        match execute(cpu)? {
+            StepOutcome::Exited => return Ok(StepOutcome::Exited),
+            StepOutcome::Drew => frame_outcome = StepOutcome::Drew,
+            _ => {}
+        }
+    }
+    Ok(frame_outcome)
 }
 
-pub fn execute(cpu: &mut CPU) -> bool {
-    // Fetch instruction
-    // Split the 2-byte instruction into four nibbles
-    let first = (cpu.mem.mem[cpu.pc_reg as usize] & 0xF0) >> 4;
-    let second = cpu.mem.mem[cpu.pc_reg as usize] & 0x0F;
-    let third = (cpu.mem.mem[(cpu.pc_reg + 1) as usize] & 0xF0) >> 4;
-    let fourth = cpu.mem.mem[(cpu.pc_reg + 1) as usize] & 0x0F;
-
-    // Increment the program counter
-    cpu.pc_reg += 2;
-
-    // Decode instruction based on first nibble
-    match first {
-        0x0 => {
-            if third == 0xE {
-                if fourth == 0x0 {
-                    cls(cpu);
-                } else if fourth == 0xE {
-                    ret(cpu);
-                }
-            } else {
-                sys(to_nnn(&second, &third, &fourth));
+/// Executes an already decoded instruction. The program counter must already point past the
+/// instruction's opcode word.
+pub fn execute_instruction(
+    cpu: &mut CPU,
+    instruction: &Instruction,
+) -> Result<StepOutcome, FaultKind> {
+    match *instruction {
+        Instruction::Sys { nnn } => sys(nnn),
+        Instruction::ScrollDown { n } => {
+            scroll_down(cpu, n);
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::ScrollUp { n } => {
+            scroll_up(cpu, n);
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::Cls => {
+            cls(cpu);
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::Ret => ret(cpu)?,
+        Instruction::ScrollRight => {
+            scroll_right(cpu);
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::ScrollLeft => {
+            scroll_left(cpu);
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::Exit => {
+            exit(cpu);
+            return Ok(StepOutcome::Exited);
+        }
+        Instruction::LowRes => {
+            low_res(cpu);
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::HighRes => {
+            high_res(cpu);
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::Jump { nnn } => jump(cpu, nnn),
+        Instruction::Call { nnn } => call(cpu, nnn)?,
+        Instruction::SkipEqual { x, kk } => skip_equal(cpu, x, kk),
+        Instruction::SkipNotEqual { x, kk } => skip_not_equal(cpu, x, kk),
+        Instruction::SkipEqualXY { x, y } => skip_equal_xy(cpu, x, y),
+        Instruction::SaveRange { x, y } => save_range(cpu, x, y)?,
+        Instruction::LoadRange { x, y } => load_range(cpu, x, y)?,
+        Instruction::Load { x, kk } => load(cpu, x, kk),
+        Instruction::Add { x, kk } => add(cpu, x, kk),
+        Instruction::LoadXY { x, y } => load_xy(cpu, x, y),
+        Instruction::Or { x, y } => bitwise_or(cpu, x, y),
+        Instruction::And { x, y } => bitwise_and(cpu, x, y),
+        Instruction::Xor { x, y } => bitwise_xor(cpu, x, y),
+        Instruction::AddXY { x, y } => add_xy(cpu, x, y),
+        Instruction::SubXY { x, y } => sub_xy(cpu, x, y),
+        Instruction::ShiftRight { x, y } => shift_right(cpu, x, y),
+        Instruction::SubYX { x, y } => sub_yx(cpu, x, y),
+        Instruction::ShiftLeft { x, y } => shift_left(cpu, x, y),
+        Instruction::SkipNotEqualXY { x, y } => skip_not_equal_xy(cpu, x, y),
+        Instruction::LoadI { nnn } => load_i(cpu, nnn),
+        Instruction::JumpV0 { nnn } => jump_v0(cpu, nnn),
+        Instruction::Rand { x, kk } => rand(cpu, x, kk),
+        Instruction::Draw { x, y, n } => {
+            draw(cpu, x, y, n)?;
+            return Ok(StepOutcome::Drew);
+        }
+        Instruction::SkipIfKey { x } => skip_if_key(cpu, x),
+        Instruction::SkipNotKey { x } => skip_not_key(cpu, x),
+        Instruction::LongLoadI => long_load_i(cpu)?,
+        Instruction::SelectPlanes { n } => select_planes(cpu, n),
+        Instruction::LoadAudioPattern => {
+            load_audio_pattern(cpu)?;
+            return Ok(StepOutcome::SoundChanged);
+        }
+        Instruction::LoadDelayToVx { x } => load_delay_to_vx(cpu, x),
+        Instruction::WaitForKey { x } => {
+            if !wait_for_key(cpu, x) {
+                return Ok(StepOutcome::WaitingForKey);
             }
         }
-        0x1 => jump(cpu, to_nnn(&second, &third, &fourth)),
-        0x2 => call(cpu, to_nnn(&second, &third, &fourth)),
-        0x3 => skip_equal(cpu, second, to_kk(&third, &fourth)),
-        0x4 => skip_not_equal(cpu, second, to_kk(&third, &fourth)),
-        0x5 => skip_equal_xy(cpu, second, third),
-        0x6 => load(cpu, second, to_kk(&third, &fourth)),
-        0x7 => add(cpu, second, to_kk(&third, &fourth)),
-        0x8 => match fourth {
-            0x0 => load_xy(cpu, second, third),
-            0x1 => bitwise_or(cpu, second, third),
-            0x2 => bitwise_and(cpu, second, third),
-            0x3 => bitwise_xor(cpu, second, third),
-            0x4 => add_xy(cpu, second, third),
-            0x5 => sub_xy(cpu, second, third),
-            0x6 => shift_right(cpu, second), // TODO: Variant of this opcode
-            0x7 => sub_yx(cpu, second, third),
-            0xE => shift_left(cpu, second), // TODO: Variant of this opcode
-            _ => {
-                panic!("Unsupported instruction!");
+        Instruction::LoadDelayTimer { x } => load_delay_timer(cpu, x),
+        Instruction::LoadSoundTimer { x } => {
+            let was_playing = cpu.sound_reg > 0;
+            load_sound_timer(cpu, x);
+            if was_playing != (cpu.sound_reg > 0) {
+                return Ok(StepOutcome::SoundChanged);
             }
-        },
-        0x9 => skip_not_equal_xy(cpu, second, third),
-        0xA => load_i(cpu, to_nnn(&second, &third, &fourth)),
-        0xB => jump_v0(cpu, to_nnn(&second, &third, &fourth)),
-        0xC => rand(cpu, second, to_kk(&third, &fourth)),
-        0xD => {
-            draw(cpu, second, third, fourth);
-            return true;
         }
-        0xE => match to_kk(&third, &fourth) {
-            0x9E => skip_if_key(cpu, second),
-            0xA1 => skip_not_key(cpu, second),
-            _ => panic!("Unsupported Instruction!"),
-        },
-        0xF => match to_kk(&third, &fourth) {
-            0x07 => load_delay_to_vx(cpu, second),
-            0x0A => wait_for_key(cpu, second),
-            0x15 => load_delay_timer(cpu, second),
-            0x18 => load_sound_timer(cpu, second),
-            0x1E => add_i_vx(cpu, second),
-            0x29 => load_ascii_address(cpu, second),
-            0x33 => load_bcd(cpu, second),
-            0x55 => store_regs(cpu, second),
-            0x65 => load_regs(cpu, second),
-            _ => panic!("Unsupported Instruction!"),
-        },
-        _ => {
-            panic!("Unsupported instruction!");
+        Instruction::AddIVx { x } => add_i_vx(cpu, x),
+        Instruction::LoadAsciiAddress { x } => load_ascii_address(cpu, x),
+        Instruction::LoadLargeFontAddress { x } => load_large_font_address(cpu, x),
+        Instruction::LoadBcd { x } => load_bcd(cpu, x)?,
+        Instruction::LoadPitch { x } => {
+            load_pitch(cpu, x);
+            return Ok(StepOutcome::SoundChanged);
         }
+        Instruction::StoreRegs { x } => store_regs(cpu, x)?,
+        Instruction::LoadRegs { x } => load_regs(cpu, x)?,
+        Instruction::StoreRplFlags { x } => store_rpl_flags(cpu, x),
+        Instruction::LoadRplFlags { x } => load_rpl_flags(cpu, x),
+    }
+    Ok(StepOutcome::Executed)
+}
+
+/// Skips over the next instruction. F000 NNNN is four bytes long, so it is skipped in full.
+fn skip_next_instruction(cpu: &mut CPU) {
+    let pc = cpu.pc_reg as usize;
+    let long = pc + 1 < cpu.mem.mem.len() && cpu.mem.mem[pc] == 0xF0 && cpu.mem.mem[pc + 1] == 0x00;
+    cpu.pc_reg = cpu.pc_reg.wrapping_add(if long { 4 } else { 2 });
+}
+
+/// Checks that the memory range [addr, addr + len) lies inside the CPU's memory
+fn check_bounds(cpu: &CPU, addr: usize, len: usize) -> Result<(), FaultKind> {
+    if addr + len > cpu.mem.mem.len() {
+        return Err(FaultKind::MemoryOutOfBounds {
+            addr: addr + len - 1,
+        });
     }
-    false
+    Ok(())
 }
 
 /// 0nnn - SYS addr
@@ -108,8 +212,18 @@ pub fn execute(cpu: &mut CPU) -> bool {
 //
 // This instruction is only used on the old computers on which Chip-8 was originally implemented.
 // It is ignored by modern interpreters.
-pub fn sys(_nnn: u16) {
-    return;
+pub fn sys(_nnn: u16) {}
+
+/// 00Cn - SCD nibble
+/// Scroll the display down by n pixels (SUPER-CHIP).
+pub fn scroll_down(cpu: &mut CPU, n: u8) {
+    cpu.display_buffer.scroll_down(n as i32);
+}
+
+/// 00Dn - SCU nibble
+/// Scroll the display up by n pixels (XO-CHIP).
+pub fn scroll_up(cpu: &mut CPU, n: u8) {
+    cpu.display_buffer.scroll_up(n as i32);
 }
 
 /// 00E0 - CLS
@@ -123,9 +237,50 @@ pub fn cls(cpu: &mut CPU) {
 ///
 /// The interpreter sets the program counter to the address at the top of the stack, then subtracts
 /// 1 from the stack pointer.
-pub fn ret(cpu: &mut CPU) {
-    cpu.pc_reg = cpu.stack[cpu.stack_pointer_reg as usize];
+///
+/// Here the stack pointer counts the addresses on the stack, so it is decremented first. See CALL.
+pub fn ret(cpu: &mut CPU) -> Result<(), FaultKind> {
+    if cpu.stack_pointer_reg == 0 {
+        return Err(FaultKind::StackUnderflow);
+    }
     cpu.stack_pointer_reg -= 1;
+    cpu.pc_reg = cpu.stack[cpu.stack_pointer_reg as usize];
+    Ok(())
+}
+
+/// 00FB - SCR
+/// Scroll the display right by 4 pixels (SUPER-CHIP).
+pub fn scroll_right(cpu: &mut CPU) {
+    cpu.display_buffer.scroll_right(4);
+}
+
+/// 00FC - SCL
+/// Scroll the display left by 4 pixels (SUPER-CHIP).
+pub fn scroll_left(cpu: &mut CPU) {
+    cpu.display_buffer.scroll_left(4);
+}
+
+/// 00FD - EXIT
+/// Exit the interpreter (SUPER-CHIP).
+///
+/// This implementation leaves the program counter on the exit instruction, so that the program
+/// stays stopped if execution continues.
+pub fn exit(cpu: &mut CPU) {
+    cpu.pc_reg = cpu.pc_reg.wrapping_sub(2);
+}
+
+/// 00FE - LOW
+/// Disable high resolution graphics mode, switching to 64x32 (SUPER-CHIP).
+pub fn low_res(cpu: &mut CPU) {
+    cpu.display_buffer
+        .set_display_mode(DisplayMode::H64V32MONOCHROME);
+}
+
+/// 00FF - HIGH
+/// Enable high resolution graphics mode, switching to 128x64 (SUPER-CHIP).
+pub fn high_res(cpu: &mut CPU) {
+    cpu.display_buffer
+        .set_display_mode(DisplayMode::H128V64MONOCHROME);
 }
 
 /// 1nnn - JP addr
@@ -141,10 +296,17 @@ pub fn jump(cpu: &mut CPU, nnn: u16) {
 ///
 /// The interpreter increments the stack pointer, then puts the current PC on the top of the stack.
 /// The PC is then set to nnn.
-pub fn call(cpu: &mut CPU, nnn: u16) {
-    cpu.stack_pointer_reg += 1;
+///
+/// Here the PC is put on the stack before the stack pointer is incremented, so the stack pointer
+/// counts the addresses on the stack and all 16 levels can be used.
+pub fn call(cpu: &mut CPU, nnn: u16) -> Result<(), FaultKind> {
+    if cpu.stack_pointer_reg as usize >= cpu.stack.len() {
+        return Err(FaultKind::StackOverflow);
+    }
     cpu.stack[cpu.stack_pointer_reg as usize] = cpu.pc_reg;
+    cpu.stack_pointer_reg += 1;
     cpu.pc_reg = nnn;
+    Ok(())
 }
 
 /// 3xkk - SE Vx, byte
@@ -154,7 +316,7 @@ pub fn call(cpu: &mut CPU, nnn: u16) {
 /// counter by 2.
 pub fn skip_equal(cpu: &mut CPU, vx: u8, kk: u8) {
     if cpu.gp_regs[vx as usize] == kk {
-        cpu.pc_reg += 2;
+        skip_next_instruction(cpu);
     }
 }
 
@@ -165,7 +327,7 @@ pub fn skip_equal(cpu: &mut CPU, vx: u8, kk: u8) {
 /// counter by 2.
 pub fn skip_not_equal(cpu: &mut CPU, vx: u8, kk: u8) {
     if cpu.gp_regs[vx as usize] != kk {
-        cpu.pc_reg += 2;
+        skip_next_instruction(cpu);
     }
 }
 
@@ -176,7 +338,42 @@ pub fn skip_not_equal(cpu: &mut CPU, vx: u8, kk: u8) {
 /// program counter by 2.
 pub fn skip_equal_xy(cpu: &mut CPU, vx: u8, vy: u8) {
     if cpu.gp_regs[vx as usize] == cpu.gp_regs[vy as usize] {
-        cpu.pc_reg += 2;
+        skip_next_instruction(cpu);
+    }
+}
+
+/// 5xy2 - SAVE Vx - Vy
+/// Store registers Vx through Vy in memory starting at location I (XO-CHIP).
+///
+/// If x is greater than y, the registers are stored in reverse order. I is not modified.
+pub fn save_range(cpu: &mut CPU, vx: u8, vy: u8) -> Result<(), FaultKind> {
+    let count = (vx as i32 - vy as i32).unsigned_abs() as usize + 1;
+    check_bounds(cpu, cpu.i_reg as usize, count)?;
+    for (index, reg) in register_range(vx, vy).enumerate() {
+        cpu.mem.mem[cpu.i_reg as usize + index] = cpu.gp_regs[reg];
+    }
+    Ok(())
+}
+
+/// 5xy3 - LOAD Vx - Vy
+/// Read registers Vx through Vy from memory starting at location I (XO-CHIP).
+///
+/// If x is greater than y, the registers are loaded in reverse order. I is not modified.
+pub fn load_range(cpu: &mut CPU, vx: u8, vy: u8) -> Result<(), FaultKind> {
+    let count = (vx as i32 - vy as i32).unsigned_abs() as usize + 1;
+    check_bounds(cpu, cpu.i_reg as usize, count)?;
+    for (index, reg) in register_range(vx, vy).enumerate() {
+        cpu.gp_regs[reg] = cpu.mem.mem[cpu.i_reg as usize + index];
+    }
+    Ok(())
+}
+
+/// Returns the register indices from x to y inclusive, counting down if x is greater than y
+fn register_range(vx: u8, vy: u8) -> Box<dyn Iterator<Item = usize>> {
+    if vx <= vy {
+        Box::new(vx as usize..=vy as usize)
+    } else {
+        Box::new((vy as usize..=vx as usize).rev())
     }
 }
 
@@ -212,7 +409,10 @@ pub fn load_xy(cpu: &mut CPU, vx: u8, vy: u8) {
 /// compares the corresponding bits from two values, and if either bit is 1, then the same bit in the
 /// result is also 1. Otherwise, it is 0.
 pub fn bitwise_or(cpu: &mut CPU, vx: u8, vy: u8) {
-    cpu.gp_regs[vx as usize] = cpu.gp_regs[vy as usize] | cpu.gp_regs[vx as usize];
+    cpu.gp_regs[vx as usize] |= cpu.gp_regs[vy as usize];
+    if cpu.quirks.logic_resets_vf {
+        cpu.gp_regs[VF] = 0;
+    }
 }
 
 ///8xy2 - AND Vx, Vy
@@ -222,7 +422,10 @@ pub fn bitwise_or(cpu: &mut CPU, vx: u8, vy: u8) {
 /// compares the corresponding bits from two values, and if both bits are 1, then the same bit in the
 /// result is also 1. Otherwise, it is 0.
 pub fn bitwise_and(cpu: &mut CPU, vx: u8, vy: u8) {
-    cpu.gp_regs[vx as usize] = cpu.gp_regs[vy as usize] & cpu.gp_regs[vx as usize];
+    cpu.gp_regs[vx as usize] &= cpu.gp_regs[vy as usize];
+    if cpu.quirks.logic_resets_vf {
+        cpu.gp_regs[VF] = 0;
+    }
 }
 
 /// 8xy3 - XOR Vx, Vy
@@ -232,7 +435,10 @@ pub fn bitwise_and(cpu: &mut CPU, vx: u8, vy: u8) {
 /// An exclusive OR compares the corresponding bits from two values, and if the bits are not both the
 /// same, then the corresponding bit in the result is set to 1. Otherwise, it is 0.
 pub fn bitwise_xor(cpu: &mut CPU, vx: u8, vy: u8) {
-    cpu.gp_regs[vx as usize] = cpu.gp_regs[vy as usize] ^ cpu.gp_regs[vx as usize];
+    cpu.gp_regs[vx as usize] ^= cpu.gp_regs[vy as usize];
+    if cpu.quirks.logic_resets_vf {
+        cpu.gp_regs[VF] = 0;
+    }
 }
 
 /// 8xy4 - ADD Vx, Vy
@@ -240,78 +446,61 @@ pub fn bitwise_xor(cpu: &mut CPU, vx: u8, vy: u8) {
 ///
 /// The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,)
 /// VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
+/// VF is written after Vx, so when x is F the flag replaces the result.
 pub fn add_xy(cpu: &mut CPU, vx: u8, vy: u8) {
-    let mut sum: u16 = cpu.gp_regs[vy as usize] as u16 + cpu.gp_regs[vx as usize] as u16;
-    let carry = sum > 255;
-    if carry {
-        sum -= 256;
-        cpu.vf_reg = 1;
-    } else {
-        cpu.vf_reg = 0;
-    }
-    cpu.gp_regs[vx as usize] = sum as u8;
+    let (sum, carry) = cpu.gp_regs[vx as usize].overflowing_add(cpu.gp_regs[vy as usize]);
+    cpu.gp_regs[vx as usize] = sum;
+    cpu.gp_regs[VF] = carry as u8;
 }
 
 /// 8xy5 - SUB Vx, Vy
-// Set Vx = Vx - Vy, set VF = NOT borrow.
-//
-// If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx,
-// and the results stored in Vx.
+/// Set Vx = Vx - Vy, set VF = NOT borrow.
+///
+/// If Vx >= Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx,
+/// and the results stored in Vx. VF is written after Vx.
 pub fn sub_xy(cpu: &mut CPU, vx: u8, vy: u8) {
-    if cpu.gp_regs[vx as usize] > cpu.gp_regs[vy as usize] {
-        cpu.vf_reg = 1;
-    } else {
-        cpu.vf_reg = 0;
-    }
-    let mut diff = cpu.gp_regs[vx as usize]
-        .overflowing_sub(cpu.gp_regs[vy as usize])
-        .0;
+    let (diff, borrow) = cpu.gp_regs[vx as usize].overflowing_sub(cpu.gp_regs[vy as usize]);
     cpu.gp_regs[vx as usize] = diff;
+    cpu.gp_regs[VF] = !borrow as u8;
 }
 
 /// 8xy6 - SHR Vx {, Vy}
 /// Set Vx = Vx SHR 1.
 ///
 /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0.
-/// Then Vx is divided by 2.
+/// Then Vx is divided by 2. VF is written after Vx.
 ///
-pub fn shift_right(cpu: &mut CPU, vx: u8) {
-    if cpu.gp_regs[vx as usize] & 0x01 > 0 {
-        cpu.vf_reg = 1;
-    } else {
-        cpu.vf_reg = 0;
-    }
-    cpu.gp_regs[vx as usize] = cpu.gp_regs[vx as usize] >> 1;
+/// With the shift_uses_vy quirk, Vy is shifted instead and the result stored in Vx.
+pub fn shift_right(cpu: &mut CPU, vx: u8, vy: u8) {
+    let src = if cpu.quirks.shift_uses_vy { vy } else { vx };
+    let val = cpu.gp_regs[src as usize];
+    cpu.gp_regs[vx as usize] = val >> 1;
+    cpu.gp_regs[VF] = val & 0x01;
 }
 
 /// 8xy7 - SUBN Vx, Vy
 /// Set Vx = Vy - Vx, set VF = NOT borrow.
 ///
-/// If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results
-/// stored in Vx.
+/// If Vy >= Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results
+/// stored in Vx. VF is written after Vx.
 pub fn sub_yx(cpu: &mut CPU, vx: u8, vy: u8) {
-    if cpu.gp_regs[vy as usize] > cpu.gp_regs[vx as usize] {
-        cpu.vf_reg = 1;
-    } else {
-        cpu.vf_reg = 0;
-    }
-    let temp_y = cpu.gp_regs[vy as usize];
-    let mut diff = temp_y.overflowing_sub(cpu.gp_regs[vy as usize]).0;
+    let (diff, borrow) = cpu.gp_regs[vy as usize].overflowing_sub(cpu.gp_regs[vx as usize]);
     cpu.gp_regs[vx as usize] = diff;
+    cpu.gp_regs[VF] = !borrow as u8;
 }
 
 /// 8xyE - SHL Vx {, Vy}
 /// Set Vx = Vx SHL 1.
 ///
 /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0.
-/// Then Vx is multiplied by 2.
-pub fn shift_left(cpu: &mut CPU, vx: u8) {
-    if cpu.gp_regs[vx as usize] & 0x80 > 0 {
-        cpu.vf_reg = 1;
-    } else {
-        cpu.vf_reg = 0;
-    }
-    cpu.gp_regs[vx as usize] = cpu.gp_regs[vx as usize] << 1;
+/// Then Vx is multiplied by 2. VF is written after Vx.
+///
+/// With the shift_uses_vy quirk, Vy is shifted instead and the result stored in Vx.
+pub fn shift_left(cpu: &mut CPU, vx: u8, vy: u8) {
+    let src = if cpu.quirks.shift_uses_vy { vy } else { vx };
+    let val = cpu.gp_regs[src as usize];
+    cpu.gp_regs[vx as usize] = val << 1;
+    cpu.gp_regs[VF] = val >> 7;
 }
 
 /// 9xy0 - SNE Vx, Vy
@@ -321,7 +510,7 @@ pub fn shift_left(cpu: &mut CPU, vx: u8) {
 /// the program counter is increased by 2.
 pub fn skip_not_equal_xy(cpu: &mut CPU, vx: u8, vy: u8) {
     if cpu.gp_regs[vx as usize] != cpu.gp_regs[vy as usize] {
-        cpu.pc_reg += 2;
+        skip_next_instruction(cpu);
     }
 }
 
@@ -337,8 +526,16 @@ pub fn load_i(cpu: &mut CPU, nnn: u16) {
 /// Jump to location nnn + V0.
 ///
 /// The program counter is set to nnn plus the value of V0.
+///
+/// With the jump_uses_vx quirk, the instruction is read as BXnn and the program counter is set to
+/// xnn plus the value of Vx.
 pub fn jump_v0(cpu: &mut CPU, nnn: u16) {
-    cpu.pc_reg += cpu.gp_regs[0] as u16 + nnn;
+    let reg = if cpu.quirks.jump_uses_vx {
+        (nnn >> 8) as usize
+    } else {
+        0
+    };
+    cpu.pc_reg = cpu.gp_regs[reg] as u16 + nnn;
 }
 
 /// Cxkk - RND Vx, byte
@@ -347,7 +544,7 @@ pub fn jump_v0(cpu: &mut CPU, nnn: u16) {
 /// The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk.
 /// The results are stored in Vx. See instruction 8xy2 for more information on AND.
 pub fn rand(cpu: &mut CPU, vx: u8, kk: u8) {
-    cpu.gp_regs[vx as usize] = rand::random::<u8>() & kk;
+    cpu.gp_regs[vx as usize] = cpu.rng.next_u8() & kk;
 }
 
 /// Dxyn - DRW Vx, Vy, nibble
@@ -360,19 +557,51 @@ pub fn rand(cpu: &mut CPU, vx: u8, kk: u8) {
 /// outside the coordinates of the display, it wraps around to the opposite side of the screen.
 /// See instruction 8xy3 for more information on XOR, and section 2.4, Display, for more information
 /// on the Chip-8 screen and sprites.
-pub fn draw(cpu: &mut CPU, vx: u8, vy: u8, n: u8) {
+///
+/// Dxy0 - DRW Vx, Vy, 0
+/// When n is 0, a 16x16 sprite is read from 32 bytes starting at I (SUPER-CHIP).
+///
+/// With the clip_sprites quirk, pixels outside the coordinates of the display are clipped instead
+/// of wrapping. With the display_wait quirk, execution halts until the next display refresh.
+pub fn draw(cpu: &mut CPU, vx: u8, vy: u8, n: u8) -> Result<(), FaultKind> {
     let x_coord = cpu.gp_regs[vx as usize] as i32;
     let y_coord = cpu.gp_regs[vy as usize] as i32;
-    let sprite_addr = cpu.i_reg as usize;
-    let sprite_slice: &[u8] = &cpu.mem.mem[sprite_addr..sprite_addr + n as usize];
-    let collision = cpu
-        .display_buffer
-        .write_sprite(x_coord, y_coord, sprite_slice);
-    if collision {
-        cpu.vf_reg = 1;
-    } else {
-        cpu.vf_reg = 0;
+    let len = if n == 0 { 32 } else { n as usize };
+    let planes = cpu.display_buffer.get_selected_planes();
+    check_bounds(cpu, cpu.i_reg as usize, len * planes.count_ones() as usize)?;
+
+    // Each selected plane is drawn from its own sprite, stored one after another from I
+    let mut sprite_addr = cpu.i_reg as usize;
+    let mut collision = false;
+    for plane in 0..NUMBER_OF_PLANES {
+        let plane_mask = 1 << plane;
+        if planes & plane_mask == 0 {
+            continue;
+        }
+        cpu.display_buffer.select_planes(plane_mask);
+        let sprite_slice: &[u8] = &cpu.mem.mem[sprite_addr..sprite_addr + len];
+        collision |= if n == 0 {
+            cpu.display_buffer.write_large_sprite(
+                x_coord,
+                y_coord,
+                sprite_slice,
+                cpu.quirks.clip_sprites,
+            )
+        } else if cpu.quirks.clip_sprites {
+            cpu.display_buffer
+                .write_sprite_clipped(x_coord, y_coord, sprite_slice)
+        } else {
+            cpu.display_buffer
+                .write_sprite(x_coord, y_coord, sprite_slice)
+        };
+        sprite_addr += len;
+    }
+    cpu.display_buffer.select_planes(planes);
+    cpu.gp_regs[VF] = collision as u8;
+    if cpu.quirks.display_wait {
+        cpu.waiting_for_display = true;
     }
+    Ok(())
 }
 /// Ex9E - SKP Vx
 /// Skip next instruction if key with the value of Vx is pressed.
@@ -381,7 +610,7 @@ pub fn draw(cpu: &mut CPU, vx: u8, vy: u8, n: u8) {
 /// down position, PC is increased by 2.
 pub fn skip_if_key(cpu: &mut CPU, vx: u8) {
     if cpu.keyboard.is_pressed(cpu.gp_regs[vx as usize]) {
-        cpu.pc_reg += 2;
+        skip_next_instruction(cpu);
     }
 }
 
@@ -391,11 +620,42 @@ pub fn skip_if_key(cpu: &mut CPU, vx: u8) {
 /// Checks the keyboard, and if the key corresponding to the value of Vx is currently in the
 /// up position, PC is increased by 2.
 pub fn skip_not_key(cpu: &mut CPU, vx: u8) {
-    if cpu.keyboard.is_pressed(cpu.gp_regs[vx as usize]) == false {
-        cpu.pc_reg += 2;
+    if !cpu.keyboard.is_pressed(cpu.gp_regs[vx as usize]) {
+        skip_next_instruction(cpu);
     }
 }
 
+/// F000 NNNN - LD I, long NNNN
+/// Set I = NNNN (XO-CHIP).
+///
+/// The 16-bit address is read from the two bytes following the instruction, and the program
+/// counter is moved past them.
+pub fn long_load_i(cpu: &mut CPU) -> Result<(), FaultKind> {
+    let pc = cpu.pc_reg as usize;
+    check_bounds(cpu, pc, 2)?;
+    cpu.i_reg = ((cpu.mem.mem[pc] as u16) << 8) | cpu.mem.mem[pc + 1] as u16;
+    cpu.pc_reg = cpu.pc_reg.wrapping_add(2);
+    Ok(())
+}
+
+/// Fn01 - PLANE n
+/// Select the drawing planes given by bitmask n (XO-CHIP).
+///
+/// Drawing, clearing and scrolling only affect the selected planes.
+pub fn select_planes(cpu: &mut CPU, n: u8) {
+    cpu.display_buffer.select_planes(n);
+}
+
+/// F002 - AUDIO
+/// Load the audio pattern buffer from the 16 bytes starting at location I (XO-CHIP).
+pub fn load_audio_pattern(cpu: &mut CPU) -> Result<(), FaultKind> {
+    let addr = cpu.i_reg as usize;
+    check_bounds(cpu, addr, 16)?;
+    cpu.audio_pattern
+        .copy_from_slice(&cpu.mem.mem[addr..addr + 16]);
+    Ok(())
+}
+
 /// Fx07 - LD Vx, DT
 /// Set Vx = delay timer value.
 ///
@@ -408,15 +668,16 @@ pub fn load_delay_to_vx(cpu: &mut CPU, vx: u8) {
 /// Wait for a key press, store the value of the key in Vx.
 ///
 /// All execution stops until a key is pressed, then the value of that key is stored in Vx.
-/// This implementation yields on no key, but does not increment program counter
-pub fn wait_for_key(cpu: &mut CPU, vx: u8) {
-    let mut key: u8 = 0x0;
+/// This implementation yields on no key, but does not increment program counter.
+/// Returns true once a key has been pressed.
+pub fn wait_for_key(cpu: &mut CPU, vx: u8) -> bool {
     let keys = cpu.keyboard.get_active_inputs();
-    if keys.is_empty() == false {
-        key = keys[0].to_hex();
-        cpu.gp_regs[vx as usize] = key;
+    if !keys.is_empty() {
+        cpu.gp_regs[vx as usize] = keys[0].to_hex();
+        true
     } else {
-        cpu.pc_reg -= 2;
+        cpu.pc_reg = cpu.pc_reg.wrapping_sub(2);
+        false
     }
 }
 
@@ -441,7 +702,7 @@ pub fn load_sound_timer(cpu: &mut CPU, vx: u8) {
 ///
 /// The values of I and Vx are added, and the results are stored in I.
 pub fn add_i_vx(cpu: &mut CPU, vx: u8) {
-    cpu.i_reg = cpu.i_reg + cpu.gp_regs[vx as usize] as u16;
+    cpu.i_reg = cpu.i_reg.wrapping_add(cpu.gp_regs[vx as usize] as u16);
 }
 
 /// Fx29 - LD F, Vx
@@ -449,8 +710,19 @@ pub fn add_i_vx(cpu: &mut CPU, vx: u8) {
 ///
 /// The value of I is set to the location for the hexadecimal sprite corresponding to the value of
 /// Vx. See section 2.4, Display, for more information on the Chip-8 hexadecimal font.
+///
+/// Only the low 4 bits of Vx are used, as for Fx30.
 pub fn load_ascii_address(cpu: &mut CPU, vx: u8) {
-    cpu.i_reg = cpu.gp_regs[vx as usize] as u16 * 5;
+    cpu.i_reg = (cpu.gp_regs[vx as usize] & 0xF) as u16 * 5;
+}
+
+/// Fx30 - LD HF, Vx
+/// Set I = location of large sprite for digit Vx (SUPER-CHIP).
+///
+/// The value of I is set to the location for the 8x10 hexadecimal sprite corresponding to the
+/// value of Vx.
+pub fn load_large_font_address(cpu: &mut CPU, vx: u8) {
+    cpu.i_reg = LARGE_FONT_ADDR + (cpu.gp_regs[vx as usize] & 0xF) as u16 * 10;
 }
 
 /// Fx33 - LD B, Vx
@@ -458,11 +730,19 @@ pub fn load_ascii_address(cpu: &mut CPU, vx: u8) {
 ///
 /// The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at
 /// location in I, the tens digit at location I+1, and the ones digit at location I+2.
-pub fn load_bcd(cpu: &mut CPU, vx: u8) {
+pub fn load_bcd(cpu: &mut CPU, vx: u8) -> Result<(), FaultKind> {
+    check_bounds(cpu, cpu.i_reg as usize, 3)?;
     let val = cpu.gp_regs[vx as usize];
     cpu.mem.mem[cpu.i_reg as usize] = val / 100;
     cpu.mem.mem[(cpu.i_reg + 1) as usize] = (val % 100) / 10;
     cpu.mem.mem[(cpu.i_reg + 2) as usize] = val % 10;
+    Ok(())
+}
+
+/// Fx3A - PITCH Vx
+/// Set the pitch register = Vx (XO-CHIP).
+pub fn load_pitch(cpu: &mut CPU, vx: u8) {
+    cpu.pitch_reg = cpu.gp_regs[vx as usize];
 }
 
 /// Fx55 - LD [I], Vx
@@ -470,19 +750,419 @@ pub fn load_bcd(cpu: &mut CPU, vx: u8) {
 ///
 /// The interpreter copies the values of registers V0 through Vx into memory,
 /// starting at the address in I.
-pub fn store_regs(cpu: &mut CPU, vx: u8) {
+///
+/// I is then moved on as the load_store_increments_i quirk says.
+pub fn store_regs(cpu: &mut CPU, vx: u8) -> Result<(), FaultKind> {
+    check_bounds(cpu, cpu.i_reg as usize, vx as usize + 1)?;
     let regs = &cpu.gp_regs[0..=vx as usize];
     for (index, val) in regs.iter().enumerate() {
         cpu.mem.mem[cpu.i_reg as usize + index] = *val;
     }
+    cpu.i_reg = cpu
+        .i_reg
+        .wrapping_add(cpu.quirks.load_store_increments_i.amount(vx));
+    Ok(())
 }
 
 /// Fx65 - LD Vx, [I]
 /// Read registers V0 through Vx from memory starting at location I.
 ///
 /// The interpreter reads values from memory starting at location I into registers V0 through Vx.
-pub fn load_regs(cpu: &mut CPU, vx: u8) {
+///
+/// I is then moved on as the load_store_increments_i quirk says.
+pub fn load_regs(cpu: &mut CPU, vx: u8) -> Result<(), FaultKind> {
+    check_bounds(cpu, cpu.i_reg as usize, vx as usize + 1)?;
     for n in 0..=vx {
         cpu.gp_regs[n as usize] = cpu.mem.mem[cpu.i_reg as usize + n as usize];
     }
+    cpu.i_reg = cpu
+        .i_reg
+        .wrapping_add(cpu.quirks.load_store_increments_i.amount(vx));
+    Ok(())
+}
+
+/// Fx75 - LD R, Vx
+/// Store registers V0 through Vx in the RPL user flags (SUPER-CHIP).
+pub fn store_rpl_flags(cpu: &mut CPU, vx: u8) {
+    let count = vx as usize + 1;
+    cpu.rpl_flags[..count].copy_from_slice(&cpu.gp_regs[..count]);
+}
+
+/// Fx85 - LD Vx, R
+/// Read registers V0 through Vx from the RPL user flags (SUPER-CHIP).
+pub fn load_rpl_flags(cpu: &mut CPU, vx: u8) {
+    let count = vx as usize + 1;
+    cpu.gp_regs[..count].copy_from_slice(&cpu.rpl_flags[..count]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Platform;
+    use crate::quirks::{LoadStoreIncrement, Quirks};
+
+    /// Creates a CPU with the given register values and the opcode loaded at 0x200
+    fn setup(opcode: u16, regs: &[(usize, u8)]) -> CPU {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        cpu.quirks = Quirks::CHIP_48;
+        cpu.mem.mem[0x200] = (opcode >> 8) as u8;
+        cpu.mem.mem[0x201] = opcode as u8;
+        cpu.pc_reg = 0x200;
+        for (reg, val) in regs {
+            cpu.gp_regs[*reg] = *val;
+        }
+        cpu
+    }
+
+    /// Executes a single opcode and returns the resulting CPU
+    fn run(opcode: u16, regs: &[(usize, u8)]) -> CPU {
+        let mut cpu = setup(opcode, regs);
+        execute(&mut cpu).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn faults_stop_with_the_faulting_instruction() {
+        // CALL to itself until the stack is full
+        let mut cpu = setup(0x2200, &[]);
+        let error = loop {
+            if let Err(error) = execute(&mut cpu) {
+                break error;
+            }
+        };
+        assert_eq!(
+            error,
+            Chip8Error {
+                pc: 0x200,
+                opcode: 0x2200,
+                kind: FaultKind::StackOverflow
+            }
+        );
+
+        let mut cpu = setup(0x00EE, &[]);
+        assert_eq!(
+            execute(&mut cpu).unwrap_err().kind,
+            FaultKind::StackUnderflow
+        );
+
+        let mut cpu = setup(0xF033, &[]);
+        cpu.i_reg = 0xFFF;
+        assert_eq!(
+            execute(&mut cpu).unwrap_err().kind,
+            FaultKind::MemoryOutOfBounds { addr: 0x1001 }
+        );
+
+        let mut cpu = setup(0x8008, &[]);
+        assert_eq!(
+            execute(&mut cpu).unwrap_err().kind,
+            FaultKind::UnknownOpcode
+        );
+        assert_eq!(cpu.pc_reg, 0x200);
+    }
+
+    #[test]
+    fn calls_nest_sixteen_deep() {
+        // CALL to itself
+        let mut cpu = setup(0x2200, &[]);
+        for _ in 0..16 {
+            execute(&mut cpu).unwrap();
+        }
+        assert_eq!(cpu.stack_pointer_reg, 16);
+        assert_eq!(
+            execute(&mut cpu).unwrap_err().kind,
+            FaultKind::StackOverflow
+        );
+
+        cpu.mem.mem[0x200..0x202].copy_from_slice(&[0x00, 0xEE]);
+        cpu.pc_reg = 0x200;
+        execute(&mut cpu).unwrap();
+        assert_eq!((cpu.pc_reg, cpu.stack_pointer_reg), (0x202, 15));
+    }
+
+    #[test]
+    fn draw_faults_when_the_sprite_runs_past_memory() {
+        let mut cpu = setup(0xD015, &[]);
+        cpu.i_reg = 0xFFE;
+        assert_eq!(
+            execute(&mut cpu),
+            Err(Chip8Error {
+                pc: 0x200,
+                opcode: 0xD015,
+                kind: FaultKind::MemoryOutOfBounds { addr: 0x1002 }
+            })
+        );
+    }
+
+    #[test]
+    fn load_xy_reads_vf() {
+        let cpu = run(0x80F0, &[(VF, 0x42)]);
+        assert_eq!(cpu.gp_regs[0], 0x42);
+    }
+
+    #[test]
+    fn logic_ops_reset_vf_with_quirk() {
+        for opcode in [0x8011, 0x8012, 0x8013] {
+            let mut cpu = setup(opcode, &[(0, 0x0F), (1, 0xF0), (VF, 1)]);
+            cpu.quirks.logic_resets_vf = true;
+            execute(&mut cpu).unwrap();
+            assert_eq!(cpu.gp_regs[VF], 0, "{:04X}", opcode);
+
+            let cpu = run(opcode, &[(0, 0x0F), (1, 0xF0), (VF, 1)]);
+            assert_eq!(cpu.gp_regs[VF], 1, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn add_xy_sets_carry() {
+        let cpu = run(0x8014, &[(0, 0xFF), (1, 0x02)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x01, 1));
+        let cpu = run(0x8014, &[(0, 0x10), (1, 0x02), (VF, 1)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x12, 0));
+    }
+
+    #[test]
+    fn add_xy_flag_overwrites_vf_result() {
+        let cpu = run(0x8F14, &[(VF, 0xFF), (1, 0x02)]);
+        assert_eq!(cpu.gp_regs[VF], 1);
+        let cpu = run(0x8F14, &[(VF, 0x10), (1, 0x02)]);
+        assert_eq!(cpu.gp_regs[VF], 0);
+    }
+
+    #[test]
+    fn sub_xy_sets_not_borrow() {
+        let cpu = run(0x8015, &[(0, 0x05), (1, 0x03)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x02, 1));
+        let cpu = run(0x8015, &[(0, 0x05), (1, 0x05)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x00, 1));
+        let cpu = run(0x8015, &[(0, 0x03), (1, 0x05)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0xFE, 0));
+        let cpu = run(0x8F15, &[(VF, 0x03), (1, 0x05)]);
+        assert_eq!(cpu.gp_regs[VF], 0);
+    }
+
+    #[test]
+    fn sub_yx_sets_not_borrow() {
+        let cpu = run(0x8017, &[(0, 0x03), (1, 0x05)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x02, 1));
+        let cpu = run(0x8017, &[(0, 0x05), (1, 0x03)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0xFE, 0));
+        let cpu = run(0x8F17, &[(VF, 0x03), (1, 0x05)]);
+        assert_eq!(cpu.gp_regs[VF], 1);
+    }
+
+    #[test]
+    fn shift_right_sets_lsb() {
+        let cpu = run(0x8016, &[(0, 0x05)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x02, 1));
+        let cpu = run(0x8016, &[(0, 0x04), (VF, 1)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x02, 0));
+        let cpu = run(0x8F06, &[(VF, 0x05)]);
+        assert_eq!(cpu.gp_regs[VF], 1);
+
+        let mut cpu = setup(0x8016, &[(0, 0x00), (1, 0x03)]);
+        cpu.quirks.shift_uses_vy = true;
+        execute(&mut cpu).unwrap();
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x01, 1));
+    }
+
+    #[test]
+    fn shift_left_sets_msb() {
+        let cpu = run(0x801E, &[(0, 0x81)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x02, 1));
+        let cpu = run(0x801E, &[(0, 0x41), (VF, 1)]);
+        assert_eq!((cpu.gp_regs[0], cpu.gp_regs[VF]), (0x82, 0));
+        let cpu = run(0x8F0E, &[(VF, 0x81)]);
+        assert_eq!(cpu.gp_regs[VF], 1);
+    }
+
+    #[test]
+    fn draw_sets_collision() {
+        let mut cpu = setup(0xD011, &[]);
+        cpu.mem.load_ascii_fonts();
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.gp_regs[VF], 0);
+        cpu.pc_reg = 0x200;
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.gp_regs[VF], 1);
+    }
+
+    #[test]
+    fn store_and_load_regs_include_vf() {
+        let mut cpu = setup(0xFF55, &[(VF, 0x99)]);
+        cpu.i_reg = 0x300;
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.mem.mem[0x30F], 0x99);
+
+        let mut cpu = setup(0xFF65, &[]);
+        cpu.i_reg = 0x300;
+        cpu.mem.mem[0x30F] = 0x77;
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.gp_regs[VF], 0x77);
+    }
+
+    #[test]
+    fn high_res_draws_large_sprite() {
+        let mut cpu = setup(0x00FF, &[(0, 120), (1, 60)]);
+        execute(&mut cpu).unwrap();
+        assert_eq!(
+            cpu.display_buffer.get_display_mode(),
+            DisplayMode::H128V64MONOCHROME
+        );
+
+        cpu.mem.mem[0x202] = 0xD0;
+        cpu.mem.mem[0x203] = 0x10;
+        cpu.i_reg = 0x300;
+        cpu.mem.mem[0x300] = 0x80;
+        cpu.mem.mem[0x301] = 0x01;
+        assert_eq!(execute(&mut cpu), Ok(StepOutcome::Drew));
+        assert!(cpu.display_buffer.get_pixel(120, 60));
+        assert!(!cpu.display_buffer.get_pixel(127, 60));
+        // Clipped at the right edge instead of wrapping to column 7
+        assert!(!cpu.display_buffer.get_pixel(7, 60));
+    }
+
+    #[test]
+    fn scroll_down_moves_pixels() {
+        let mut cpu = setup(0x00C3, &[]);
+        cpu.display_buffer.set_pixel(5, 0);
+        execute(&mut cpu).unwrap();
+        assert!(!cpu.display_buffer.get_pixel(5, 0));
+        assert!(cpu.display_buffer.get_pixel(5, 3));
+    }
+
+    #[test]
+    fn exit_stays_on_instruction() {
+        let mut cpu = setup(0x00FD, &[]);
+        assert_eq!(execute(&mut cpu), Ok(StepOutcome::Exited));
+        assert_eq!(cpu.pc_reg, 0x200);
+    }
+
+    #[test]
+    fn rpl_flags_round_trip() {
+        let mut cpu = run(0xF275, &[(0, 1), (1, 2), (2, 3)]);
+        cpu.gp_regs = [0; 16];
+        cpu.mem.mem[0x202] = 0xF2;
+        cpu.mem.mem[0x203] = 0x85;
+        execute(&mut cpu).unwrap();
+        assert_eq!(&cpu.gp_regs[..3], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn long_load_i_and_skip_over_it() {
+        let mut cpu = CPU::for_platform(Platform::XoChip);
+        cpu.pc_reg = 0x200;
+        cpu.mem.mem[0x200..0x208]
+            .copy_from_slice(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00]);
+        cpu.mem.mem[0x208..0x20A].copy_from_slice(&[0xAB, 0xCD]);
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.pc_reg, 0x206);
+        execute(&mut cpu).unwrap();
+        assert_eq!((cpu.i_reg, cpu.pc_reg), (0xABCD, 0x20A));
+    }
+
+    #[test]
+    fn save_and_load_register_range() {
+        let mut cpu = setup(0x5312, &[(1, 0xA), (2, 0xB), (3, 0xC)]);
+        cpu.i_reg = 0x300;
+        execute(&mut cpu).unwrap();
+        assert_eq!(&cpu.mem.mem[0x300..0x303], &[0xC, 0xB, 0xA]);
+        assert_eq!(cpu.i_reg, 0x300);
+
+        let mut cpu = setup(0x5463, &[]);
+        cpu.i_reg = 0x300;
+        cpu.mem.mem[0x300..0x303].copy_from_slice(&[1, 2, 3]);
+        execute(&mut cpu).unwrap();
+        assert_eq!(&cpu.gp_regs[4..7], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn draw_reads_one_sprite_per_plane() {
+        let mut cpu = setup(0xF301, &[]);
+        cpu.mem.mem[0x202..0x204].copy_from_slice(&[0xD0, 0x01]);
+        cpu.i_reg = 0x300;
+        cpu.mem.mem[0x300..0x302].copy_from_slice(&[0xC0, 0x80]);
+        execute(&mut cpu).unwrap();
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.display_buffer.get_pixel_color(0, 0), 3);
+        assert_eq!(cpu.display_buffer.get_pixel_color(1, 0), 1);
+    }
+
+    #[test]
+    fn new_cpu_runs_with_cosmac_vip_quirks() {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        assert_eq!(cpu.quirks, Quirks::COSMAC_VIP);
+        cpu.mem.mem[0x200..0x204].copy_from_slice(&[0x80, 0x16, 0xF1, 0x55]);
+        cpu.pc_reg = 0x200;
+        cpu.gp_regs[1] = 0x03;
+        cpu.i_reg = 0x300;
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.gp_regs[0], 0x01);
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.i_reg, 0x302);
+    }
+
+    #[test]
+    fn jump_reads_vx_with_quirk() {
+        let cpu = run(0xB210, &[(0, 0x10), (2, 0x20)]);
+        assert_eq!(cpu.pc_reg, 0x230);
+
+        let mut cpu = setup(0xB210, &[(0, 0x10), (2, 0x20)]);
+        cpu.quirks.jump_uses_vx = false;
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.pc_reg, 0x220);
+    }
+
+    #[test]
+    fn draw_waits_for_the_next_frame_with_quirk() {
+        // DRW V0, V0, 1; ADD V2, 1; JP 0x202
+        let program = [0xD0, 0x01, 0x72, 0x01, 0x12, 0x02];
+        let mut cpu = setup(0, &[]);
+        cpu.mem.mem[0x200..0x206].copy_from_slice(&program);
+        cpu.quirks.display_wait = true;
+        run_frame(&mut cpu).unwrap();
+        assert_eq!((cpu.pc_reg, cpu.gp_regs[2]), (0x202, 0));
+        run_frame(&mut cpu).unwrap();
+        assert!(cpu.gp_regs[2] > 0);
+
+        let mut cpu = setup(0, &[]);
+        cpu.mem.mem[0x200..0x206].copy_from_slice(&program);
+        run_frame(&mut cpu).unwrap();
+        assert!(cpu.gp_regs[2] > 0);
+    }
+
+    #[test]
+    fn sprites_clip_or_wrap_at_the_edge() {
+        let mut cpu = setup(0xD011, &[(0, 62)]);
+        cpu.i_reg = 0x300;
+        cpu.mem.mem[0x300] = 0xFF;
+        execute(&mut cpu).unwrap();
+        assert!(cpu.display_buffer.get_pixel(62, 0) && cpu.display_buffer.get_pixel(63, 0));
+        assert!(!cpu.display_buffer.get_pixel(0, 0));
+
+        let mut cpu = setup(0xD011, &[(0, 62)]);
+        cpu.quirks.clip_sprites = false;
+        cpu.i_reg = 0x300;
+        cpu.mem.mem[0x300] = 0xFF;
+        execute(&mut cpu).unwrap();
+        assert!(cpu.display_buffer.get_pixel(63, 0));
+        assert!(cpu.display_buffer.get_pixel(0, 0) && cpu.display_buffer.get_pixel(5, 0));
+        assert!(!cpu.display_buffer.get_pixel(6, 0));
+    }
+
+    #[test]
+    fn registers_wrap_at_the_end_of_memory() {
+        let mut cpu = CPU::for_platform(Platform::XoChip);
+        cpu.quirks.load_store_increments_i = LoadStoreIncrement::ByXPlusOne;
+        cpu.pc_reg = 0xFFFE;
+        cpu.mem.mem[0xFFFE..].copy_from_slice(&[0xF0, 0x55]);
+        cpu.i_reg = 0xFFFF;
+        execute(&mut cpu).unwrap();
+        assert_eq!((cpu.pc_reg, cpu.i_reg), (0x0000, 0x0000));
+
+        // SE V0, 0 skips past the end of memory
+        cpu.pc_reg = 0xFFFE;
+        cpu.mem.mem[0xFFFE..].copy_from_slice(&[0x30, 0x00]);
+        execute(&mut cpu).unwrap();
+        assert_eq!(cpu.pc_reg, 0x0002);
+    }
 }
@@ -0,0 +1,258 @@
+//! Minimal JSON reader for the data files of the interpreter, such as the ROM database.
+//!
+//! Parses the whole of RFC 8259 apart from keeping numbers exact: numbers are read as f64.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A parsed JSON value. Objects keep their members in file order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the name of the JSON type of this value, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "an array",
+            Value::Object(_) => "an object",
+        }
+    }
+}
+
+/// A JSON syntax error. Carries the 1-based line number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Parses a JSON document
+pub fn parse(text: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+        line: 1,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek().copied() {
+        None => Ok(value),
+        Some(c) => Err(parser.error(format!("Unexpected '{}' after the document", c))),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\r' | '\n') = self.chars.peek() {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("Expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("Expected '{}', found the end", expected))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('-' | '0'..='9') => self.number(),
+            Some('a'..='z') => self.literal(),
+            Some(c) => Err(self.error(format!("Unexpected '{}'", c))),
+            None => Err(self.error(String::from("Unexpected end of the document"))),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.expect('{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.next();
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(self.error(String::from("Expected a member name")));
+            }
+            let name = self.string()?;
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(members)),
+                _ => return Err(self.error(String::from("Expected ',' or '}' in object"))),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect('[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.next();
+            return Ok(Value::Array(elements));
+        }
+        loop {
+            elements.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(elements)),
+                _ => return Err(self.error(String::from("Expected ',' or ']' in array"))),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => return Err(self.error(String::from("Invalid escape in string"))),
+                    };
+                    string.push(escaped);
+                }
+                Some('\n') | None => return Err(self.error(String::from("Unterminated string"))),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// Reads the hex digits of a \u escape, and the low surrogate escape that follows a high one
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.next() != Some('\\') || self.next() != Some('u') {
+                return Err(self.error(String::from("Unpaired surrogate in string")));
+            }
+            let low = self.hex4()?;
+            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| self.error(String::from("Invalid \\u escape")))
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error(String::from("Invalid \\u escape")))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let mut text = String::new();
+        while let Some(c @ ('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) = self.chars.peek() {
+            text.push(*c);
+            self.next();
+        }
+        text.parse()
+            .map(Value::Number)
+            .map_err(|_| self.error(format!("Invalid number '{}'", text)))
+    }
+
+    fn literal(&mut self) -> Result<Value, ParseError> {
+        let mut word = String::new();
+        while let Some(c @ 'a'..='z') = self.chars.peek() {
+            word.push(*c);
+            self.next();
+        }
+        match word.as_str() {
+            "null" => Ok(Value::Null),
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(self.error(format!("Unexpected '{}'", word))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_documents() {
+        let value = parse(
+            r#"{ "name": "Pong \"1\" \u00e9\ud83d\ude00", "ipf": 1.5e1,
+                 "on": [true, false, null], "nested": {} }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            Value::Object(vec![
+                (
+                    String::from("name"),
+                    Value::String(String::from("Pong \"1\" é😀"))
+                ),
+                (String::from("ipf"), Value::Number(15.0)),
+                (
+                    String::from("on"),
+                    Value::Array(vec![Value::Bool(true), Value::Bool(false), Value::Null])
+                ),
+                (String::from("nested"), Value::Object(Vec::new())),
+            ])
+        );
+
+        assert_eq!(parse("{\n\"a\": tru }").unwrap_err().line, 2);
+        assert!(parse("[1, 2,]").is_err());
+        assert!(parse("{} {}").is_err());
+    }
+}
@@ -1,3 +1,30 @@
+#![allow(clippy::upper_case_acronyms)]
+
+// Modules
+pub mod assembler;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod emulator;
+pub mod error;
+mod inflate;
+pub mod input;
+pub mod instructions;
+mod json;
+pub mod memory;
+pub mod movie;
+pub mod opcode;
+pub mod pacing;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod rom_database;
+pub mod rom_loader;
+pub mod save_state;
+pub mod timers;
+mod zip;
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -1,62 +1,471 @@
-// Modules
-mod cpu;
-mod display;
-mod input;
-mod instructions;
-mod memory;
-mod rom_loader;
-
 // Core libraries
-use crate::cpu::CPU;
-use crate::display::{Display, DisplayBuffer, DisplayMode};
-use crate::input::{ChipKeys, Input};
-use crate::memory::Memory;
+use chip8_interpreter::cpu::CPU;
+use chip8_interpreter::display::{Display, DisplayMode};
+use chip8_interpreter::emulator::Emulator;
+use chip8_interpreter::error::Chip8Error;
+use chip8_interpreter::input::{ChipKeys, Input, Keyboard};
+use chip8_interpreter::instructions::{self, StepOutcome};
+use chip8_interpreter::movie::{Movie, MovieInput};
+use chip8_interpreter::rewind::RewindBuffer;
+use chip8_interpreter::rng::SeededRng;
+use chip8_interpreter::rom_database::{RomDatabase, RomInfo};
+use chip8_interpreter::rom_loader::{RomCollection, RomLoadError, RomLoader};
+use chip8_interpreter::{assembler, disassembler, rom_loader};
 
 // Concrete Displays
-use crate::display::crossterm_display::CrosstermDisplay;
+use chip8_interpreter::display::crossterm_display::{CrosstermDisplay, DEFAULT_PIXEL_COLORS};
+use chip8_interpreter::display::graphics_display::{
+    GraphicsDisplay, GraphicsProtocol, DEFAULT_SCALE,
+};
+use chip8_interpreter::display::persistence::{Persistence, PersistenceFilter};
+use chip8_interpreter::display::theme::ColorDepth;
 
 // Concrete Inputs
-use crate::input::crossterm_input::CrosstermInput;
-use crate::input::ChipKeys::Key5;
-use std::ops::Sub;
+use chip8_interpreter::input::crossterm_input::{CrosstermInput, DEFAULT_KEYMAP};
+
+mod cli;
+mod config;
+
+use chip8_interpreter::quirks::Platform;
+use cli::{Command, Options};
+use config::{Config, Graphics, Settings};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+/// ROM database file that extends the built in one, read when none is named on the command line,
+/// if it exists
+const LOCAL_ROM_DATABASE_FILE: &str = "chip8_roms.json";
+
+/// Seconds of play kept in the rewind buffer
+const REWIND_SECONDS: usize = 10;
+
+/// Frames stepped back by each rewind key event. Holding the key repeats it.
+const REWIND_FRAMES_PER_PRESS: usize = 15;
 
 fn main() {
     //keyboard_test()
 
-    let mut display = CrosstermDisplay::new(&DisplayMode::H64V32MONOCHROME);
-    let mut system_input = CrosstermInput::new(0);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+    match command {
+        Command::Run(options) => run(&options),
+        Command::Record { movie, options } => record(&movie, &options),
+        Command::Play { movie, options } => play(&movie, &options),
+        Command::Disasm { rom } => disasm(&rom),
+        Command::Asm { source, rom } => asm(&source, &rom),
+        Command::Help => println!("{}", cli::USAGE),
+    }
+}
+
+/// Prints an error and exits with a failure status
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+/// Reads the ROM named in the options: a file, standard input for -, or an entry of a zip
+/// archive or directory as collection:entry. Naming a collection alone lists its ROMs and exits.
+/// Returns the ROM and the file name its platform may be detected from.
+fn read_rom(options: &Options) -> Result<(Vec<u8>, String), String> {
+    if options.rom == "-" {
+        let mut rom = Vec::new();
+        io::stdin()
+            .read_to_end(&mut rom)
+            .map_err(|err| format!("Could not read ROM from standard input: {}", err))?;
+        return Ok((rom, options.rom.clone()));
+    }
+
+    let error = |err: RomLoadError| format!("Could not read ROM '{}': {}", options.rom, err);
+    let (path, entry) = rom_loader::split_collection_path(&options.rom);
+    if entry.is_none() && !RomCollection::is_collection(path) {
+        let rom = fs::read(path).map_err(|err| error(err.into()))?;
+        return Ok((rom, options.rom.clone()));
+    }
+
+    let collection = RomCollection::open(path).map_err(error)?;
+    let entry = match entry {
+        Some(entry) => entry,
+        None => {
+            list_collection(path, &collection);
+            std::process::exit(0);
+        }
+    };
+    let index = collection.select(entry).map_err(error)?;
+    let rom = collection.read(index).map_err(error)?;
+    Ok((rom, collection.entries()[index].name.clone()))
+}
+
+/// Prints the ROMs in a collection, with the index each can be chosen by
+fn list_collection(path: &str, collection: &RomCollection) {
+    println!("{} holds {} ROMs:", path, collection.entries().len());
+    for (index, entry) in collection.entries().iter().enumerate() {
+        println!("{:>4}  {:>5} bytes  {}", index, entry.size, entry.name);
+    }
+    println!("Run one with {}:<name or index>", path);
+}
+
+/// Returns the settings for a ROM, as the settings given for it and the defaults of the global
+/// section of the config file. The command line options take precedence over the ROM's section of
+/// the config file.
+fn resolve_settings(options: &Options, rom: &[u8]) -> (Settings, Settings) {
+    let config = match &options.config {
+        Some(path) => Config::load(path).unwrap_or_else(|err| fail(&err)),
+        None if Path::new(config::DEFAULT_CONFIG_FILE).exists() => {
+            Config::load(config::DEFAULT_CONFIG_FILE).unwrap_or_else(|err| fail(&err))
+        }
+        None => Config::default(),
+    };
+    let rom_settings = options
+        .settings
+        .or(&config.rom_settings(&rom_loader::rom_sha1_hex(rom)));
+    (rom_settings, config.global)
+}
+
+/// Returns the built in ROM database, extended with the local database file
+fn rom_database(options: &Options) -> RomDatabase {
+    let mut database = RomDatabase::builtin();
+    let path = match &options.rom_database {
+        Some(path) => path.as_str(),
+        None if Path::new(LOCAL_ROM_DATABASE_FILE).exists() => LOCAL_ROM_DATABASE_FILE,
+        None => return database,
+    };
+    if let Err(err) = database.extend_from_file(path) {
+        fail(&format!("Could not read ROM database '{}': {}", path, err));
+    }
+    database
+}
+
+/// Constructs the CPU for the options, with fonts and the ROM loaded, ready to run. The CPU is
+/// configured from the ROM database, then the settings given for the ROM. The platform named by the
+/// file extension, then the global settings, only apply where neither says anything. Returns the
+/// settings the ROM runs with and a title for the ROM.
+fn boot_cpu(options: &Options) -> (CPU, Settings, String) {
+    let (rom, file_name) = read_rom(options).unwrap_or_else(|err| fail(&err));
+    let (rom_settings, global) = resolve_settings(options, &rom);
+    let database = rom_database(options);
+    let info = database.lookup(&rom);
+    let platform = rom_settings
+        .platform
+        .or_else(|| info.and_then(|info| info.platform))
+        .or_else(|| rom_loader::platform_from_extension(&file_name))
+        .or(global.platform)
+        .unwrap_or(Platform::CosmacVip);
+
+    let mut cpu = CPU::for_platform(platform);
     cpu.mem.load_ascii_fonts();
+    cpu.mem.load_large_fonts();
+    // The quirks the ROM needs still apply when it is run on another platform
+    if let Some(info) = info {
+        info.quirks.apply(&mut cpu.quirks);
+    }
+    let loader = RomLoader::new(
+        options
+            .load_address
+            .unwrap_or(rom_loader::DEFAULT_LOAD_ADDRESS),
+    );
+    if let Err(err) = loader.load_bytes(&mut cpu, &rom) {
+        fail(&format!("Could not load ROM '{}': {}", options.rom, err));
+    }
+
+    let settings = rom_settings.or(&global);
+    if let Some(mode) = settings.display_mode {
+        cpu.display_buffer.set_display_mode(mode);
+    }
+    let database_ipf = info.and_then(|info| info.instructions_per_frame);
+    if let Some(ipf) = rom_settings
+        .instructions_per_frame
+        .or(database_ipf)
+        .or(global.instructions_per_frame)
+    {
+        cpu.clock.instructions_per_frame = ipf.max(1);
+    }
+    if let Some(seed) = options.seed {
+        cpu.seed_rng(seed);
+    }
 
-    //rom_loader::load_rom_file(&mut cpu, "roms/IBM_Logo.ch8").unwrap();
-    //rom_loader::load_rom_file(&mut cpu, "roms/test_opcode.ch8").unwrap();
-    rom_loader::load_rom_file(&mut cpu, "roms/delay_timer_test.ch8").unwrap();
-    cpu.pc_reg = 0x200;
+    let title = match info {
+        Some(RomInfo {
+            title,
+            key_hints: Some(keys),
+            ..
+        }) => format!("{} ({})", title, keys),
+        Some(info) => info.title.clone(),
+        None => options.rom.clone(),
+    };
+    (cpu, settings, title)
+}
 
-    loop {
-        let start_time = Instant::now();
-        system_input.update(&mut cpu.keyboard);
-        if cpu.keyboard.esc {
+/// Constructs the terminal display for the settings. Draws images if the settings ask for them, or
+/// the terminal supports them, and text otherwise. Colours given in the settings replace those of
+/// the theme.
+fn terminal_display(cpu: &CPU, settings: &Settings, title: &str) -> Box<dyn Display> {
+    let colors = settings
+        .colors
+        .or(settings.theme.map(|theme| theme.pixel_colors))
+        .unwrap_or(DEFAULT_PIXEL_COLORS);
+    let protocol = match settings.graphics.unwrap_or(Graphics::Auto) {
+        Graphics::Auto => GraphicsProtocol::detect(),
+        Graphics::Text => None,
+        Graphics::Protocol(protocol) => Some(protocol),
+    };
+    if let Some(protocol) = protocol {
+        let mut display = GraphicsDisplay::new(protocol, settings.scale.unwrap_or(DEFAULT_SCALE));
+        if let Some(theme) = settings.theme {
+            display.set_theme(&theme);
+        }
+        display.set_plane_colors(colors);
+        display.set_title(title);
+        return with_persistence(Box::new(display), settings);
+    }
+
+    let depth = settings.color_depth.unwrap_or_else(ColorDepth::detect);
+    let mut display = CrosstermDisplay::new(&cpu.display_buffer.get_display_mode());
+    display.set_color_depth(depth);
+    if let Some(theme) = settings.theme {
+        display.set_theme(&theme.degraded(depth));
+    }
+    let colors = colors.map(|color| depth.degrade(color));
+    display.set_plane_colors(colors);
+    if let Some(character) = settings.character {
+        display.set_term_character(&character, &colors[0]);
+    }
+    if let Some(render_mode) = settings.render_mode {
+        display.set_render_mode(render_mode);
+    }
+    display.set_title(title);
+    with_persistence(Box::new(display), settings)
+}
+
+/// Puts the persistence filter of the settings in front of a display
+fn with_persistence(display: Box<dyn Display>, settings: &Settings) -> Box<dyn Display> {
+    match settings.persistence {
+        None | Some(Persistence::Off) => display,
+        Some(persistence) => Box::new(PersistenceFilter::new(display, persistence)),
+    }
+}
+
+/// Constructs the terminal keyboard for the settings
+fn terminal_input(settings: &Settings) -> CrosstermInput {
+    CrosstermInput::with_keymap(0, settings.keymap.unwrap_or(DEFAULT_KEYMAP))
+}
+
+/// Returns true once the frame limit of the options is reached
+fn frame_limit_reached(cpu: &CPU, options: &Options) -> bool {
+    options
+        .max_frames
+        .is_some_and(|max_frames| cpu.clock.frame >= max_frames)
+}
+
+/// Runs the ROM in the terminal, or headless when asked to
+fn run(options: &Options) {
+    let (cpu, settings, title) = boot_cpu(options);
+    if options.headless {
+        run_headless(cpu, options, None);
+        return;
+    }
+
+    let display = terminal_display(&cpu, &settings, &title);
+    let mut emulator = Emulator::new(cpu, display, terminal_input(&settings));
+    let mut rewind = RewindBuffer::new(REWIND_SECONDS * 60);
+    // True while the CPU is still at the rewound snapshot, which is already the newest one
+    let mut rewound = false;
+
+    while !frame_limit_reached(&emulator.cpu, options) {
+        emulator.wait_for_next_frame();
+        emulator.update_input();
+        if emulator.cpu.keyboard.esc {
+            return;
+        }
+        if emulator.cpu.keyboard.speed_up || emulator.cpu.keyboard.speed_down {
+            let ipf = emulator.instructions_per_frame();
+            let step = (ipf / 4).max(1);
+            if emulator.cpu.keyboard.speed_up {
+                emulator.set_instructions_per_frame(ipf + step);
+            } else {
+                emulator.set_instructions_per_frame(ipf.saturating_sub(step));
+            }
+        }
+
+        if emulator.cpu.keyboard.rewind {
+            if rewind
+                .rewind(&mut emulator.cpu, REWIND_FRAMES_PER_PRESS)
+                .is_ok()
+            {
+                // Pixels fading from the frames rewound over are not drawn on the earlier one
+                emulator.display.clear_screen();
+                emulator.present();
+                rewound = true;
+            }
+            continue;
+        }
+        if !rewound {
+            rewind.push(&emulator.cpu);
+        }
+        rewound = false;
+        match emulator.run_frame() {
+            Ok(StepOutcome::Exited) => return,
+            Ok(_) => {}
+            Err(err) => crash(emulator, &err),
+        }
+    }
+}
+
+/// Runs the ROM as fast as possible with no display, until it exits, the movie ends or the frame
+/// limit is reached. Keys come from the movie, if any. Prints the final screen to standard output.
+fn run_headless(mut cpu: CPU, options: &Options, mut movie: Option<MovieInput>) {
+    while !frame_limit_reached(&cpu, options) {
+        if let Some(input) = movie.as_mut() {
+            if input.finished() {
+                break;
+            }
+            input.update(&mut cpu.keyboard);
+        }
+        match instructions::run_frame(&mut cpu) {
+            Ok(StepOutcome::Exited) => break,
+            Ok(_) => {}
+            Err(err) => {
+                print_crash_report(&cpu, &err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mode = cpu.display_buffer.get_display_mode();
+    for y in 0..mode.get_v_res() {
+        let row: String = (0..mode.get_h_res())
+            .map(|x| match cpu.display_buffer.get_pixel_color(x, y) {
+                0 => '.',
+                _ => '#',
+            })
+            .collect();
+        println!("{}", row);
+    }
+    println!("Stopped after {} frames", cpu.clock.frame);
+}
+
+/// record subcommand. Runs the ROM one frame per 60hz tick, and writes the keys pressed on every
+/// frame to the movie file when ESC is pressed.
+fn record(movie_path: &str, options: &Options) {
+    let (mut cpu, settings, title) = boot_cpu(options);
+    let mut movie = Movie::new(
+        &cpu,
+        options
+            .seed
+            .unwrap_or_else(|| SeededRng::from_entropy().state()),
+        cpu.clock.instructions_per_frame,
+    );
+    movie.start(&mut cpu).unwrap();
+
+    let display = terminal_display(&cpu, &settings, &title);
+    let mut emulator = Emulator::new(cpu, display, terminal_input(&settings));
+    while !frame_limit_reached(&emulator.cpu, options) {
+        emulator.wait_for_next_frame();
+        emulator.update_input();
+        if emulator.cpu.keyboard.esc {
+            break;
+        }
+        movie.record_frame(&emulator.cpu.keyboard);
+        match emulator.run_frame() {
+            Ok(StepOutcome::Exited) => break,
+            Ok(_) => {}
+            Err(err) => crash(emulator, &err),
+        }
+    }
+    drop(emulator);
+
+    if let Err(err) = movie.save(movie_path) {
+        fail(&format!("Could not write {}: {}", movie_path, err));
+    }
+}
+
+/// play subcommand. Replays a movie file. ESC stops playback.
+fn play(movie_path: &str, options: &Options) {
+    let (mut cpu, settings, title) = boot_cpu(options);
+    let movie = match Movie::load(movie_path).and_then(|movie| movie.start(&mut cpu).map(|_| movie))
+    {
+        Ok(movie) => movie,
+        Err(err) => fail(&format!("Could not play {}: {}", movie_path, err)),
+    };
+    if options.headless {
+        run_headless(cpu, options, Some(MovieInput::new(&movie)));
+        return;
+    }
+
+    // Playback only reads ESC from the terminal, the CHIP-8 keys come from the movie
+    let mut system_input = CrosstermInput::new(0);
+    let mut system_keys = Keyboard::new();
+    let display = terminal_display(&cpu, &settings, &title);
+    let mut emulator = Emulator::new(cpu, display, MovieInput::new(&movie));
+    while !emulator.input.finished() && !frame_limit_reached(&emulator.cpu, options) {
+        emulator.wait_for_next_frame();
+        system_input.update(&mut system_keys);
+        if system_keys.esc {
             return;
         }
-        if instructions::execute(&mut cpu) {
-            display.draw(&cpu.display_buffer);
+        emulator.update_input();
+        match emulator.run_frame() {
+            Ok(StepOutcome::Exited) => return,
+            Ok(_) => {}
+            Err(err) => crash(emulator, &err),
         }
-        cpu.update_time_registers();
+    }
+}
+
+/// Restores the terminal, reports the fault and exits
+fn crash<I: Input>(emulator: Emulator<Box<dyn Display>, I>, err: &Chip8Error) -> ! {
+    drop(emulator.display);
+    print_crash_report(&emulator.cpu, err);
+    std::process::exit(1);
+}
+
+/// disasm subcommand. Prints the disassembly of a ROM file.
+fn disasm(rom_path: &str) {
+    match fs::read(rom_path) {
+        Ok(rom) => print!("{}", disassembler::disassemble(&rom, 0x200).render()),
+        Err(err) => fail(&format!("Could not read {}: {}", rom_path, err)),
+    }
+}
+
+/// asm subcommand. Assembles a source file into a ROM file.
+fn asm(source_path: &str, rom_path: &str) {
+    let source = fs::read_to_string(source_path)
+        .unwrap_or_else(|err| fail(&format!("Could not read {}: {}", source_path, err)));
+    let rom = assembler::assemble(&source)
+        .unwrap_or_else(|err| fail(&format!("{}:{}", source_path, err)));
+    if let Err(err) = fs::write(rom_path, rom) {
+        fail(&format!("Could not write {}: {}", rom_path, err));
+    }
+}
 
-        // rate limit to ~500hz
-        let elapsed = start_time.elapsed();
-        let sleep_time = match Duration::from_millis(2).checked_sub(elapsed) {
-            Some(dur) => dur,
-            None => Duration::default(),
-        };
-        sleep(sleep_time);
+/// Prints the fault and a dump of the CPU registers to stderr
+fn print_crash_report(cpu: &CPU, err: &Chip8Error) {
+    eprintln!("CHIP-8 interpreter stopped: {}", err);
+    for (index, val) in cpu.gp_regs.iter().enumerate() {
+        eprint!("V{:X}={:02X} ", index, val);
     }
+    eprintln!();
+    eprintln!(
+        "I={:03X} PC={:03X} SP={:X} DT={:02X} ST={:02X}",
+        cpu.i_reg, cpu.pc_reg, cpu.stack_pointer_reg, cpu.delay_reg, cpu.sound_reg
+    );
+    eprintln!(
+        "Stack: {:03X?}",
+        &cpu.stack[..cpu.stack_pointer_reg as usize]
+    );
 }
 
+#[allow(dead_code)]
 fn keyboard_test() {
     let mut display = CrosstermDisplay::new(&DisplayMode::H64V32MONOCHROME);
     let mut system_input = CrosstermInput::new(0);
@@ -74,16 +483,16 @@ fn keyboard_test() {
         let keys = cpu.keyboard.get_active_inputs();
 
         if keys.contains(&ChipKeys::Key5) {
-            y = ((y - 1) % vres);
+            y = (y - 1) % vres;
         }
         if keys.contains(&ChipKeys::Key7) {
-            x = ((x - 1) % hres);
+            x = (x - 1) % hres;
         }
         if keys.contains(&ChipKeys::Key8) {
-            y = ((y + 1) % vres);
+            y = (y + 1) % vres;
         }
         if keys.contains(&ChipKeys::Key9) {
-            x = ((x + 1) % hres);
+            x = (x + 1) % hres;
         }
         if keys.contains(&ChipKeys::ESC) {
             return;
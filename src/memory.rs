@@ -1,17 +1,55 @@
-#[derive(Copy, Clone)]
+/// Address of the first SUPER-CHIP large font sprite
+pub const LARGE_FONT_ADDR: u16 = 0x50;
+
+/// SUPER-CHIP large font sprites 0-F, 10 bytes per digit
+const LARGE_FONTS: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Size in bytes of the standard CHIP-8 address space
+pub const STANDARD_MEMORY_SIZE: usize = 4096;
+/// Size in bytes of the extended XO-CHIP address space
+pub const XO_CHIP_MEMORY_SIZE: usize = 65536;
+
+#[derive(Clone)]
 /// Struct wrapper for Chip-8 Memory. Contains actual memory storage, as well as helper functions
 /// for working with Chip-8 Memory.
-pub struct Memory{
-    /// Byte storage memory. 4096 Bytes represented as a 1D array of u8 type.
-    pub mem: [u8; 4096],
+pub struct Memory {
+    /// Byte storage memory. 4096 Bytes (65536 for XO-CHIP) represented as a 1D array of u8 type.
+    pub mem: Vec<u8>,
 }
 
-impl Memory{
-    /// Create a new Memory object. All memory values are initialized to 0.
-    pub fn new() -> Memory{
-        Memory{
-            mem: [0; 4096],
-        }
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+impl Memory {
+    /// Create a new 4096 byte Memory object. All memory values are initialized to 0.
+    pub fn new() -> Memory {
+        Memory::with_size(STANDARD_MEMORY_SIZE)
+    }
+
+    /// Create a new Memory object of the given size in bytes. All memory values are initialized
+    /// to 0.
+    pub fn with_size(size: usize) -> Memory {
+        Memory { mem: vec![0; size] }
     }
 
     /// Returns the entire memory contents as a hex formatted string
@@ -21,7 +59,7 @@ impl Memory{
 
     /// Clears the memory. Sets all memory bytes to 0.
     pub fn clear(&mut self) {
-        self.mem = [0; 4096];
+        self.mem.iter_mut().for_each(|byte| *byte = 0);
     }
 
     /// Loads ascii fonts 0-F hex into memory, as per CHIP-8 documentation.
@@ -126,6 +164,14 @@ impl Memory{
         self.mem[0x4F] = 0x80;
     }
 
+    /// Loads the SUPER-CHIP large fonts 0-F hex into memory, following the Ascii fonts.
+    /// Large fonts occupy memory space 0x50 - 0xEF. Each large font is an 8x10 sprite, that
+    /// occupies 10 bytes per digit.
+    pub fn load_large_fonts(&mut self) {
+        let start = LARGE_FONT_ADDR as usize;
+        self.mem[start..start + LARGE_FONTS.len()].copy_from_slice(&LARGE_FONTS);
+    }
+
     /// Returns an array slice that contains the Ascii font sprite specified by parameter [val]
     pub fn get_ascii_slice(&self, val: u8) -> Result<&[u8], String> {
         if val > 0xF {
@@ -137,4 +183,3 @@ impl Memory{
         Ok(slice)
     }
 }
-
@@ -0,0 +1,440 @@
+//! Input movies for deterministic recording and playback.
+//!
+//! A movie records everything needed to replay a run bit-identically: the ROM SHA-1, the RNG seed,
+//! the platform, display mode and quirks, the number of instructions per frame and the CHIP-8 keys
+//! held on every frame.
+//! The emulated clock of the CPU is set to the recorded instructions per frame, and the keys of
+//! one movie frame are applied before each call to instructions::run_frame.
+//!
+//! Movies are stored as text so they can be attached to bug tickets and diffed:
+//!
+//! ```text
+//! CHIP8MOVIE 1
+//! rom 0a5f3b2a8e1e6f6d7f6b3a2c1d0e9f8a7b6c5d4e
+//! seed 1A2B3C4D
+//! platform schip
+//! display 64x32
+//! quirks 110111
+//! ipf 10
+//! frames
+//! 0000 120
+//! 0020 3
+//! 0000 45
+//! ```
+//!
+//! Each frame line is the hex key mask (bit n set while key n is held) and the number of
+//! consecutive frames with that mask, up to MAX_FRAMES in all. Quirk flags are listed in Quirks
+//! field order, 1 for true and 0 for false. The load_store_increments_i flag is 0 for unchanged,
+//! 1 for X + 1 and 2 for X.
+
+use crate::cpu::CPU;
+use crate::display::DisplayMode;
+use crate::input::{Input, Keyboard};
+use crate::quirks::{LoadStoreIncrement, Platform, Quirks};
+use crate::rom_loader::to_hex;
+use crate::timers::FrameClock;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// First line of every movie file
+const HEADER: &str = "CHIP8MOVIE 1";
+
+/// Most frames a movie can hold, 24 hours at 60hz
+pub const MAX_FRAMES: usize = 24 * 60 * 60 * 60;
+
+/// Error raised when a movie cannot be read or does not match the loaded ROM
+#[derive(Debug)]
+pub enum MovieError {
+    /// Reading or writing the movie file failed
+    Io(io::Error),
+    /// The movie text is malformed. Carries the 1-based line number.
+    Parse { line: usize, message: String },
+    /// The movie was recorded with a different ROM than the one loaded
+    RomMismatch { expected: [u8; 20], found: [u8; 20] },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(err) => write!(f, "{}", err),
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie is for ROM {}, but ROM {} is loaded",
+                to_hex(found),
+                to_hex(expected)
+            ),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> MovieError {
+        MovieError::Io(err)
+    }
+}
+
+/// A recorded run
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    /// SHA-1 digest of the ROM the movie was recorded with
+    pub rom_sha1: [u8; 20],
+    /// Seed of the CPU random number generator
+    pub seed: u32,
+    /// Platform the movie was recorded on
+    pub platform: Platform,
+    /// Display mode at the start of the movie
+    pub display_mode: DisplayMode,
+    /// Quirks the movie was recorded with
+    pub quirks: Quirks,
+    /// Instructions executed per 60hz frame
+    pub instructions_per_frame: u32,
+    /// Key mask of every frame, as returned by Keyboard::key_mask
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Constructs an empty movie for the ROM, platform, display mode and quirks of the CPU
+    pub fn new(cpu: &CPU, seed: u32, instructions_per_frame: u32) -> Movie {
+        Movie {
+            rom_sha1: cpu.rom_sha1,
+            seed,
+            platform: cpu.platform,
+            display_mode: cpu.display_buffer.get_display_mode(),
+            quirks: cpu.quirks,
+            instructions_per_frame,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Prepares a CPU with the ROM loaded for recording or playing this movie. Seeds the RNG,
+    /// applies the platform, display mode and quirks and restarts the clock and timers. Fails if a
+    /// different ROM is loaded.
+    ///
+    /// The program counter, registers, memory and display are not reset, so the CPU must be freshly
+    /// booted with the ROM for the run to match the recording.
+    pub fn start(&self, cpu: &mut CPU) -> Result<(), MovieError> {
+        if cpu.rom_sha1 != self.rom_sha1 {
+            return Err(MovieError::RomMismatch {
+                expected: cpu.rom_sha1,
+                found: self.rom_sha1,
+            });
+        }
+        cpu.seed_rng(self.seed);
+        cpu.set_platform(self.platform);
+        cpu.display_buffer.set_display_mode(self.display_mode);
+        cpu.quirks = self.quirks;
+        cpu.clock = FrameClock::new(self.instructions_per_frame);
+        cpu.delay_reg = 0;
+        cpu.sound_reg = 0;
+        cpu.waiting_for_display = false;
+        cpu.keyboard.clear();
+        Ok(())
+    }
+
+    /// Appends the keys currently held on the keyboard as the next frame
+    pub fn record_frame(&mut self, keyboard: &Keyboard) {
+        self.frames.push(keyboard.key_mask());
+    }
+
+    /// Parses a movie from its text form
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+        let parse_error = |line: usize, message: &str| MovieError::Parse {
+            line,
+            message: message.to_string(),
+        };
+
+        // A missing field is reported on the last line of the header
+        let mut last_line = match lines.next() {
+            Some((line, HEADER)) => line,
+            Some((line, _)) => return Err(parse_error(line, "not a CHIP-8 movie")),
+            None => return Err(parse_error(1, "empty movie")),
+        };
+        let mut field = |name: &str| -> Result<(usize, &str), MovieError> {
+            match lines.next() {
+                Some((line, text)) => {
+                    last_line = line;
+                    match text.split_once(' ') {
+                        Some((key, value)) if key == name => Ok((line, value.trim())),
+                        _ => Err(parse_error(line, &format!("expected '{}'", name))),
+                    }
+                }
+                None => Err(parse_error(last_line, &format!("missing '{}'", name))),
+            }
+        };
+        let (line, rom) = field("rom")?;
+        if rom.len() != 40 || !rom.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(parse_error(line, "ROM SHA-1 must be 40 hex digits"));
+        }
+        let mut rom_sha1 = [0; 20];
+        for (index, byte) in rom_sha1.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&rom[index * 2..index * 2 + 2], 16).unwrap();
+        }
+        let (line, seed) = field("seed")?;
+        let seed = u32::from_str_radix(seed, 16).map_err(|_| parse_error(line, "bad seed"))?;
+        let (line, platform) = field("platform")?;
+        let platform = platform
+            .parse()
+            .map_err(|err: String| parse_error(line, &err))?;
+        let (line, display) = field("display")?;
+        let display_mode = match display {
+            "64x32" => DisplayMode::H64V32MONOCHROME,
+            "128x64" => DisplayMode::H128V64MONOCHROME,
+            _ => return Err(parse_error(line, "display must be 64x32 or 128x64")),
+        };
+        let (line, flags) = field("quirks")?;
+        let flags: Vec<char> = flags.chars().collect();
+        let bad_flags = || parse_error(line, "quirks must be 6 flags");
+        if flags.len() != 6 {
+            return Err(bad_flags());
+        }
+        let flag = |index: usize| match flags[index] {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(bad_flags()),
+        };
+        let quirks = Quirks {
+            shift_uses_vy: flag(0)?,
+            load_store_increments_i: flags[1]
+                .to_digit(10)
+                .and_then(|digit| LoadStoreIncrement::from_flag(digit as u8))
+                .ok_or_else(bad_flags)?,
+            jump_uses_vx: flag(2)?,
+            logic_resets_vf: flag(3)?,
+            clip_sprites: flag(4)?,
+            display_wait: flag(5)?,
+        };
+        let (line, ipf) = field("ipf")?;
+        let instructions_per_frame = ipf
+            .parse()
+            .map_err(|_| parse_error(line, "bad instructions per frame"))?;
+
+        match lines.next() {
+            Some((_, "frames")) => {}
+            Some((line, _)) => return Err(parse_error(line, "expected 'frames'")),
+            None => return Err(parse_error(last_line, "missing 'frames'")),
+        }
+        let mut frames = Vec::new();
+        for (line, text) in lines {
+            let mut parts = text.split_whitespace();
+            let mask = parts
+                .next()
+                .and_then(|mask| u16::from_str_radix(mask, 16).ok())
+                .ok_or_else(|| parse_error(line, "bad key mask"))?;
+            let count: usize = match parts.next() {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| parse_error(line, "bad frame count"))?,
+                None => 1,
+            };
+            if count > MAX_FRAMES - frames.len() {
+                return Err(parse_error(
+                    line,
+                    &format!("movie is longer than {} frames", MAX_FRAMES),
+                ));
+            }
+            frames.extend(std::iter::repeat_n(mask, count));
+        }
+
+        Ok(Movie {
+            rom_sha1,
+            seed,
+            platform,
+            display_mode,
+            quirks,
+            instructions_per_frame,
+            frames,
+        })
+    }
+
+    /// Reads a movie file
+    pub fn load(file_path: &str) -> Result<Movie, MovieError> {
+        Movie::parse(&fs::read_to_string(file_path)?)
+    }
+
+    /// Writes the movie to a file
+    pub fn save(&self, file_path: &str) -> Result<(), MovieError> {
+        fs::write(file_path, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let q = &self.quirks;
+        let flags: String = [
+            q.shift_uses_vy as u8,
+            q.load_store_increments_i.flag(),
+            q.jump_uses_vx as u8,
+            q.logic_resets_vf as u8,
+            q.clip_sprites as u8,
+            q.display_wait as u8,
+        ]
+        .iter()
+        .map(|flag| char::from(b'0' + flag))
+        .collect();
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "rom {}", to_hex(&self.rom_sha1))?;
+        writeln!(f, "seed {:08X}", self.seed)?;
+        writeln!(f, "platform {}", self.platform)?;
+        let display = match self.display_mode {
+            DisplayMode::H64V32MONOCHROME => "64x32",
+            DisplayMode::H128V64MONOCHROME => "128x64",
+        };
+        writeln!(f, "display {}", display)?;
+        writeln!(f, "quirks {}", flags)?;
+        writeln!(f, "ipf {}", self.instructions_per_frame)?;
+        writeln!(f, "frames")?;
+
+        let mut frames = self.frames.iter().peekable();
+        while let Some(mask) = frames.next() {
+            let mut count = 1;
+            while frames.peek() == Some(&mask) {
+                frames.next();
+                count += 1;
+            }
+            writeln!(f, "{:04X} {}", mask, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scripted Input that replays the key masks of a movie, one frame per update
+pub struct MovieInput {
+    frames: Vec<u16>,
+    position: usize,
+}
+
+impl MovieInput {
+    /// Constructs a new MovieInput positioned at the first frame of the movie
+    pub fn new(movie: &Movie) -> MovieInput {
+        MovieInput {
+            frames: movie.frames.clone(),
+            position: 0,
+        }
+    }
+
+    /// Returns true once every frame of the movie has been replayed
+    pub fn finished(&self) -> bool {
+        self.position >= self.frames.len()
+    }
+}
+
+impl Input for MovieInput {
+    /// Sets the keyboard to the next frame of the movie. After the last frame no keys are held.
+    fn update(&mut self, keyboard: &mut Keyboard) {
+        keyboard.clear();
+        if let Some(mask) = self.frames.get(self.position) {
+            keyboard.set_key_mask(*mask);
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::run_frame;
+    use crate::rom_loader::load_rom_bytes;
+
+    /// Plays a movie from a fresh CPU and returns the final state
+    fn play(movie: &Movie, rom: &[u8]) -> CPU {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        load_rom_bytes(&mut cpu, rom).unwrap();
+        cpu.pc_reg = 0x200;
+        movie.start(&mut cpu).unwrap();
+        let mut input = MovieInput::new(movie);
+        while !input.finished() {
+            input.update(&mut cpu.keyboard);
+            run_frame(&mut cpu).unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn replays_identically_after_text_round_trip() {
+        // loop: RND V0, #FF; SKNP V1; ADD V2, 1; LD DT, V0; DRW V0, V2, 1; JP loop
+        let rom = [
+            0xC0, 0xFF, 0xE1, 0xA1, 0x72, 0x01, 0xF0, 0x15, 0xD0, 0x21, 0x12, 0x00,
+        ];
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        load_rom_bytes(&mut cpu, &rom).unwrap();
+        // CHIP-48 moves I by X, the load/store flag that is not 0 or 1
+        cpu.set_platform(Platform::Chip48);
+        cpu.display_buffer
+            .set_display_mode(DisplayMode::H128V64MONOCHROME);
+        let mut movie = Movie::new(&cpu, 0xC0FFEE, 12);
+        let mut keyboard = Keyboard::new();
+        for frame in 0..100 {
+            keyboard.key_0 = frame % 7 < 3;
+            movie.record_frame(&keyboard);
+        }
+
+        let text = movie.to_string();
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed, movie);
+
+        let first = play(&movie, &rom);
+        let second = play(&parsed, &rom);
+        assert_eq!(second.platform, Platform::Chip48);
+        assert_eq!(
+            second.display_buffer.get_display_mode(),
+            DisplayMode::H128V64MONOCHROME
+        );
+        assert_eq!(first.mem.mem, second.mem.mem);
+        assert_eq!(first.gp_regs, second.gp_regs);
+        assert_eq!(
+            first.display_buffer.get_buffer()[..],
+            second.display_buffer.get_buffer()[..]
+        );
+    }
+
+    #[test]
+    fn rejects_other_roms() {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        load_rom_bytes(&mut cpu, &[0x12, 0x00]).unwrap();
+        let movie = Movie::new(&cpu, 1, 10);
+        load_rom_bytes(&mut cpu, &[0x12, 0x02]).unwrap();
+        assert!(matches!(
+            movie.start(&mut cpu),
+            Err(MovieError::RomMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_movies_too_long_to_hold() {
+        let cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        let mut movie = Movie::new(&cpu, 1, 10);
+        movie.frames = vec![0; 2];
+        let text = movie.to_string().replace("0000 2", "0000 99999999999");
+        assert!(matches!(
+            Movie::parse(&text),
+            Err(MovieError::Parse { line: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn reports_missing_fields_on_the_last_header_line() {
+        let cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        let text = Movie::new(&cpu, 1, 10).to_string();
+        let header: Vec<&str> = text.lines().take(3).collect();
+        assert!(matches!(
+            Movie::parse(&header.join("\n")),
+            Err(MovieError::Parse { line: 3, .. })
+        ));
+        let header: Vec<&str> = text.lines().take(7).collect();
+        assert!(matches!(
+            Movie::parse(&header.join("\n")),
+            Err(MovieError::Parse { line: 7, .. })
+        ));
+    }
+}
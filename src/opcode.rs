@@ -0,0 +1,378 @@
+//! Decoding and encoding of CHIP-8, SUPER-CHIP and XO-CHIP opcodes.
+//!
+//! Instruction names follow the functions in the instructions module, and Display uses the
+//! mnemonics from CowGod's CHIP-8 Reference page, extended with the SUPER-CHIP and XO-CHIP
+//! mnemonics. Immediate values are written in hex with a '#' prefix, sprite heights and scroll
+//! distances in decimal.
+
+use std::error::Error;
+use std::fmt;
+
+/// A single decoded instruction. Fields follow the naming used in the instructions module:
+/// x and y are register indices, n a nibble, kk a byte and nnn a 12-bit address.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Instruction {
+    /// 0nnn - SYS addr
+    Sys { nnn: u16 },
+    /// 00Cn - SCD nibble
+    ScrollDown { n: u8 },
+    /// 00Dn - SCU nibble
+    ScrollUp { n: u8 },
+    /// 00E0 - CLS
+    Cls,
+    /// 00EE - RET
+    Ret,
+    /// 00FB - SCR
+    ScrollRight,
+    /// 00FC - SCL
+    ScrollLeft,
+    /// 00FD - EXIT
+    Exit,
+    /// 00FE - LOW
+    LowRes,
+    /// 00FF - HIGH
+    HighRes,
+    /// 1nnn - JP addr
+    Jump { nnn: u16 },
+    /// 2nnn - CALL addr
+    Call { nnn: u16 },
+    /// 3xkk - SE Vx, byte
+    SkipEqual { x: u8, kk: u8 },
+    /// 4xkk - SNE Vx, byte
+    SkipNotEqual { x: u8, kk: u8 },
+    /// 5xy0 - SE Vx, Vy
+    SkipEqualXY { x: u8, y: u8 },
+    /// 5xy2 - SAVE Vx, Vy
+    SaveRange { x: u8, y: u8 },
+    /// 5xy3 - LOAD Vx, Vy
+    LoadRange { x: u8, y: u8 },
+    /// 6xkk - LD Vx, byte
+    Load { x: u8, kk: u8 },
+    /// 7xkk - ADD Vx, byte
+    Add { x: u8, kk: u8 },
+    /// 8xy0 - LD Vx, Vy
+    LoadXY { x: u8, y: u8 },
+    /// 8xy1 - OR Vx, Vy
+    Or { x: u8, y: u8 },
+    /// 8xy2 - AND Vx, Vy
+    And { x: u8, y: u8 },
+    /// 8xy3 - XOR Vx, Vy
+    Xor { x: u8, y: u8 },
+    /// 8xy4 - ADD Vx, Vy
+    AddXY { x: u8, y: u8 },
+    /// 8xy5 - SUB Vx, Vy
+    SubXY { x: u8, y: u8 },
+    /// 8xy6 - SHR Vx {, Vy}
+    ShiftRight { x: u8, y: u8 },
+    /// 8xy7 - SUBN Vx, Vy
+    SubYX { x: u8, y: u8 },
+    /// 8xyE - SHL Vx {, Vy}
+    ShiftLeft { x: u8, y: u8 },
+    /// 9xy0 - SNE Vx, Vy
+    SkipNotEqualXY { x: u8, y: u8 },
+    /// Annn - LD I, addr
+    LoadI { nnn: u16 },
+    /// Bnnn - JP V0, addr
+    JumpV0 { nnn: u16 },
+    /// Cxkk - RND Vx, byte
+    Rand { x: u8, kk: u8 },
+    /// Dxyn - DRW Vx, Vy, nibble
+    Draw { x: u8, y: u8, n: u8 },
+    /// Ex9E - SKP Vx
+    SkipIfKey { x: u8 },
+    /// ExA1 - SKNP Vx
+    SkipNotKey { x: u8 },
+    /// F000 NNNN - LD I, LONG NNNN. The address is the word following the instruction.
+    LongLoadI,
+    /// Fn01 - PLANE n
+    SelectPlanes { n: u8 },
+    /// F002 - AUDIO
+    LoadAudioPattern,
+    /// Fx07 - LD Vx, DT
+    LoadDelayToVx { x: u8 },
+    /// Fx0A - LD Vx, K
+    WaitForKey { x: u8 },
+    /// Fx15 - LD DT, Vx
+    LoadDelayTimer { x: u8 },
+    /// Fx18 - LD ST, Vx
+    LoadSoundTimer { x: u8 },
+    /// Fx1E - ADD I, Vx
+    AddIVx { x: u8 },
+    /// Fx29 - LD F, Vx
+    LoadAsciiAddress { x: u8 },
+    /// Fx30 - LD HF, Vx
+    LoadLargeFontAddress { x: u8 },
+    /// Fx33 - LD B, Vx
+    LoadBcd { x: u8 },
+    /// Fx3A - PITCH Vx
+    LoadPitch { x: u8 },
+    /// Fx55 - LD [I], Vx
+    StoreRegs { x: u8 },
+    /// Fx65 - LD Vx, [I]
+    LoadRegs { x: u8 },
+    /// Fx75 - LD R, Vx
+    StoreRplFlags { x: u8 },
+    /// Fx85 - LD Vx, R
+    LoadRplFlags { x: u8 },
+}
+
+impl Instruction {
+    /// Returns the size in bytes of this instruction, including any trailing operand words
+    pub fn size(&self) -> u16 {
+        match *self {
+            Instruction::LongLoadI => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Error returned when an opcode does not decode to any supported instruction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DecodeError {
+    /// The raw opcode that could not be decoded
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {:04X}", self.opcode)
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Decodes a raw 16-bit opcode into an Instruction
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    // Split the 2-byte instruction into four nibbles
+    let first = (opcode >> 12) as u8;
+    let x = ((opcode >> 8) & 0xF) as u8;
+    let y = ((opcode >> 4) & 0xF) as u8;
+    let n = (opcode & 0xF) as u8;
+    let kk = (opcode & 0xFF) as u8;
+    let nnn = opcode & 0xFFF;
+
+    let instruction = match first {
+        0x0 => match (x, y, n) {
+            (0x0, 0xC, n) => Instruction::ScrollDown { n },
+            (0x0, 0xD, n) => Instruction::ScrollUp { n },
+            (0x0, 0xE, 0x0) => Instruction::Cls,
+            (0x0, 0xE, 0xE) => Instruction::Ret,
+            (0x0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0x0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0x0, 0xF, 0xD) => Instruction::Exit,
+            (0x0, 0xF, 0xE) => Instruction::LowRes,
+            (0x0, 0xF, 0xF) => Instruction::HighRes,
+            (0x0, 0xE, _) | (0x0, 0xF, _) => return Err(DecodeError { opcode }),
+            _ => Instruction::Sys { nnn },
+        },
+        0x1 => Instruction::Jump { nnn },
+        0x2 => Instruction::Call { nnn },
+        0x3 => Instruction::SkipEqual { x, kk },
+        0x4 => Instruction::SkipNotEqual { x, kk },
+        0x5 => match n {
+            0x0 => Instruction::SkipEqualXY { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x6 => Instruction::Load { x, kk },
+        0x7 => Instruction::Add { x, kk },
+        0x8 => match n {
+            0x0 => Instruction::LoadXY { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::AddXY { x, y },
+            0x5 => Instruction::SubXY { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubYX { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x9 => match n {
+            0x0 => Instruction::SkipNotEqualXY { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xA => Instruction::LoadI { nnn },
+        0xB => Instruction::JumpV0 { nnn },
+        0xC => Instruction::Rand { x, kk },
+        0xD => Instruction::Draw { x, y, n },
+        0xE => match kk {
+            0x9E => Instruction::SkipIfKey { x },
+            0xA1 => Instruction::SkipNotKey { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        _ => match kk {
+            0x00 if x == 0x0 => Instruction::LongLoadI,
+            0x01 => Instruction::SelectPlanes { n: x },
+            0x02 if x == 0x0 => Instruction::LoadAudioPattern,
+            0x07 => Instruction::LoadDelayToVx { x },
+            0x0A => Instruction::WaitForKey { x },
+            0x15 => Instruction::LoadDelayTimer { x },
+            0x18 => Instruction::LoadSoundTimer { x },
+            0x1E => Instruction::AddIVx { x },
+            0x29 => Instruction::LoadAsciiAddress { x },
+            0x30 => Instruction::LoadLargeFontAddress { x },
+            0x33 => Instruction::LoadBcd { x },
+            0x3A => Instruction::LoadPitch { x },
+            0x55 => Instruction::StoreRegs { x },
+            0x65 => Instruction::LoadRegs { x },
+            0x75 => Instruction::StoreRplFlags { x },
+            0x85 => Instruction::LoadRplFlags { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+    };
+    Ok(instruction)
+}
+
+/// Encodes an Instruction into its raw 16-bit opcode. For LongLoadI only the first word (F000)
+/// is returned; the address follows it in memory.
+pub fn encode(instruction: &Instruction) -> u16 {
+    // Helpers to assemble the operand nibbles
+    let xkk = |x: u8, kk: u8| ((x as u16 & 0xF) << 8) | kk as u16;
+    let xyn =
+        |x: u8, y: u8, n: u8| ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | (n as u16 & 0xF);
+
+    match *instruction {
+        Instruction::Sys { nnn } => nnn & 0xFFF,
+        Instruction::ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+        Instruction::ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+        Instruction::Cls => 0x00E0,
+        Instruction::Ret => 0x00EE,
+        Instruction::ScrollRight => 0x00FB,
+        Instruction::ScrollLeft => 0x00FC,
+        Instruction::Exit => 0x00FD,
+        Instruction::LowRes => 0x00FE,
+        Instruction::HighRes => 0x00FF,
+        Instruction::Jump { nnn } => 0x1000 | (nnn & 0xFFF),
+        Instruction::Call { nnn } => 0x2000 | (nnn & 0xFFF),
+        Instruction::SkipEqual { x, kk } => 0x3000 | xkk(x, kk),
+        Instruction::SkipNotEqual { x, kk } => 0x4000 | xkk(x, kk),
+        Instruction::SkipEqualXY { x, y } => 0x5000 | xyn(x, y, 0x0),
+        Instruction::SaveRange { x, y } => 0x5000 | xyn(x, y, 0x2),
+        Instruction::LoadRange { x, y } => 0x5000 | xyn(x, y, 0x3),
+        Instruction::Load { x, kk } => 0x6000 | xkk(x, kk),
+        Instruction::Add { x, kk } => 0x7000 | xkk(x, kk),
+        Instruction::LoadXY { x, y } => 0x8000 | xyn(x, y, 0x0),
+        Instruction::Or { x, y } => 0x8000 | xyn(x, y, 0x1),
+        Instruction::And { x, y } => 0x8000 | xyn(x, y, 0x2),
+        Instruction::Xor { x, y } => 0x8000 | xyn(x, y, 0x3),
+        Instruction::AddXY { x, y } => 0x8000 | xyn(x, y, 0x4),
+        Instruction::SubXY { x, y } => 0x8000 | xyn(x, y, 0x5),
+        Instruction::ShiftRight { x, y } => 0x8000 | xyn(x, y, 0x6),
+        Instruction::SubYX { x, y } => 0x8000 | xyn(x, y, 0x7),
+        Instruction::ShiftLeft { x, y } => 0x8000 | xyn(x, y, 0xE),
+        Instruction::SkipNotEqualXY { x, y } => 0x9000 | xyn(x, y, 0x0),
+        Instruction::LoadI { nnn } => 0xA000 | (nnn & 0xFFF),
+        Instruction::JumpV0 { nnn } => 0xB000 | (nnn & 0xFFF),
+        Instruction::Rand { x, kk } => 0xC000 | xkk(x, kk),
+        Instruction::Draw { x, y, n } => 0xD000 | xyn(x, y, n),
+        Instruction::SkipIfKey { x } => 0xE000 | xkk(x, 0x9E),
+        Instruction::SkipNotKey { x } => 0xE000 | xkk(x, 0xA1),
+        Instruction::LongLoadI => 0xF000,
+        Instruction::SelectPlanes { n } => 0xF000 | xkk(n, 0x01),
+        Instruction::LoadAudioPattern => 0xF002,
+        Instruction::LoadDelayToVx { x } => 0xF000 | xkk(x, 0x07),
+        Instruction::WaitForKey { x } => 0xF000 | xkk(x, 0x0A),
+        Instruction::LoadDelayTimer { x } => 0xF000 | xkk(x, 0x15),
+        Instruction::LoadSoundTimer { x } => 0xF000 | xkk(x, 0x18),
+        Instruction::AddIVx { x } => 0xF000 | xkk(x, 0x1E),
+        Instruction::LoadAsciiAddress { x } => 0xF000 | xkk(x, 0x29),
+        Instruction::LoadLargeFontAddress { x } => 0xF000 | xkk(x, 0x30),
+        Instruction::LoadBcd { x } => 0xF000 | xkk(x, 0x33),
+        Instruction::LoadPitch { x } => 0xF000 | xkk(x, 0x3A),
+        Instruction::StoreRegs { x } => 0xF000 | xkk(x, 0x55),
+        Instruction::LoadRegs { x } => 0xF000 | xkk(x, 0x65),
+        Instruction::StoreRplFlags { x } => 0xF000 | xkk(x, 0x75),
+        Instruction::LoadRplFlags { x } => 0xF000 | xkk(x, 0x85),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys { nnn } => write!(f, "SYS #{:03X}", nnn),
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump { nnn } => write!(f, "JP #{:03X}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL #{:03X}", nnn),
+            Instruction::SkipEqual { x, kk } => write!(f, "SE V{:X}, #{:02X}", x, kk),
+            Instruction::SkipNotEqual { x, kk } => write!(f, "SNE V{:X}, #{:02X}", x, kk),
+            Instruction::SkipEqualXY { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::Load { x, kk } => write!(f, "LD V{:X}, #{:02X}", x, kk),
+            Instruction::Add { x, kk } => write!(f, "ADD V{:X}, #{:02X}", x, kk),
+            Instruction::LoadXY { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddXY { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubXY { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubYX { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNotEqualXY { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI { nnn } => write!(f, "LD I, #{:03X}", nnn),
+            Instruction::JumpV0 { nnn } => write!(f, "JP V0, #{:03X}", nnn),
+            Instruction::Rand { x, kk } => write!(f, "RND V{:X}, #{:02X}", x, kk),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipIfKey { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LongLoadI => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes { n } => write!(f, "PLANE {}", n),
+            Instruction::LoadAudioPattern => write!(f, "AUDIO"),
+            Instruction::LoadDelayToVx { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitForKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::LoadDelayTimer { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::LoadSoundTimer { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIVx { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadAsciiAddress { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::LoadLargeFontAddress { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::LoadBcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::LoadPitch { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::StoreRegs { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegs { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreRplFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadRplFlags { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for opcode in 0..=0xFFFF {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(encode(&instruction), opcode, "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_fail_to_decode() {
+        for opcode in [
+            0x00E1, 0x00F0, 0x5001, 0x8008, 0x9001, 0xE000, 0xF100, 0xF0FF,
+        ] {
+            assert_eq!(decode(opcode), Err(DecodeError { opcode }));
+        }
+    }
+
+    #[test]
+    fn display_uses_standard_mnemonics() {
+        assert_eq!(decode(0x6012).unwrap().to_string(), "LD V0, #12");
+        assert_eq!(decode(0xD125).unwrap().to_string(), "DRW V1, V2, 5");
+        assert_eq!(decode(0x2ABC).unwrap().to_string(), "CALL #ABC");
+        assert_eq!(decode(0xF355).unwrap().to_string(), "LD [I], V3");
+    }
+}
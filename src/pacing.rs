@@ -0,0 +1,163 @@
+//! Wall clock pacing for frontends.
+//!
+//! The core only knows emulated frames (see timers). FramePacer keeps frames in step with a
+//! Clock, normally the host's SystemClock. It sleeps for most of the wait and spins for the last
+//! stretch, because a sleep can overshoot by a millisecond or more on a busy host, which shows up
+//! as frame-time jitter.
+
+use std::hint::spin_loop;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Duration of one 60hz frame
+pub const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+/// Part of each wait spent spinning instead of sleeping
+const SPIN_DURATION: Duration = Duration::from_micros(1_500);
+
+/// Source of the wall clock time a FramePacer keeps frames in step with
+pub trait Clock {
+    /// Returns the current time
+    fn now(&self) -> Instant;
+
+    /// Blocks until the deadline has passed
+    fn wait_until(&mut self, deadline: Instant);
+}
+
+/// The host's clock. Sleeps for most of a wait and spins for the rest.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wait_until(&mut self, deadline: Instant) {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        if wait > SPIN_DURATION {
+            sleep(wait - SPIN_DURATION);
+        }
+        while Instant::now() < deadline {
+            spin_loop();
+        }
+    }
+}
+
+/// Keeps emulated frames in step with the wall clock
+pub struct FramePacer<C: Clock = SystemClock> {
+    /// Clock the frames are paced by
+    pub clock: C,
+    /// Time at which the next frame is due
+    next_frame: Instant,
+    /// Most frames the caller may fall behind. When the host stalls for longer, the missed
+    /// frames are dropped instead of being run back to back.
+    pub max_catch_up: u32,
+}
+
+impl FramePacer {
+    /// Constructs a new FramePacer on the host's clock with the first frame due immediately
+    pub fn new(max_catch_up: u32) -> FramePacer {
+        FramePacer::with_clock(SystemClock, max_catch_up)
+    }
+}
+
+impl<C: Clock> FramePacer<C> {
+    /// Constructs a new FramePacer on the given clock with the first frame due immediately
+    pub fn with_clock(clock: C, max_catch_up: u32) -> FramePacer<C> {
+        FramePacer {
+            next_frame: clock.now(),
+            clock,
+            max_catch_up,
+        }
+    }
+
+    /// Waits until the next frame is due and schedules the one after it. Returns immediately if
+    /// the frame is already late, so the caller catches up by running frames back to back.
+    pub fn wait_for_next_frame(&mut self) {
+        let now = self.clock.now();
+        if now >= self.next_frame {
+            let behind = (now - self.next_frame).as_micros() / FRAME_DURATION.as_micros();
+            if behind >= self.max_catch_up as u128 {
+                self.next_frame = now;
+            }
+        } else {
+            self.clock.wait_until(self.next_frame);
+        }
+        self.next_frame += FRAME_DURATION;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock that only moves when waited on or stalled, and overshoots every wait
+    struct FakeClock {
+        now: Instant,
+        overshoot: Duration,
+        waits: u32,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.now
+        }
+
+        fn wait_until(&mut self, deadline: Instant) {
+            self.now = self.now.max(deadline + self.overshoot);
+            self.waits += 1;
+        }
+    }
+
+    /// Returns a pacer on a FakeClock that allows 4 frames of catch up
+    fn pacer(overshoot: Duration) -> FramePacer<FakeClock> {
+        let clock = FakeClock {
+            now: Instant::now(),
+            overshoot,
+            waits: 0,
+        };
+        FramePacer::with_clock(clock, 4)
+    }
+
+    #[test]
+    fn overshooting_waits_do_not_drift() {
+        let mut pacer = pacer(Duration::from_micros(900));
+        let start = pacer.clock.now;
+        for _ in 0..601 {
+            pacer.wait_for_next_frame();
+        }
+        // Frames stay on the 60hz grid. Only the last overshoot shows.
+        assert_eq!(pacer.clock.waits, 600);
+        assert_eq!(
+            pacer.clock.now - start,
+            FRAME_DURATION * 600 + Duration::from_micros(900)
+        );
+    }
+
+    #[test]
+    fn catches_up_short_stalls_and_drops_long_ones() {
+        // The frames missed in a stall of 3 frames are run back to back
+        let mut pacer = pacer_after_stall(3);
+        for _ in 0..3 {
+            pacer.wait_for_next_frame();
+        }
+        assert_eq!(pacer.clock.waits, 0);
+        pacer.wait_for_next_frame();
+        assert_eq!(pacer.clock.waits, 1);
+
+        // A stall of 10 frames is longer than max_catch_up, so only one frame is run late
+        let mut pacer = pacer_after_stall(10);
+        pacer.wait_for_next_frame();
+        assert_eq!(pacer.clock.waits, 0);
+        pacer.wait_for_next_frame();
+        assert_eq!(pacer.clock.waits, 1);
+    }
+
+    /// Returns a pacer that has run one frame, then stalled for the given number of frames
+    fn pacer_after_stall(frames: u32) -> FramePacer<FakeClock> {
+        let mut pacer = pacer(Duration::ZERO);
+        pacer.wait_for_next_frame();
+        pacer.clock.now += FRAME_DURATION * frames;
+        pacer
+    }
+}
@@ -0,0 +1,291 @@
+use crate::memory::{STANDARD_MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use std::fmt;
+use std::str::FromStr;
+
+/// Behaviour switches for the CHIP-8 opcodes whose semantics differ between interpreters.
+/// Documentation of the individual quirks pulled from Timendus' CHIP-8 test suite
+/// https://github.com/Timendus/chip8-test-suite
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quirks {
+    /// 8xy6/8xyE - When true, Vy is shifted and the result stored in Vx. When false, Vx is shifted
+    /// in place and Vy is ignored.
+    pub shift_uses_vy: bool,
+    /// Fx55/Fx65 - How far I is moved on by storing or loading registers.
+    pub load_store_increments_i: LoadStoreIncrement,
+    /// Bnnn - When true, the instruction is treated as BXnn and jumps to xnn + Vx.
+    /// When false, it jumps to nnn + V0.
+    pub jump_uses_vx: bool,
+    /// 8xy1/8xy2/8xy3 - When true, VF is reset to 0 after the logical operation.
+    pub logic_resets_vf: bool,
+    /// Dxyn - When true, sprites drawn past the edge of the screen are clipped. When false, they
+    /// wrap around to the opposite side. The starting coordinate always wraps.
+    pub clip_sprites: bool,
+    /// Dxyn - When true, the interpreter waits for the next 60hz display refresh after drawing.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP CHIP-8 interpreter (1977)
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: LoadStoreIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        display_wait: true,
+    };
+
+    /// CHIP-48 interpreter for the HP-48 calculators (1990)
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: LoadStoreIncrement::ByX,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    /// SUPER-CHIP 1.1 interpreter for the HP-48 calculators (1991)
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: LoadStoreIncrement::Unchanged,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        display_wait: false,
+    };
+
+    /// XO-CHIP, as implemented by Octo (2014)
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: LoadStoreIncrement::ByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        display_wait: false,
+    };
+}
+
+/// The default is the COSMAC VIP preset, the interpreter the ambiguous opcodes were first defined
+/// by. Before quirks were configurable, the interpreter instead shifted Vx in place, left I
+/// unchanged after Fx55/Fx65, kept VF after 8xy1/8xy2/8xy3, did not wait for the display and
+/// wrapped sprites at the screen edges. ROMs that rely on that should run as chip48 or schip.
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::COSMAC_VIP
+    }
+}
+
+/// Where Fx55/Fx65 leave I after storing or loading registers V0 through Vx
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LoadStoreIncrement {
+    /// I is unchanged
+    Unchanged,
+    /// I is moved on by X, pointing at the last register stored or loaded
+    ByX,
+    /// I is moved on by X + 1, pointing one past the last register stored or loaded
+    ByXPlusOne,
+}
+
+impl LoadStoreIncrement {
+    /// Returns how far I is moved on by storing or loading registers V0 through Vx
+    pub fn amount(&self, vx: u8) -> u16 {
+        match *self {
+            LoadStoreIncrement::Unchanged => 0,
+            LoadStoreIncrement::ByX => vx as u16,
+            LoadStoreIncrement::ByXPlusOne => vx as u16 + 1,
+        }
+    }
+
+    /// Returns the number this LoadStoreIncrement is stored as in save states and movies
+    pub fn flag(&self) -> u8 {
+        match *self {
+            LoadStoreIncrement::Unchanged => 0,
+            LoadStoreIncrement::ByXPlusOne => 1,
+            LoadStoreIncrement::ByX => 2,
+        }
+    }
+
+    /// Returns the LoadStoreIncrement stored as the number, the reverse of flag
+    pub fn from_flag(flag: u8) -> Option<LoadStoreIncrement> {
+        match flag {
+            0 => Some(LoadStoreIncrement::Unchanged),
+            1 => Some(LoadStoreIncrement::ByXPlusOne),
+            2 => Some(LoadStoreIncrement::ByX),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of this LoadStoreIncrement, as accepted by from_str
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LoadStoreIncrement::Unchanged => "unchanged",
+            LoadStoreIncrement::ByX => "x",
+            LoadStoreIncrement::ByXPlusOne => "x+1",
+        }
+    }
+}
+
+impl fmt::Display for LoadStoreIncrement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for LoadStoreIncrement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LoadStoreIncrement, String> {
+        match s.to_ascii_lowercase().replace(' ', "").as_str() {
+            "unchanged" | "0" => Ok(LoadStoreIncrement::Unchanged),
+            "x" | "+x" => Ok(LoadStoreIncrement::ByX),
+            "x+1" | "+x+1" => Ok(LoadStoreIncrement::ByXPlusOne),
+            _ => Err(format!(
+                "Unknown load/store increment '{}', expected unchanged, x or x+1",
+                s
+            )),
+        }
+    }
+}
+
+/// Changes to individual quirks of a platform preset, as a ROM that needs them records them.
+/// None leaves the preset's quirk as it is.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct QuirkOverrides {
+    pub shift_uses_vy: Option<bool>,
+    pub load_store_increments_i: Option<LoadStoreIncrement>,
+    pub jump_uses_vx: Option<bool>,
+    pub logic_resets_vf: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub display_wait: Option<bool>,
+}
+
+impl QuirkOverrides {
+    /// Applies the overrides to a set of quirks
+    pub fn apply(&self, quirks: &mut Quirks) {
+        fn apply<T: Copy>(quirk: &mut T, value: Option<T>) {
+            *quirk = value.unwrap_or(*quirk);
+        }
+        apply(&mut quirks.shift_uses_vy, self.shift_uses_vy);
+        apply(
+            &mut quirks.load_store_increments_i,
+            self.load_store_increments_i,
+        );
+        apply(&mut quirks.jump_uses_vx, self.jump_uses_vx);
+        apply(&mut quirks.logic_resets_vf, self.logic_resets_vf);
+        apply(&mut quirks.clip_sprites, self.clip_sprites);
+        apply(&mut quirks.display_wait, self.display_wait);
+    }
+}
+
+/// Named CHIP-8 platforms. Each platform selects a preset of Quirks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Platform {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    /// Returns the Quirks preset of this Platform
+    pub fn quirks(&self) -> Quirks {
+        match *self {
+            Platform::CosmacVip => Quirks::COSMAC_VIP,
+            Platform::Chip48 => Quirks::CHIP_48,
+            Platform::SuperChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+
+    /// Returns the size in bytes of the address space of this Platform
+    pub fn memory_size(&self) -> usize {
+        match *self {
+            Platform::XoChip => XO_CHIP_MEMORY_SIZE,
+            _ => STANDARD_MEMORY_SIZE,
+        }
+    }
+
+    /// Returns the short name of this Platform, as accepted by from_str
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Platform::CosmacVip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Platform, String> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "vip" | "cosmacvip" | "chip8" => Ok(Platform::CosmacVip),
+            "chip48" => Ok(Platform::Chip48),
+            "schip" | "superchip" => Ok(Platform::SuperChip),
+            "xochip" | "xo" => Ok(Platform::XoChip),
+            _ => Err(format!("Unknown platform '{}'", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosmac_vip_preset() {
+        let quirks = Platform::CosmacVip.quirks();
+        assert_eq!(quirks, Quirks::default());
+        assert!(quirks.shift_uses_vy && quirks.logic_resets_vf && quirks.display_wait);
+        assert_eq!(
+            quirks.load_store_increments_i,
+            LoadStoreIncrement::ByXPlusOne
+        );
+        assert!(!quirks.jump_uses_vx);
+        assert!(quirks.clip_sprites);
+    }
+
+    #[test]
+    fn chip_48_preset() {
+        let quirks = Platform::Chip48.quirks();
+        assert_eq!(quirks.load_store_increments_i, LoadStoreIncrement::ByX);
+        assert_eq!(quirks.load_store_increments_i.amount(3), 3);
+        assert!(!quirks.shift_uses_vy && !quirks.logic_resets_vf && !quirks.display_wait);
+        assert!(quirks.jump_uses_vx && quirks.clip_sprites);
+        assert_ne!(quirks, Quirks::SUPER_CHIP);
+    }
+
+    #[test]
+    fn super_chip_preset() {
+        let quirks = Platform::SuperChip.quirks();
+        assert_eq!(
+            quirks.load_store_increments_i,
+            LoadStoreIncrement::Unchanged
+        );
+        assert!(!quirks.shift_uses_vy && !quirks.logic_resets_vf && !quirks.display_wait);
+        assert!(quirks.jump_uses_vx && quirks.clip_sprites);
+        assert_eq!(Platform::SuperChip.memory_size(), STANDARD_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn xo_chip_preset() {
+        let quirks = Platform::XoChip.quirks();
+        assert_eq!(
+            quirks.load_store_increments_i,
+            LoadStoreIncrement::ByXPlusOne
+        );
+        assert!(quirks.shift_uses_vy);
+        assert!(!quirks.jump_uses_vx && !quirks.logic_resets_vf);
+        assert!(!quirks.clip_sprites && !quirks.display_wait);
+        assert_eq!(Platform::XoChip.memory_size(), XO_CHIP_MEMORY_SIZE);
+    }
+}
@@ -0,0 +1,239 @@
+//! Rewind buffer of per-frame machine snapshots.
+//!
+//! Snapshots are save states (see save_state). Only the newest one is kept in full. Every older
+//! frame is stored as the XOR of its state with the next newer state, and the zero runs are
+//! run-length encoded. Between two frames usually only a few registers and some display bytes
+//! change, so each frame costs a few dozen bytes instead of a full 12 KB state.
+
+use crate::cpu::CPU;
+use crate::save_state::SaveStateError;
+use std::collections::VecDeque;
+
+/// Ring buffer of the last frames of play
+pub struct RewindBuffer {
+    /// Maximum number of frames that can be rewound
+    capacity: usize,
+    /// Full save state of the newest frame
+    latest: Option<Vec<u8>>,
+    /// Encoded deltas to older frames. The back turns latest into the frame before it, and each
+    /// delta further to the front goes one frame further back.
+    deltas: VecDeque<Delta>,
+}
+
+/// Difference between a frame's state and the state of the frame after it
+enum Delta {
+    /// Run-length encoded XOR of the two states
+    Xor(Vec<u8>),
+    /// The full older state, used when the states differ in length (memory size changed)
+    Full(Vec<u8>),
+}
+
+impl RewindBuffer {
+    /// Constructs a new RewindBuffer that can go back up to capacity frames
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity,
+            latest: None,
+            deltas: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of frames that can currently be rewound
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// Returns true if there is no frame to rewind to
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Discards all snapshots
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Returns the approximate number of bytes used by the snapshots
+    pub fn memory_usage(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |state| state.len());
+        latest
+            + self
+                .deltas
+                .iter()
+                .map(|delta| match delta {
+                    Delta::Xor(bytes) | Delta::Full(bytes) => bytes.len(),
+                })
+                .sum::<usize>()
+    }
+
+    /// Records a snapshot of the CPU. Call once per frame.
+    pub fn push(&mut self, cpu: &CPU) {
+        let state = cpu.save_state();
+        if let Some(previous) = self.latest.take() {
+            let delta = if previous.len() == state.len() {
+                Delta::Xor(encode_delta(&previous, &state))
+            } else {
+                Delta::Full(previous)
+            };
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            if self.capacity > 0 {
+                self.deltas.push_back(delta);
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Restores the CPU to the state of up to frames snapshots ago, and drops the newer
+    /// snapshots. Returns the number of frames actually rewound.
+    ///
+    /// Fails if the snapshot cannot be restored, such as when the CPU uses a custom random source
+    /// that cannot restore its state. The CPU is then left as it was and the buffer is cleared.
+    pub fn rewind(&mut self, cpu: &mut CPU, frames: usize) -> Result<usize, SaveStateError> {
+        let mut state = match self.latest.take() {
+            Some(state) => state,
+            None => return Ok(0),
+        };
+        let frames = frames.min(self.deltas.len());
+        for _ in 0..frames {
+            match self.deltas.pop_back().unwrap() {
+                Delta::Xor(delta) => apply_delta(&mut state, &delta),
+                Delta::Full(older) => state = older,
+            }
+        }
+        if let Err(err) = cpu.load_state(&state) {
+            self.clear();
+            return Err(err);
+        }
+        self.latest = Some(state);
+        Ok(frames)
+    }
+}
+
+/// Encodes the XOR of two equal length states as a list of (zero run, literal run, literals)
+/// records. Run lengths are LEB128 varints.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < old.len() {
+        let zeros_start = pos;
+        while pos < old.len() && old[pos] == new[pos] {
+            pos += 1;
+        }
+        let literal_start = pos;
+        while pos < old.len() && old[pos] != new[pos] {
+            pos += 1;
+        }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, pos - literal_start);
+        out.extend((literal_start..pos).map(|index| old[index] ^ new[index]));
+    }
+    out
+}
+
+/// Applies a delta produced by encode_delta to a state, in place
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut input = delta;
+    while !input.is_empty() {
+        let zeros = read_varint(&mut input);
+        let literals = read_varint(&mut input);
+        pos += zeros;
+        for (byte, xor) in state[pos..pos + literals].iter_mut().zip(input.iter()) {
+            *byte ^= xor;
+        }
+        input = &input[literals..];
+        pos += literals;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = input[0];
+        *input = &input[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::execute;
+    use crate::quirks::Platform;
+    use crate::rng::RandomSource;
+    use crate::rom_loader::load_rom_bytes;
+
+    #[test]
+    fn rewinds_to_earlier_frames() {
+        let mut cpu = CPU::for_platform(Platform::Chip48);
+        // loop: ADD V0, 1; DRW V0, V0, 1; JP loop
+        load_rom_bytes(&mut cpu, &[0x70, 0x01, 0xD0, 0x01, 0x12, 0x00]).unwrap();
+        cpu.pc_reg = 0x200;
+
+        let mut rewind = RewindBuffer::new(4);
+        let mut history = Vec::new();
+        for _ in 0..10 {
+            rewind.push(&cpu);
+            history.push((cpu.gp_regs[0], *cpu.display_buffer.get_buffer()));
+            for _ in 0..3 {
+                execute(&mut cpu).unwrap();
+            }
+        }
+        assert_eq!(rewind.len(), 4);
+        assert!(rewind.memory_usage() < cpu.save_state().len() + 4 * 64);
+
+        assert_eq!(rewind.rewind(&mut cpu, 3).unwrap(), 3);
+        assert_eq!(cpu.gp_regs[0], history[6].0);
+        assert_eq!(cpu.display_buffer.get_buffer()[..], history[6].1[..]);
+        assert_eq!(rewind.rewind(&mut cpu, 5).unwrap(), 1);
+        assert_eq!(cpu.gp_regs[0], history[5].0);
+        assert_eq!(rewind.rewind(&mut cpu, 1).unwrap(), 0);
+    }
+
+    /// Random source that cannot be restored from a save state
+    struct Unrestorable;
+
+    impl RandomSource for Unrestorable {
+        fn next_u8(&mut self) -> u8 {
+            4
+        }
+
+        fn save(&self) -> Vec<u8> {
+            vec![0xFF]
+        }
+    }
+
+    #[test]
+    fn failed_rewind_leaves_the_cpu_alone() {
+        let mut cpu = CPU::for_platform(Platform::Chip48);
+        load_rom_bytes(&mut cpu, &[0x70, 0x01, 0x12, 0x00]).unwrap();
+        cpu.pc_reg = 0x200;
+        cpu.rng = Box::new(Unrestorable);
+
+        let mut rewind = RewindBuffer::new(4);
+        for _ in 0..3 {
+            rewind.push(&cpu);
+            execute(&mut cpu).unwrap();
+        }
+        assert!(rewind.rewind(&mut cpu, 1).is_err());
+        assert_eq!(cpu.gp_regs[0], 2);
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.rewind(&mut cpu, 1).unwrap(), 0);
+    }
+}
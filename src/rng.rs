@@ -0,0 +1,217 @@
+//! Random number sources for Cxkk.
+//!
+//! The CPU owns a boxed RandomSource, so the sequence of random bytes can be seeded, scripted for
+//! tests, or made to follow the original COSMAC VIP interpreter. Every source can serialize its
+//! state, which lets save states, rewind and movies reproduce the same bytes. The built-in sources
+//! are rebuilt from their state by restore, and other sources restore themselves by overriding
+//! RandomSource::restore.
+
+/// Tag bytes identifying the built-in sources in saved states
+const TAG_SEEDED: u8 = 0;
+const TAG_FIXED: u8 = 1;
+const TAG_COSMAC_VIP: u8 = 2;
+
+/// Source of the random bytes used by Cxkk
+pub trait RandomSource {
+    /// Returns the next random byte
+    fn next_u8(&mut self) -> u8;
+
+    /// Serializes the complete state of the source, starting with a tag byte that identifies it.
+    /// restore turns the result back into an identical source.
+    fn save(&self) -> Vec<u8>;
+
+    /// Returns the source to a state produced by its own save. Returns false, leaving the source
+    /// unchanged, if it cannot restore the data. The default restores nothing, which is enough for
+    /// the built-in sources.
+    fn restore(&mut self, _data: &[u8]) -> bool {
+        false
+    }
+}
+
+/// Reconstructs a built-in random source from the output of RandomSource::save. Returns None if
+/// the data does not describe a built-in source.
+pub fn restore(data: &[u8]) -> Option<Box<dyn RandomSource>> {
+    let (tag, state) = data.split_first()?;
+    match *tag {
+        TAG_SEEDED if state.len() == 4 => {
+            let mut seed = [0; 4];
+            seed.copy_from_slice(state);
+            Some(Box::new(SeededRng::new(u32::from_le_bytes(seed))))
+        }
+        TAG_FIXED if state.len() >= 4 => {
+            let mut position = [0; 4];
+            position.copy_from_slice(&state[..4]);
+            let mut source = FixedSequence::new(state[4..].to_vec());
+            source.position = u32::from_le_bytes(position) as usize;
+            Some(Box::new(source))
+        }
+        TAG_COSMAC_VIP if state.len() == 2 + 256 => {
+            let mut page = [0; 256];
+            page.copy_from_slice(&state[2..]);
+            Some(Box::new(CosmacVipRandom::new(
+                u16::from_le_bytes([state[0], state[1]]),
+                page,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// Xorshift32 pseudo random number generator. The whole state is a single word.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SeededRng {
+    state: u32,
+}
+
+impl SeededRng {
+    /// Constructs a new SeededRng from a seed. Xorshift cannot leave the all zero state, so a seed
+    /// of 0 is replaced with a fixed non-zero value.
+    pub fn new(seed: u32) -> SeededRng {
+        SeededRng {
+            state: if seed == 0 { 0x2545_F491 } else { seed },
+        }
+    }
+
+    /// Constructs a new SeededRng seeded from the operating system
+    pub fn from_entropy() -> SeededRng {
+        SeededRng::new(rand::random())
+    }
+
+    /// Returns the current state. Constructing a SeededRng with the state as seed continues the
+    /// same sequence.
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+}
+
+impl RandomSource for SeededRng {
+    fn next_u8(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 24) as u8
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut data = vec![TAG_SEEDED];
+        data.extend_from_slice(&self.state.to_le_bytes());
+        data
+    }
+}
+
+/// Plays back a fixed list of bytes, starting over after the last one. Meant for tests that need
+/// to know exactly what Cxkk returns. An empty list always returns 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixedSequence {
+    values: Vec<u8>,
+    position: usize,
+}
+
+impl FixedSequence {
+    /// Constructs a new FixedSequence that returns the given values in order
+    pub fn new(values: Vec<u8>) -> FixedSequence {
+        FixedSequence {
+            values,
+            position: 0,
+        }
+    }
+}
+
+impl RandomSource for FixedSequence {
+    fn next_u8(&mut self) -> u8 {
+        if self.values.is_empty() {
+            return 0;
+        }
+        let value = self.values[self.position % self.values.len()];
+        self.position = (self.position + 1) % self.values.len();
+        value
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut data = vec![TAG_FIXED];
+        data.extend_from_slice(&(self.position as u32).to_le_bytes());
+        data.extend_from_slice(&self.values);
+        data
+    }
+}
+
+/// Emulation of the random routine of the COSMAC VIP CHIP-8 interpreter. The VIP keeps a 16-bit
+/// seed in register R9. For every Cxkk the low byte of R9 is incremented and used as an index
+/// into the interpreter's own code page, and the byte found there is added to the high byte of
+/// R9. The new high byte is the random number.
+///
+/// The sequence therefore depends on the bytes of that page. For a bit-exact VIP sequence,
+/// construct the source with page 0x0100-0x01FF of the VIP interpreter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CosmacVipRandom {
+    r9: u16,
+    page: [u8; 256],
+}
+
+impl CosmacVipRandom {
+    /// Constructs a new CosmacVipRandom with the given initial R9 and table page
+    pub fn new(seed: u16, page: [u8; 256]) -> CosmacVipRandom {
+        CosmacVipRandom { r9: seed, page }
+    }
+
+    /// Constructs a new CosmacVipRandom that uses page 0x0100-0x01FF of the given memory as its
+    /// table. Memory shorter than 0x200 bytes is padded with zeros.
+    pub fn from_memory(seed: u16, mem: &[u8]) -> CosmacVipRandom {
+        let mut page = [0; 256];
+        for (value, byte) in page.iter_mut().zip(mem.iter().skip(0x100)) {
+            *value = *byte;
+        }
+        CosmacVipRandom::new(seed, page)
+    }
+}
+
+impl RandomSource for CosmacVipRandom {
+    fn next_u8(&mut self) -> u8 {
+        let low = (self.r9 as u8).wrapping_add(1);
+        let high = ((self.r9 >> 8) as u8).wrapping_add(self.page[low as usize]);
+        self.r9 = ((high as u16) << 8) | low as u16;
+        high
+    }
+
+    fn save(&self) -> Vec<u8> {
+        let mut data = vec![TAG_COSMAC_VIP];
+        data.extend_from_slice(&self.r9.to_le_bytes());
+        data.extend_from_slice(&self.page);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_restore_mid_sequence() {
+        let mut page = [0; 256];
+        for (index, value) in page.iter_mut().enumerate() {
+            *value = (index as u8).wrapping_mul(37);
+        }
+        let mut sources: Vec<Box<dyn RandomSource>> = vec![
+            Box::new(SeededRng::new(1234)),
+            Box::new(FixedSequence::new(vec![1, 2, 3])),
+            Box::new(CosmacVipRandom::new(0x1234, page)),
+        ];
+        for source in sources.iter_mut() {
+            source.next_u8();
+            let mut restored = restore(&source.save()).unwrap();
+            let expected: Vec<u8> = (0..10).map(|_| source.next_u8()).collect();
+            let actual: Vec<u8> = (0..10).map(|_| restored.next_u8()).collect();
+            assert_eq!(expected, actual);
+        }
+        assert!(restore(&[9, 9]).is_none());
+    }
+
+    #[test]
+    fn fixed_sequence_cycles() {
+        let mut source = FixedSequence::new(vec![5, 6]);
+        let values: Vec<u8> = (0..5).map(|_| source.next_u8()).collect();
+        assert_eq!(values, vec![5, 6, 5, 6, 5]);
+    }
+}
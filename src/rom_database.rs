@@ -0,0 +1,322 @@
+//! Compatibility database of known ROMs.
+//!
+//! Maps the SHA-1 of a ROM image to its title and the settings it needs to run correctly. A table
+//! of ROMs is built into the interpreter, and can be extended or overridden from a JSON file of
+//! the same format:
+//!
+//! ```text
+//! {
+//!     "<40 hex digits of the ROM's SHA-1>": {
+//!         "title": "Space Invaders",
+//!         "author": "David Winter",
+//!         "platform": "schip",
+//!         "quirks": { "shift_uses_vy": false, "display_wait": false },
+//!         "ipf": 15,
+//!         "keys": "4/6 move, 5 fire"
+//!     }
+//! }
+//! ```
+//!
+//! Only title is required. quirks overrides individual quirks of the platform preset, using the
+//! names of the fields of Quirks. load_store_increments_i is "unchanged", "x" or "x+1", or
+//! false or true for unchanged or x+1.
+
+use crate::cpu::CPU;
+use crate::json::{self, Value};
+use crate::quirks::{LoadStoreIncrement, Platform, QuirkOverrides};
+use crate::rom_loader::rom_sha1_hex;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// Database built into the interpreter
+const BUILTIN_DATABASE: &str = include_str!("rom_database.json");
+
+/// What the database knows about a ROM
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub author: Option<String>,
+    /// Platform the ROM was written for
+    pub platform: Option<Platform>,
+    /// Quirks the ROM needs that differ from the preset of its platform. They also apply when
+    /// the ROM is run on another platform.
+    pub quirks: QuirkOverrides,
+    /// Recommended speed, in instructions per 60hz frame
+    pub instructions_per_frame: Option<u32>,
+    /// Short description of the controls, in CHIP-8 keys
+    pub key_hints: Option<String>,
+}
+
+impl RomInfo {
+    /// Configures the CPU to run this ROM. Sets the platform, quirks and speed the database
+    /// knows of, and leaves the rest as it was.
+    pub fn configure(&self, cpu: &mut CPU) {
+        if let Some(platform) = self.platform {
+            cpu.set_platform(platform);
+        }
+        self.quirks.apply(&mut cpu.quirks);
+        if let Some(ipf) = self.instructions_per_frame {
+            cpu.clock.instructions_per_frame = ipf.max(1);
+        }
+    }
+}
+
+/// Errors reading a ROM database file
+#[derive(Debug)]
+pub enum RomDatabaseError {
+    /// Reading the file failed
+    Io(io::Error),
+    /// The file is not valid JSON. Carries the 1-based line number.
+    Json { line: usize, message: String },
+    /// An entry is valid JSON but not a valid ROM entry
+    Entry { sha1: String, message: String },
+}
+
+impl fmt::Display for RomDatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomDatabaseError::Io(err) => write!(f, "{}", err),
+            RomDatabaseError::Json { line, message } => write!(f, "line {}: {}", line, message),
+            RomDatabaseError::Entry { sha1, message } => write!(f, "ROM {}: {}", sha1, message),
+        }
+    }
+}
+
+impl std::error::Error for RomDatabaseError {}
+
+impl From<io::Error> for RomDatabaseError {
+    fn from(err: io::Error) -> RomDatabaseError {
+        RomDatabaseError::Io(err)
+    }
+}
+
+/// Known ROMs, keyed by lowercase SHA-1 hex digest
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomDatabase {
+    entries: HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    /// Constructs an empty database
+    pub fn new() -> RomDatabase {
+        RomDatabase::default()
+    }
+
+    /// Constructs the database built into the interpreter
+    pub fn builtin() -> RomDatabase {
+        let mut database = RomDatabase::new();
+        database
+            .extend_from_json(BUILTIN_DATABASE)
+            .expect("built in ROM database is valid");
+        database
+    }
+
+    /// Returns the number of ROMs in the database
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the database has no ROMs
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds or replaces the entry for the ROM with the given SHA-1 hex digest
+    pub fn insert(&mut self, sha1: &str, info: RomInfo) {
+        self.entries.insert(sha1.to_ascii_lowercase(), info);
+    }
+
+    /// Returns the entry for the ROM with the given SHA-1 hex digest
+    pub fn get(&self, sha1: &str) -> Option<&RomInfo> {
+        self.entries.get(&sha1.to_ascii_lowercase())
+    }
+
+    /// Returns the entry for a ROM image
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.get(&rom_sha1_hex(rom))
+    }
+
+    /// Adds the entries of a JSON database. Entries for ROMs already in the database replace them.
+    /// Nothing is added if any entry is invalid.
+    pub fn extend_from_json(&mut self, text: &str) -> Result<(), RomDatabaseError> {
+        let document = json::parse(text).map_err(|err| RomDatabaseError::Json {
+            line: err.line,
+            message: err.message,
+        })?;
+        let members = match document {
+            Value::Object(members) => members,
+            other => {
+                return Err(RomDatabaseError::Json {
+                    line: 1,
+                    message: format!("Expected an object of ROMs, found {}", other.type_name()),
+                })
+            }
+        };
+
+        let mut entries = Vec::with_capacity(members.len());
+        for (sha1, entry) in members {
+            if sha1.len() != 40 || !sha1.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(RomDatabaseError::Entry {
+                    sha1,
+                    message: String::from("key is not a SHA-1 hex digest"),
+                });
+            }
+            match parse_entry(&entry) {
+                Ok(info) => entries.push((sha1, info)),
+                Err(message) => return Err(RomDatabaseError::Entry { sha1, message }),
+            }
+        }
+        for (sha1, info) in entries {
+            self.insert(&sha1, info);
+        }
+        Ok(())
+    }
+
+    /// Adds the entries of a JSON database file
+    pub fn extend_from_file(&mut self, path: &str) -> Result<(), RomDatabaseError> {
+        self.extend_from_json(&fs::read_to_string(path)?)
+    }
+}
+
+/// Converts a JSON entry into a RomInfo
+fn parse_entry(entry: &Value) -> Result<RomInfo, String> {
+    let members = match entry {
+        Value::Object(members) => members,
+        other => return Err(format!("expected an object, found {}", other.type_name())),
+    };
+
+    let mut info = RomInfo {
+        title: String::new(),
+        author: None,
+        platform: None,
+        quirks: QuirkOverrides::default(),
+        instructions_per_frame: None,
+        key_hints: None,
+    };
+    let mut quirk_overrides = None;
+    for (key, value) in members {
+        match key.as_str() {
+            "title" => info.title = string(key, value)?,
+            "author" => info.author = Some(string(key, value)?),
+            "platform" => info.platform = Some(string(key, value)?.parse()?),
+            "quirks" => quirk_overrides = Some(value),
+            "ipf" => match value {
+                Value::Number(n) if n.fract() == 0.0 && *n >= 1.0 && *n <= u32::MAX as f64 => {
+                    info.instructions_per_frame = Some(*n as u32)
+                }
+                _ => return Err(String::from("ipf must be a positive whole number")),
+            },
+            "keys" => info.key_hints = Some(string(key, value)?),
+            _ => return Err(format!("unknown field '{}'", key)),
+        }
+    }
+    if info.title.is_empty() {
+        return Err(String::from("title is missing"));
+    }
+
+    if let Some(overrides) = quirk_overrides {
+        let overrides = match overrides {
+            Value::Object(members) => members,
+            other => {
+                return Err(format!(
+                    "quirks must be an object, not {}",
+                    other.type_name()
+                ))
+            }
+        };
+        for (name, value) in overrides {
+            let quirks = &mut info.quirks;
+            if name == "load_store_increments_i" {
+                quirks.load_store_increments_i = Some(match value {
+                    Value::Bool(false) => LoadStoreIncrement::Unchanged,
+                    Value::Bool(true) => LoadStoreIncrement::ByXPlusOne,
+                    Value::String(increment) => increment.parse()?,
+                    _ => return Err(format!("quirk '{}' must be a string or boolean", name)),
+                });
+                continue;
+            }
+            let value = match value {
+                Value::Bool(value) => *value,
+                _ => return Err(format!("quirk '{}' must be true or false", name)),
+            };
+            match name.as_str() {
+                "shift_uses_vy" => quirks.shift_uses_vy = Some(value),
+                "jump_uses_vx" => quirks.jump_uses_vx = Some(value),
+                "logic_resets_vf" => quirks.logic_resets_vf = Some(value),
+                "clip_sprites" => quirks.clip_sprites = Some(value),
+                "display_wait" => quirks.display_wait = Some(value),
+                _ => return Err(format!("unknown quirk '{}'", name)),
+            }
+        }
+    }
+    Ok(info)
+}
+
+/// Returns a string field of an entry
+fn string(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(string) => Ok(string.clone()),
+        other => Err(format!(
+            "{} must be a string, not {}",
+            key,
+            other.type_name()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DisplayMode;
+    use crate::memory::XO_CHIP_MEMORY_SIZE;
+    use crate::quirks::Quirks;
+
+    const SHA1: &str = "A9993E364706816ABA3E25717850C26C9CD0D89D";
+
+    #[test]
+    fn builtin_database_knows_the_bundled_roms() {
+        let database = RomDatabase::builtin();
+        let rom = std::fs::read("roms/delay_timer_test.ch8").unwrap();
+        let info = database.lookup(&rom).unwrap();
+        assert_eq!(info.title, "Delay Timer Test");
+        assert_eq!(info.platform, Some(Platform::CosmacVip));
+        assert!(database.lookup(b"not a rom").is_none());
+    }
+
+    #[test]
+    fn extends_and_configures() {
+        let mut database = RomDatabase::builtin();
+        let builtin = database.len();
+        database
+            .extend_from_json(&format!(
+                r#"{{ "{}": {{ "title": "Test", "platform": "xochip", "ipf": 100,
+                    "quirks": {{ "clip_sprites": true }} }} }}"#,
+                SHA1
+            ))
+            .unwrap();
+        assert_eq!(database.len(), builtin + 1);
+
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        database.lookup(b"abc").unwrap().configure(&mut cpu);
+        assert_eq!(cpu.mem.mem.len(), XO_CHIP_MEMORY_SIZE);
+        assert_eq!(cpu.clock.instructions_per_frame, 100);
+        assert_eq!(
+            cpu.quirks,
+            Quirks {
+                clip_sprites: true,
+                ..Quirks::XO_CHIP
+            }
+        );
+
+        let error = database
+            .extend_from_json(&format!(
+                r#"{{ "{}": {{ "title": "T", "ipf": -1 }} }}"#,
+                SHA1
+            ))
+            .unwrap_err();
+        assert!(matches!(error, RomDatabaseError::Entry { .. }));
+        assert_eq!(database.get(SHA1).unwrap().title, "Test");
+    }
+}
@@ -1,11 +1,639 @@
-use std::fs::File;
+//! Loading ROM images into memory.
+//!
+//! RomLoader reads a ROM from a file, any reader or a byte slice, checks that it fits in the
+//! memory of the CPU's platform and copies it to the load address. Loading returns RomMetadata
+//! describing the ROM, and fails with a RomLoadError rather than truncating.
+//!
+//! RomCollection lists and reads the ROMs in a zip archive or a directory, such as a community
+//! ROM pack. On the command line a ROM inside a collection is named as collection:entry, where
+//! entry is the path inside the collection, the file name, or the index in the listing:
+//!
+//! ```text
+//! packs/chip8-roms.zip:games/Pong.ch8
+//! packs/chip8-roms.zip:Pong.ch8
+//! packs/chip8-roms.zip:12
+//! ~/roms:schip/Blinky.sc8
+//! ```
+
+use std::fmt;
+use std::fs::{self, File};
 use std::io;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
 use crate::cpu::CPU;
+use crate::memory::XO_CHIP_MEMORY_SIZE;
+use crate::quirks::Platform;
+use crate::rom_database::{RomDatabase, RomInfo};
+use crate::zip::{self, ZipEntry};
+
+/// Address CHIP-8 programs are loaded at and start from
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
+
+/// Address programs for the ETI-660 are loaded at and start from
+pub const ETI_660_LOAD_ADDRESS: u16 = 0x600;
+
+/// Largest ROM any platform can load, the size of the XO-CHIP address space. Zip entries are not
+/// extracted past it.
+pub const MAX_ROM_SIZE: usize = XO_CHIP_MEMORY_SIZE;
+
+/// Errors loading a ROM
+#[derive(Debug)]
+pub enum RomLoadError {
+    /// Reading the ROM failed
+    Io(io::Error),
+    /// The ROM has no bytes
+    Empty,
+    /// The ROM does not fit in memory between the load address and the end of memory
+    TooLarge { size: usize, max_size: usize },
+    /// The load address is outside of memory
+    BadLoadAddress(u16),
+    /// A zip archive is malformed or uses features that are not supported
+    Archive(String),
+    /// A collection has no ROM of the given name or index
+    NotFound(String),
+}
+
+impl fmt::Display for RomLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomLoadError::Io(err) => write!(f, "{}", err),
+            RomLoadError::Empty => write!(f, "ROM is empty"),
+            RomLoadError::TooLarge { size, max_size } => write!(
+                f,
+                "ROM is {} bytes, but only {} bytes fit in memory",
+                size, max_size
+            ),
+            RomLoadError::BadLoadAddress(addr) => {
+                write!(f, "load address {:#05X} is outside of memory", addr)
+            }
+            RomLoadError::Archive(message) => write!(f, "{}", message),
+            RomLoadError::NotFound(name) => write!(f, "no ROM named '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for RomLoadError {}
+
+impl From<io::Error> for RomLoadError {
+    fn from(err: io::Error) -> RomLoadError {
+        RomLoadError::Io(err)
+    }
+}
+
+/// Description of a loaded ROM
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomMetadata {
+    /// Size of the ROM in bytes
+    pub size: usize,
+    /// Address the ROM was loaded at
+    pub load_address: u16,
+    /// SHA-1 digest, as recorded in the CPU
+    pub sha1: [u8; 20],
+    /// Platform detected from the file extension, if the ROM was loaded from a file
+    pub platform: Option<Platform>,
+}
+
+impl RomMetadata {
+    /// Returns the SHA-1 digest as 40 lowercase hex digits
+    pub fn sha1_hex(&self) -> String {
+        to_hex(&self.sha1)
+    }
+}
+
+/// Loads ROMs into a CPU
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RomLoader {
+    /// Address the ROM is copied to, and the program counter is set to
+    pub load_address: u16,
+    /// Switch the CPU to the platform detected from the file extension when loading a file
+    pub detect_platform: bool,
+}
+
+impl Default for RomLoader {
+    fn default() -> RomLoader {
+        RomLoader {
+            load_address: DEFAULT_LOAD_ADDRESS,
+            detect_platform: true,
+        }
+    }
+}
+
+impl RomLoader {
+    /// Constructs a RomLoader that loads at the given address and detects the platform of files
+    pub fn new(load_address: u16) -> RomLoader {
+        RomLoader {
+            load_address,
+            ..RomLoader::default()
+        }
+    }
+
+    /// Copies a ROM image into memory at the load address and points the program counter at it.
+    /// The hash of the ROM is recorded in the CPU. Memory is untouched if the ROM does not fit.
+    pub fn load_bytes(&self, cpu: &mut CPU, rom: &[u8]) -> Result<RomMetadata, RomLoadError> {
+        self.check_fits(rom, cpu.mem.mem.len())?;
+        let start = self.load_address as usize;
+        cpu.mem.mem[start..start + rom.len()].copy_from_slice(rom);
+        cpu.pc_reg = self.load_address;
+        cpu.rom_sha1 = rom_sha1(rom);
+        Ok(RomMetadata {
+            size: rom.len(),
+            load_address: self.load_address,
+            sha1: cpu.rom_sha1,
+            platform: None,
+        })
+    }
+
+    /// Checks a ROM image fits in memory of the given size at the load address
+    fn check_fits(&self, rom: &[u8], memory_size: usize) -> Result<(), RomLoadError> {
+        let start = self.load_address as usize;
+        if start >= memory_size {
+            return Err(RomLoadError::BadLoadAddress(self.load_address));
+        }
+        let max_size = memory_size - start;
+        if rom.is_empty() {
+            return Err(RomLoadError::Empty);
+        }
+        if rom.len() > max_size {
+            return Err(RomLoadError::TooLarge {
+                size: rom.len(),
+                max_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Loads a ROM image, switching the CPU to the detected platform if detect_platform is set.
+    /// The CPU is only switched once the ROM is known to fit the platform's memory.
+    fn load_detected(
+        &self,
+        cpu: &mut CPU,
+        rom: &[u8],
+        platform: Option<Platform>,
+    ) -> Result<RomMetadata, RomLoadError> {
+        if let (true, Some(platform)) = (self.detect_platform, platform) {
+            self.check_fits(rom, platform.memory_size())?;
+            cpu.set_platform(platform);
+        }
+        let mut metadata = self.load_bytes(cpu, rom)?;
+        metadata.platform = platform;
+        Ok(metadata)
+    }
+
+    /// Reads a ROM to the end and loads it. Short reads are retried until the end of the ROM.
+    pub fn load_reader<R: Read>(
+        &self,
+        cpu: &mut CPU,
+        mut reader: R,
+    ) -> Result<RomMetadata, RomLoadError> {
+        let mut rom = Vec::new();
+        reader.read_to_end(&mut rom)?;
+        self.load_bytes(cpu, &rom)
+    }
+
+    /// Loads a ROM file. If detect_platform is set and the extension names a platform, the CPU is
+    /// switched to that platform once the ROM has been read and fits.
+    pub fn load_file<P: AsRef<Path>>(
+        &self,
+        cpu: &mut CPU,
+        path: P,
+    ) -> Result<RomMetadata, RomLoadError> {
+        let path = path.as_ref();
+        let mut rom = Vec::new();
+        File::open(path)?.read_to_end(&mut rom)?;
+        self.load_detected(cpu, &rom, platform_from_extension(path))
+    }
+
+    /// Loads a ROM from a collection. If detect_platform is set and the extension of the entry
+    /// names a platform, the CPU is switched to that platform once the ROM has been read and fits.
+    pub fn load_entry(
+        &self,
+        cpu: &mut CPU,
+        collection: &RomCollection,
+        index: usize,
+    ) -> Result<RomMetadata, RomLoadError> {
+        let rom = collection.read(index)?;
+        self.load_detected(
+            cpu,
+            &rom,
+            platform_from_extension(&collection.entries[index].name),
+        )
+    }
+
+    /// Looks a ROM image up in the database and configures the CPU for it, then loads it as
+    /// load_bytes does. Returns what the database knows about the ROM, if anything. The CPU is
+    /// only configured once the ROM is known to fit the memory of the platform it needs.
+    pub fn load_known_bytes<'a>(
+        &self,
+        cpu: &mut CPU,
+        rom: &[u8],
+        database: &'a RomDatabase,
+    ) -> Result<(RomMetadata, Option<&'a RomInfo>), RomLoadError> {
+        let info = database.lookup(rom);
+        if let Some(info) = info {
+            let memory_size = info
+                .platform
+                .map_or(cpu.mem.mem.len(), |platform| platform.memory_size());
+            self.check_fits(rom, memory_size)?;
+            info.configure(cpu);
+        }
+        Ok((self.load_bytes(cpu, rom)?, info))
+    }
+}
+
+/// Loads a ROM file at 0x200, switching the CPU to the platform its extension names
+pub fn load_rom_file<P: AsRef<Path>>(cpu: &mut CPU, path: P) -> Result<RomMetadata, RomLoadError> {
+    RomLoader::default().load_file(cpu, path)
+}
+
+/// Copies a ROM image, such as the output of the assembler, into memory at 0x200
+pub fn load_rom_bytes(cpu: &mut CPU, rom: &[u8]) -> Result<RomMetadata, RomLoadError> {
+    RomLoader::default().load_bytes(cpu, rom)
+}
+
+/// Returns the platform a ROM file extension stands for. .c8x (CHIP-8X) ROMs run with the
+/// COSMAC VIP preset; the CHIP-8X colour instructions are not emulated.
+pub fn platform_from_extension<P: AsRef<Path>>(path: P) -> Option<Platform> {
+    let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "ch8" | "c8x" => Some(Platform::CosmacVip),
+        "sc8" => Some(Platform::SuperChip),
+        "xo8" => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+/// A ROM listed in a RomCollection
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomEntry {
+    /// Path of the ROM inside the collection, with / separators
+    pub name: String,
+    /// Size of the ROM in bytes
+    pub size: usize,
+}
+
+/// Where the ROMs of a RomCollection are read from
+enum CollectionSource {
+    /// Contents of a zip archive, and the zip entry of each ROM
+    Zip(Vec<u8>, Vec<ZipEntry>),
+    /// Directory the ROM names are relative to
+    Directory(PathBuf),
+}
+
+/// The ROMs in a zip archive or a directory and its subdirectories. Only files with a ROM
+/// extension (.ch8, .sc8, .xo8, .c8x) are listed, sorted by name.
+pub struct RomCollection {
+    source: CollectionSource,
+    entries: Vec<RomEntry>,
+}
 
-pub fn load_rom_file(cpu: &mut CPU, file_path: &str) -> io::Result<()> {
-    let mut in_file = File::open(file_path)?;
-    in_file.read(&mut cpu.mem.mem[0x200..])?;
+impl RomCollection {
+    /// Returns true if the path is a directory or a .zip file, rather than a single ROM
+    pub fn is_collection<P: AsRef<Path>>(path: P) -> bool {
+        let path = path.as_ref();
+        path.is_dir() || has_extension(path, "zip")
+    }
+
+    /// Opens a zip archive or a directory and lists the ROMs in it
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<RomCollection, RomLoadError> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let mut entries = Vec::new();
+            list_directory(path, "", &mut entries)?;
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            return Ok(RomCollection {
+                source: CollectionSource::Directory(path.to_path_buf()),
+                entries,
+            });
+        }
+
+        let archive = fs::read(path)?;
+        let mut zip_entries: Vec<ZipEntry> = zip::entries(&archive)
+            .map_err(|err| RomLoadError::Archive(format!("{}: {}", path.display(), err)))?
+            .into_iter()
+            .filter(|entry| !entry.is_directory() && is_rom_name(&entry.name))
+            .collect();
+        zip_entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(RomCollection {
+            entries: zip_entries
+                .iter()
+                .map(|entry| RomEntry {
+                    name: entry.name.clone(),
+                    size: entry.size,
+                })
+                .collect(),
+            source: CollectionSource::Zip(archive, zip_entries),
+        })
+    }
+
+    /// Returns the ROMs in the collection
+    pub fn entries(&self) -> &[RomEntry] {
+        &self.entries
+    }
+
+    /// Returns the index of the ROM with the given path inside the collection. Failing that,
+    /// matches the file name alone, ignoring case, if only one ROM has that name.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        if let Some(index) = self.entries.iter().position(|entry| entry.name == name) {
+            return Some(index);
+        }
+        let mut matches = self.entries.iter().enumerate().filter(|(_, entry)| {
+            let file_name = entry.name.rsplit('/').next().unwrap_or(&entry.name);
+            file_name.eq_ignore_ascii_case(name)
+        });
+        match (matches.next(), matches.next()) {
+            (Some((index, _)), None) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the index of the ROM named by path or file name as find does, or by its index
+    pub fn select(&self, name_or_index: &str) -> Result<usize, RomLoadError> {
+        self.find(name_or_index)
+            .or_else(|| {
+                name_or_index
+                    .parse()
+                    .ok()
+                    .filter(|index| *index < self.entries.len())
+            })
+            .ok_or_else(|| RomLoadError::NotFound(String::from(name_or_index)))
+    }
+
+    /// Reads the ROM at the given index
+    pub fn read(&self, index: usize) -> Result<Vec<u8>, RomLoadError> {
+        let entry = self
+            .entries
+            .get(index)
+            .ok_or_else(|| RomLoadError::NotFound(index.to_string()))?;
+        match &self.source {
+            CollectionSource::Zip(archive, zip_entries) => {
+                zip::extract(archive, &zip_entries[index], MAX_ROM_SIZE)
+                    .map_err(RomLoadError::Archive)
+            }
+            CollectionSource::Directory(root) => Ok(fs::read(root.join(&entry.name))?),
+        }
+    }
+}
+
+/// Splits a ROM path of the form collection:entry into the collection path and the entry. The
+/// collection is a path ending in .zip, or a directory. Any other path is returned whole.
+pub fn split_collection_path(path: &str) -> (&str, Option<&str>) {
+    for (index, _) in path.match_indices(':') {
+        let collection = &path[..index];
+        if has_extension(Path::new(collection), "zip") || Path::new(collection).is_dir() {
+            return (collection, Some(&path[index + 1..]));
+        }
+    }
+    (path, None)
+}
+
+/// Adds the ROMs in a directory and its subdirectories to entries, named relative to the root
+fn list_directory(dir: &Path, prefix: &str, entries: &mut Vec<RomEntry>) -> io::Result<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let name = format!("{}{}", prefix, dir_entry.file_name().to_string_lossy());
+        let file_type = dir_entry.file_type()?;
+        if file_type.is_dir() {
+            list_directory(&dir_entry.path(), &format!("{}/", name), entries)?;
+        } else if is_rom_name(&name) {
+            entries.push(RomEntry {
+                name,
+                size: dir_entry.metadata()?.len() as usize,
+            });
+        }
+    }
     Ok(())
 }
+
+/// Returns true if a file name has a ROM extension
+fn is_rom_name(name: &str) -> bool {
+    platform_from_extension(name).is_some()
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Returns the SHA-1 digest of a ROM image. This is the hash ROM databases, configuration files,
+/// save states and movies use to identify a ROM.
+pub fn rom_sha1(rom: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+
+    // Pad with a 1 bit, zeros, and the message length in bits, to a multiple of 64 bytes
+    let mut message = rom.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((rom.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, state) in digest.chunks_mut(4).zip(h.iter()) {
+        bytes.copy_from_slice(&state.to_be_bytes());
+    }
+    digest
+}
+
+/// Returns the SHA-1 digest of a ROM image as 40 lowercase hex digits
+pub fn rom_sha1_hex(rom: &[u8]) -> String {
+    to_hex(&rom_sha1(rom))
+}
+
+/// Returns bytes as lowercase hex digits
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DisplayMode;
+
+    /// Reader that returns one byte per read call
+    struct ShortReads<'a>(&'a [u8]);
+
+    impl<'a> Read for ShortReads<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((byte, rest)) if !buf.is_empty() => {
+                    buf[0] = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn loads_and_validates_roms() {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        let metadata = RomLoader::new(ETI_660_LOAD_ADDRESS)
+            .load_reader(&mut cpu, ShortReads(b"abc"))
+            .unwrap();
+        assert_eq!(&cpu.mem.mem[0x600..0x603], b"abc");
+        assert_eq!((cpu.pc_reg, metadata.size), (0x600, 3));
+        assert_eq!(cpu.rom_sha1, metadata.sha1);
+        assert_eq!(
+            metadata.sha1_hex(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+
+        let max_size = cpu.mem.mem.len() - 0x200;
+        assert!(load_rom_bytes(&mut cpu, &vec![0xAA; max_size]).is_ok());
+        assert!(matches!(
+            load_rom_bytes(&mut cpu, &vec![0xBB; max_size + 1]),
+            Err(RomLoadError::TooLarge { size, max_size: max }) if size == max_size + 1 && max == max_size
+        ));
+        assert_eq!(cpu.mem.mem[0x200], 0xAA);
+        assert!(matches!(
+            load_rom_bytes(&mut cpu, &[]),
+            Err(RomLoadError::Empty)
+        ));
+        assert!(matches!(
+            RomLoader::new(0xF000).load_bytes(&mut cpu, b"abc"),
+            Err(RomLoadError::BadLoadAddress(0xF000))
+        ));
+    }
+
+    #[test]
+    fn lists_and_loads_collections() {
+        let zip = RomCollection::open("roms/test_pack.zip").unwrap();
+        let names: Vec<&str> = zip.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "delay_timer_test.ch8",
+                "pack/IBM_Logo.ch8",
+                "pack/test_opcode.ch8"
+            ]
+        );
+        let dir = RomCollection::open("roms").unwrap();
+        assert!(dir.find("test_opcode.ch8").is_some());
+
+        // Stored, fixed Huffman and dynamic Huffman entries
+        for name in [
+            "delay_timer_test.ch8",
+            "ibm_logo.ch8",
+            "pack/test_opcode.ch8",
+        ] {
+            let index = zip.select(name).unwrap();
+            let file_name = zip.entries()[index].name.rsplit('/').next().unwrap();
+            let expected = std::fs::read(format!("roms/{}", file_name)).unwrap();
+            assert_eq!(zip.read(index).unwrap(), expected);
+            assert_eq!(dir.read(dir.find(file_name).unwrap()).unwrap(), expected);
+        }
+
+        assert_eq!(zip.select("2").unwrap(), 2);
+        assert!(matches!(zip.select("3"), Err(RomLoadError::NotFound(_))));
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        let metadata = RomLoader::default().load_entry(&mut cpu, &zip, 1).unwrap();
+        assert_eq!(metadata.size, 132);
+
+        assert_eq!(
+            split_collection_path("roms/test_pack.zip:pack/IBM_Logo.ch8"),
+            ("roms/test_pack.zip", Some("pack/IBM_Logo.ch8"))
+        );
+        assert_eq!(split_collection_path("roms:3"), ("roms", Some("3")));
+        assert_eq!(split_collection_path("pong.ch8"), ("pong.ch8", None));
+    }
+
+    #[test]
+    fn detects_platform_from_extension() {
+        assert_eq!(
+            platform_from_extension("a/pong.CH8"),
+            Some(Platform::CosmacVip)
+        );
+        assert_eq!(
+            platform_from_extension("blinky.sc8"),
+            Some(Platform::SuperChip)
+        );
+        assert_eq!(platform_from_extension("t8nks.xo8"), Some(Platform::XoChip));
+        assert_eq!(platform_from_extension("game.bin"), None);
+
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        let metadata = load_rom_file(&mut cpu, "roms/IBM_Logo.ch8").unwrap();
+        assert_eq!(metadata.platform, Some(Platform::CosmacVip));
+        assert_eq!(metadata.size, 132);
+        assert!(matches!(
+            load_rom_file(&mut cpu, "roms/missing.ch8"),
+            Err(RomLoadError::Io(_))
+        ));
+
+        // A ROM that does not fit the detected platform leaves the CPU as it was
+        let mut cpu = CPU::for_platform(Platform::XoChip);
+        assert!(matches!(
+            RomLoader::new(0x1000).load_file(&mut cpu, "roms/IBM_Logo.ch8"),
+            Err(RomLoadError::BadLoadAddress(0x1000))
+        ));
+        assert_eq!(cpu.mem.mem.len(), Platform::XoChip.memory_size());
+        assert_eq!(cpu.quirks, Platform::XoChip.quirks());
+
+        // The same holds for the platform the database knows the ROM by
+        let rom = std::fs::read("roms/IBM_Logo.ch8").unwrap();
+        assert!(matches!(
+            RomLoader::new(0x1000).load_known_bytes(&mut cpu, &rom, &RomDatabase::builtin()),
+            Err(RomLoadError::BadLoadAddress(0x1000))
+        ));
+        assert_eq!(cpu.mem.mem.len(), Platform::XoChip.memory_size());
+        assert_eq!(cpu.quirks, Platform::XoChip.quirks());
+    }
+
+    #[test]
+    fn sha1_matches_known_digests() {
+        assert_eq!(
+            rom_sha1_hex(b""),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            rom_sha1_hex(b"abc"),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            rom_sha1_hex(&[b'a'; 1000]),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+    }
+}
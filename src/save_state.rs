@@ -0,0 +1,424 @@
+//! Save states for the complete machine.
+//!
+//! A save state is a versioned little-endian binary image of the CPU: platform, memory, registers,
+//! stack, timers and the emulated clock, DisplayBuffer, keyboard, quirks and RNG.
+//! The header carries the SHA-1 of the loaded ROM, and a state is only restored into a CPU running
+//! the same ROM.
+//!
+//! Layout (version 2):
+//!
+//! ```text
+//! magic "C8ST" | version u16 | rom SHA-1 20 x u8
+//! platform u8 | memory, the memory size of the platform
+//! stack 16 x u16 | V0-VF | I u16 | DT u8 | ST u8 | PC u16 | SP u8
+//! RPL flags 16 x u8 | audio pattern 16 x u8 | pitch u8
+//! instructions per frame u32 | cycle in frame u32 | frame u64 | waiting for display u8
+//! quirks 6 x u8 | rng state length u16 | rng state, see RandomSource::save
+//! display mode u8 | selected planes u8 | display buffer 64 x 128 x u8
+//! keyboard mask u16
+//! ```
+
+use crate::cpu::CPU;
+use crate::display::{DisplayBuffer, DisplayMode};
+use crate::quirks::{LoadStoreIncrement, Platform, Quirks};
+use crate::rng;
+use crate::rom_loader::to_hex;
+use crate::timers::FrameClock;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+/// Magic bytes at the start of every save state
+pub const MAGIC: [u8; 4] = *b"C8ST";
+/// Current version of the save state format
+pub const VERSION: u16 = 2;
+
+/// Error raised when a save state cannot be written or restored
+#[derive(Debug)]
+pub enum SaveStateError {
+    /// Reading or writing the save state file failed
+    Io(io::Error),
+    /// The data does not start with the save state magic bytes
+    BadMagic,
+    /// The save state was written by an unsupported version of the format
+    UnsupportedVersion(u16),
+    /// The save state was made with a different ROM than the one loaded
+    RomMismatch { expected: [u8; 20], found: [u8; 20] },
+    /// The data ended before the save state was complete
+    Truncated,
+    /// A field holds a value the interpreter cannot represent
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io(err) => write!(f, "{}", err),
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            SaveStateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state is for ROM {}, but ROM {} is loaded",
+                to_hex(found),
+                to_hex(expected)
+            ),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(err: io::Error) -> SaveStateError {
+        SaveStateError::Io(err)
+    }
+}
+
+impl CPU {
+    /// Serializes the complete machine state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.mem.mem.len() + 8300);
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_sha1);
+
+        out.push(match self.platform {
+            Platform::CosmacVip => 0,
+            Platform::Chip48 => 1,
+            Platform::SuperChip => 2,
+            Platform::XoChip => 3,
+        });
+        out.extend_from_slice(&self.mem.mem);
+        for level in self.stack.iter() {
+            out.extend_from_slice(&level.to_le_bytes());
+        }
+        out.extend_from_slice(&self.gp_regs);
+        out.extend_from_slice(&self.i_reg.to_le_bytes());
+        out.push(self.delay_reg);
+        out.push(self.sound_reg);
+        out.extend_from_slice(&self.pc_reg.to_le_bytes());
+        out.push(self.stack_pointer_reg);
+        out.extend_from_slice(&self.rpl_flags);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch_reg);
+
+        out.extend_from_slice(&self.clock.instructions_per_frame.to_le_bytes());
+        out.extend_from_slice(&self.clock.cycle.to_le_bytes());
+        out.extend_from_slice(&self.clock.frame.to_le_bytes());
+        out.push(self.waiting_for_display as u8);
+
+        let quirks = &self.quirks;
+        out.extend_from_slice(&[
+            quirks.shift_uses_vy as u8,
+            quirks.load_store_increments_i.flag(),
+            quirks.jump_uses_vx as u8,
+            quirks.logic_resets_vf as u8,
+            quirks.clip_sprites as u8,
+            quirks.display_wait as u8,
+        ]);
+        let rng_state = self.rng.save();
+        out.extend_from_slice(&(rng_state.len() as u16).to_le_bytes());
+        out.extend_from_slice(&rng_state);
+
+        out.push(match self.display_buffer.get_display_mode() {
+            DisplayMode::H64V32MONOCHROME => 0,
+            DisplayMode::H128V64MONOCHROME => 1,
+        });
+        out.push(self.display_buffer.get_selected_planes());
+        for row in self.display_buffer.get_buffer().iter() {
+            out.extend_from_slice(row);
+        }
+
+        out.extend_from_slice(&self.keyboard.key_mask().to_le_bytes());
+        out
+    }
+
+    /// Restores a machine state produced by save_state. The state must have been saved with the
+    /// same ROM loaded. The CPU is left unchanged if the state cannot be restored.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.bytes(4).map_err(|_| SaveStateError::BadMagic)? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let rom_sha1 = reader.bytes(20)?;
+        if rom_sha1 != self.rom_sha1 {
+            let mut found = [0; 20];
+            found.copy_from_slice(rom_sha1);
+            return Err(SaveStateError::RomMismatch {
+                expected: self.rom_sha1,
+                found,
+            });
+        }
+
+        let platform = match reader.u8()? {
+            0 => Platform::CosmacVip,
+            1 => Platform::Chip48,
+            2 => Platform::SuperChip,
+            3 => Platform::XoChip,
+            _ => return Err(SaveStateError::Invalid("platform")),
+        };
+        let mem = reader.bytes(platform.memory_size())?.to_vec();
+        let mut stack = [0; 16];
+        for level in stack.iter_mut() {
+            *level = reader.u16()?;
+        }
+        let gp_regs = reader.array16()?;
+        let i_reg = reader.u16()?;
+        let delay_reg = reader.u8()?;
+        let sound_reg = reader.u8()?;
+        let pc_reg = reader.u16()?;
+        let stack_pointer_reg = reader.u8()?;
+        if stack_pointer_reg as usize > stack.len() {
+            return Err(SaveStateError::Invalid("stack pointer"));
+        }
+        let rpl_flags = reader.array16()?;
+        let audio_pattern = reader.array16()?;
+        let pitch_reg = reader.u8()?;
+
+        let clock = FrameClock {
+            instructions_per_frame: reader.u32()?,
+            cycle: reader.u32()?,
+            frame: reader.u64()?,
+        };
+        let waiting_for_display = reader.bool()?;
+
+        let quirks = Quirks {
+            shift_uses_vy: reader.bool()?,
+            load_store_increments_i: LoadStoreIncrement::from_flag(reader.u8()?)
+                .ok_or(SaveStateError::Invalid("flag"))?,
+            jump_uses_vx: reader.bool()?,
+            logic_resets_vf: reader.bool()?,
+            clip_sprites: reader.bool()?,
+            display_wait: reader.bool()?,
+        };
+        let rng_len = reader.u16()? as usize;
+        let rng_state = reader.bytes(rng_len)?;
+
+        let display_mode = match reader.u8()? {
+            0 => DisplayMode::H64V32MONOCHROME,
+            1 => DisplayMode::H128V64MONOCHROME,
+            _ => return Err(SaveStateError::Invalid("display mode")),
+        };
+        let selected_planes = reader.u8()?;
+        let mut buff = [[0; 128]; 64];
+        for row in buff.iter_mut() {
+            row.copy_from_slice(reader.bytes(128)?);
+        }
+        let key_mask = reader.u16()?;
+        if reader.pos != data.len() {
+            return Err(SaveStateError::Invalid("length"));
+        }
+        // The current source gets the first chance to restore its own state, so custom sources
+        // survive a load
+        if !self.rng.restore(rng_state) {
+            self.rng =
+                rng::restore(rng_state).ok_or(SaveStateError::Invalid("random number source"))?;
+        }
+
+        self.platform = platform;
+        self.mem.mem = mem;
+        self.stack = stack;
+        self.gp_regs = gp_regs;
+        self.i_reg = i_reg;
+        self.delay_reg = delay_reg;
+        self.sound_reg = sound_reg;
+        self.pc_reg = pc_reg;
+        self.stack_pointer_reg = stack_pointer_reg;
+        self.rpl_flags = rpl_flags;
+        self.audio_pattern = audio_pattern;
+        self.pitch_reg = pitch_reg;
+        self.clock = clock;
+        self.waiting_for_display = waiting_for_display;
+        self.quirks = quirks;
+        self.display_buffer = DisplayBuffer::from_parts(display_mode, selected_planes, buff);
+        self.keyboard.set_key_mask(key_mask);
+        Ok(())
+    }
+
+    /// Writes the machine state to a file
+    pub fn save_state_file(&self, file_path: &str) -> Result<(), SaveStateError> {
+        fs::write(file_path, self.save_state())?;
+        Ok(())
+    }
+
+    /// Restores the machine state from a file written by save_state_file
+    pub fn load_state_file(&mut self, file_path: &str) -> Result<(), SaveStateError> {
+        let data = fs::read(file_path)?;
+        self.load_state(&data)
+    }
+}
+
+/// Cursor over save state bytes
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let slice = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(SaveStateError::Truncated)?;
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("flag")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        let mut word = [0; 2];
+        word.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(word))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        let mut word = [0; 4];
+        word.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut word = [0; 8];
+        word.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(word))
+    }
+
+    fn array16(&mut self) -> Result<[u8; 16], SaveStateError> {
+        let mut array = [0; 16];
+        array.copy_from_slice(self.bytes(16)?);
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::execute;
+    use crate::rng::RandomSource;
+    use crate::rom_loader::load_rom_bytes;
+
+    /// Runs a ROM that draws random sprites for a while
+    fn running_cpu() -> CPU {
+        let mut cpu = CPU::for_platform(Platform::XoChip);
+        // loop: RND V0, #FF; RND V1, #3F; LD I, #000; DRW V0, V1, 5; LD ST, V0; JP loop
+        let rom = [
+            0xC0, 0xFF, 0xC1, 0x3F, 0xA0, 0x00, 0xD0, 0x15, 0xF0, 0x18, 0x12, 0x00,
+        ];
+        load_rom_bytes(&mut cpu, &rom).unwrap();
+        cpu.pc_reg = 0x200;
+        cpu.keyboard.key_5 = true;
+        for _ in 0..50 {
+            execute(&mut cpu).unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn round_trip_continues_identically() {
+        let mut original = running_cpu();
+        let state = original.save_state();
+
+        let mut restored = CPU::new(DisplayMode::H64V32MONOCHROME);
+        restored.rom_sha1 = original.rom_sha1;
+        restored.load_state(&state).unwrap();
+        assert!(restored.keyboard.key_5);
+        assert_eq!(restored.platform, Platform::XoChip);
+        assert_eq!(restored.mem.mem.len(), Platform::XoChip.memory_size());
+        assert_eq!(restored.quirks, Quirks::XO_CHIP);
+
+        for _ in 0..60 {
+            execute(&mut original).unwrap();
+            execute(&mut restored).unwrap();
+        }
+        assert_eq!(original.gp_regs, restored.gp_regs);
+        assert_eq!(original.sound_reg, restored.sound_reg);
+        assert_eq!(
+            original.display_buffer.get_buffer()[..],
+            restored.display_buffer.get_buffer()[..]
+        );
+    }
+
+    /// Random source that counts up, with a tag byte not used by the built-in sources
+    struct Counter(u8);
+
+    impl RandomSource for Counter {
+        fn next_u8(&mut self) -> u8 {
+            self.0 = self.0.wrapping_add(1);
+            self.0
+        }
+
+        fn save(&self) -> Vec<u8> {
+            vec![0x80, self.0]
+        }
+
+        fn restore(&mut self, data: &[u8]) -> bool {
+            match data {
+                [0x80, count] => {
+                    self.0 = *count;
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+
+    #[test]
+    fn custom_random_sources_restore_themselves() {
+        let mut cpu = running_cpu();
+        cpu.rng = Box::new(Counter(7));
+        let state = cpu.save_state();
+        cpu.rng.next_u8();
+        cpu.rng.next_u8();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.rng.next_u8(), 8);
+
+        // A CPU without the custom source cannot restore it
+        let mut other = CPU::new(DisplayMode::H64V32MONOCHROME);
+        other.rom_sha1 = cpu.rom_sha1;
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::Invalid("random number source"))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_roms_and_bad_data() {
+        let state = running_cpu().save_state();
+        let mut other = CPU::new(DisplayMode::H64V32MONOCHROME);
+        load_rom_bytes(&mut other, &[0x12, 0x00]).unwrap();
+        assert!(matches!(
+            other.load_state(&state),
+            Err(SaveStateError::RomMismatch { .. })
+        ));
+
+        let mut cpu = running_cpu();
+        assert!(matches!(
+            cpu.load_state(&state[..100]),
+            Err(SaveStateError::Truncated)
+        ));
+        assert!(matches!(
+            cpu.load_state(b"nope"),
+            Err(SaveStateError::BadMagic)
+        ));
+    }
+}
@@ -0,0 +1,80 @@
+//! Emulated clock for the delay and sound timers.
+//!
+//! Time inside the interpreter is measured in executed instructions, not wall clock time. Every
+//! instructions_per_frame instructions make one 60hz frame, and at the end of each frame both
+//! timers are decremented together. Running the same program with the same input therefore
+//! always produces the same result, however fast the host is. Keeping up with real time is the
+//! job of the pacing layer outside the core (see pacing).
+
+/// Default instructions per frame, about 500 instructions per second
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 8;
+
+/// Counts executed instructions into 60hz frames
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameClock {
+    /// Instructions executed per 60hz frame. Values below 1 are treated as 1.
+    pub instructions_per_frame: u32,
+    /// Instructions executed so far in the current frame
+    pub cycle: u32,
+    /// Number of frames completed since the clock started
+    pub frame: u64,
+}
+
+impl FrameClock {
+    /// Constructs a new FrameClock at the start of frame 0
+    pub fn new(instructions_per_frame: u32) -> FrameClock {
+        FrameClock {
+            instructions_per_frame,
+            cycle: 0,
+            frame: 0,
+        }
+    }
+
+    /// Counts one instruction. Returns true if it completed the current frame.
+    pub fn step(&mut self) -> bool {
+        self.cycle += 1;
+        if self.cycle >= self.instructions_per_frame.max(1) {
+            self.cycle = 0;
+            self.frame += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for FrameClock {
+    fn default() -> FrameClock {
+        FrameClock::new(DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+    use crate::display::DisplayMode;
+    use crate::instructions::run_frame;
+
+    #[test]
+    fn timers_tick_together_once_per_frame() {
+        let mut cpu = CPU::new(DisplayMode::H64V32MONOCHROME);
+        // loop: JP loop
+        cpu.mem.mem[0x200] = 0x12;
+        cpu.pc_reg = 0x200;
+        cpu.clock = FrameClock::new(10);
+        cpu.delay_reg = 5;
+        cpu.sound_reg = 3;
+
+        run_frame(&mut cpu).unwrap();
+        assert_eq!((cpu.delay_reg, cpu.sound_reg), (4, 2));
+        for _ in 0..9 {
+            crate::instructions::execute(&mut cpu).unwrap();
+        }
+        assert_eq!((cpu.delay_reg, cpu.clock.cycle), (4, 9));
+        for _ in 0..5 {
+            run_frame(&mut cpu).unwrap();
+        }
+        assert_eq!((cpu.delay_reg, cpu.sound_reg, cpu.clock.frame), (0, 0, 6));
+    }
+}
@@ -0,0 +1,134 @@
+//! Reader for zip archives, enough to pull ROMs out of community ROM packs.
+//!
+//! Supports stored and deflated entries. Zip64, encryption and multi-disk archives are rejected.
+
+use crate::inflate::inflate;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4B50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4B50;
+const LOCAL_FILE_HEADER: u32 = 0x0403_4B50;
+
+/// A file in a zip archive
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZipEntry {
+    /// Path of the file inside the archive, with / separators
+    pub name: String,
+    /// Uncompressed size in bytes
+    pub size: usize,
+    compressed_size: usize,
+    method: u16,
+    crc32: u32,
+    flags: u16,
+    local_header_offset: usize,
+}
+
+impl ZipEntry {
+    /// Returns true for the entries that only record a directory
+    pub fn is_directory(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// Lists the entries of a zip archive from its central directory
+pub fn entries(archive: &[u8]) -> Result<Vec<ZipEntry>, String> {
+    // The end of central directory record is 22 bytes, followed by a comment of up to 64K
+    let search_start = archive.len().saturating_sub(22 + 0xFFFF);
+    let end = (search_start..archive.len().saturating_sub(21))
+        .rev()
+        .find(|&offset| u32_at(archive, offset) == Some(END_OF_CENTRAL_DIRECTORY))
+        .ok_or_else(|| String::from("not a zip archive"))?;
+
+    let count = u16_at(archive, end + 10).unwrap_or(0) as usize;
+    let directory_offset = u32_at(archive, end + 16).unwrap_or(0);
+    if u16_at(archive, end + 4) != Some(0) || count == 0xFFFF || directory_offset == 0xFFFF_FFFF {
+        return Err(String::from(
+            "multi-disk and zip64 archives are not supported",
+        ));
+    }
+
+    let mut offset = directory_offset as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let field = |at: usize| u16_at(archive, offset + at).map(|value| value as usize);
+        if u32_at(archive, offset) != Some(CENTRAL_DIRECTORY_HEADER) {
+            return Err(String::from("central directory is corrupt"));
+        }
+        let corrupt = || String::from("central directory is truncated");
+        let name_len = field(28).ok_or_else(corrupt)?;
+        let extra_len = field(30).ok_or_else(corrupt)?;
+        let comment_len = field(32).ok_or_else(corrupt)?;
+        let name = archive
+            .get(offset + 46..offset + 46 + name_len)
+            .ok_or_else(corrupt)?;
+
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).replace('\\', "/"),
+            size: u32_at(archive, offset + 24).ok_or_else(corrupt)? as usize,
+            compressed_size: u32_at(archive, offset + 20).ok_or_else(corrupt)? as usize,
+            method: field(10).ok_or_else(corrupt)? as u16,
+            crc32: u32_at(archive, offset + 16).ok_or_else(corrupt)?,
+            flags: field(8).ok_or_else(corrupt)? as u16,
+            local_header_offset: u32_at(archive, offset + 42).ok_or_else(corrupt)? as usize,
+        });
+        offset += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Extracts the contents of an entry, checking its CRC-32. Entries larger than max_size bytes are
+/// not extracted.
+pub fn extract(archive: &[u8], entry: &ZipEntry, max_size: usize) -> Result<Vec<u8>, String> {
+    let header = entry.local_header_offset;
+    if u32_at(archive, header) != Some(LOCAL_FILE_HEADER) {
+        return Err(format!("{}: local header is corrupt", entry.name));
+    }
+    if entry.flags & 1 != 0 {
+        return Err(format!(
+            "{}: encrypted entries are not supported",
+            entry.name
+        ));
+    }
+    if entry.size > max_size {
+        return Err(format!("{}: is larger than {} bytes", entry.name, max_size));
+    }
+    let name_len = u16_at(archive, header + 26).unwrap_or(0) as usize;
+    let extra_len = u16_at(archive, header + 28).unwrap_or(0) as usize;
+    let start = header + 30 + name_len + extra_len;
+    let data = archive
+        .get(start..start + entry.compressed_size)
+        .ok_or_else(|| format!("{}: data is truncated", entry.name))?;
+
+    let contents = match entry.method {
+        0 => data.to_vec(),
+        8 => inflate(data, max_size).map_err(|err| format!("{}: {}", entry.name, err))?,
+        method => {
+            return Err(format!(
+                "{}: compression method {} is not supported",
+                entry.name, method
+            ))
+        }
+    };
+    if contents.len() != entry.size || crc32(&contents) != entry.crc32 {
+        return Err(format!("{}: contents fail the CRC check", entry.name));
+    }
+    Ok(contents)
+}
+
+/// Returns the CRC-32 (ISO-HDLC, as used by zip) of the data
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}